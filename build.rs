@@ -0,0 +1,6 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    #[cfg(feature = "server")]
+    tonic_build::configure()
+        .compile(&["proto/try_api.proto"], &["proto"])?;
+    Ok(())
+}
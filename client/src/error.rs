@@ -0,0 +1,44 @@
+use std::time::Duration;
+use thiserror::Error;
+use try_api::dto::FieldErrorDto;
+
+/// Failures of an API call, by the status the service answered with.
+#[derive(Error, Debug)]
+pub enum Error {
+    /// 404: the account, or its key for the exchange, doesn't exist.
+    #[error("{0}")]
+    NotFound(String),
+    /// 409: the account already exists, or was deleted and awaits restore or purge.
+    #[error("{0}")]
+    Conflict(String),
+    /// 412: the account changed since the version the call expected.
+    #[error("{0}")]
+    VersionMismatch(String),
+    /// 422: the request broke the field rules of the API.
+    #[error("{message}: {}", .errors.iter().map(|error| format!("{} {}", error.field, error.message)).collect::<Vec<_>>().join("; "))]
+    Validation { message: String, errors: Vec<FieldErrorDto> },
+    /// 429 after retries ran out.
+    #[error("{message}")]
+    RateLimited { message: String, retry_after: Option<Duration> },
+    /// `send_signed` was asked for a method HTTP doesn't have; nothing was signed or sent.
+    #[error("\"{0}\" is not an HTTP method")]
+    UnsupportedMethod(String),
+    /// Any other error status.
+    #[error("{status}: {message}")]
+    Api { status: u16, message: String },
+    /// The service couldn't be reached or answered with something unreadable.
+    #[error(transparent)]
+    Http(#[from] reqwest::Error),
+}
+
+impl Error {
+    /// Whether repeating the call could succeed; calls are retried on these errors.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::RateLimited { .. } => true,
+            Error::Api { status, .. } => (502..=504).contains(status),
+            Error::Http(err) => err.is_connect() || err.is_timeout(),
+            _ => false,
+        }
+    }
+}
@@ -0,0 +1,298 @@
+//! Typed async client for the `/v1` routes of the try_api service, sharing its DTOs and models.
+
+mod error;
+#[cfg(feature = "signing")]
+mod signing;
+
+pub use error::Error;
+pub use try_api::{dto, models};
+pub use try_api::secret::Secret;
+
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use reqwest::header::{CONTENT_TYPE, ETAG, IF_MATCH, RETRY_AFTER};
+use reqwest::{Method, RequestBuilder, StatusCode};
+use serde::de::DeserializeOwned;
+use std::time::Duration;
+use try_api::dto::{
+    AccountChangesDto, AccountDetailsDto, AccountPageDto, AccountRefDto, ApiKeyDto, CreateAccountDto, ErrorDto,
+    KeyPairRequestDto, ListAccountsQuery, PublicKeyDto, SignRequestDto, SignatureDto, SignedRequestDto,
+    ValidationErrorDto,
+};
+use try_api::models::{ExchangeName, KeyType};
+use uuid::Uuid;
+
+const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+const REQUEST_TIMEOUT_SECS: u64 = 30;
+
+/// A response with the account version from its `ETag`, to pass as `expected_version` of the next change.
+#[derive(Debug)]
+pub struct Versioned<T> {
+    pub value: T,
+    pub version: Option<i64>,
+}
+
+/// How failed calls are repeated: up to `max_retries` times, waiting `backoff` doubled for each
+/// further retry, or as long as the `Retry-After` of a rate limited answer asks.
+#[derive(Copy, Clone, Debug)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub backoff: Duration,
+}
+
+impl RetryPolicy {
+    pub fn none() -> RetryPolicy {
+        RetryPolicy { max_retries: 0, backoff: Duration::from_secs(0) }
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy { max_retries: 3, backoff: Duration::from_millis(200) }
+    }
+}
+
+#[derive(Clone)]
+pub struct Client {
+    http: reqwest::Client,
+    base_url: String,
+    token: Option<Secret>,
+    retry: RetryPolicy,
+}
+
+impl Client {
+    /// A client of the service at `base_url`, e.g. `http://127.0.0.1:3030`.
+    pub fn new(base_url: &str) -> Result<Client, Error> {
+        let http = reqwest::Client::builder()
+            .timeout(Duration::from_secs(REQUEST_TIMEOUT_SECS))
+            .build()?;
+        Ok(Client::with_http(http, base_url))
+    }
+
+    /// Uses `http` as configured, e.g. with other timeouts or TLS roots.
+    pub fn with_http(http: reqwest::Client, base_url: &str) -> Client {
+        Client {
+            http,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            token: None,
+            retry: RetryPolicy::default(),
+        }
+    }
+
+    /// Sends `token` as `Authorization: Bearer`, which identifies the caller to the rate limits.
+    pub fn with_token(mut self, token: Secret) -> Client {
+        self.token = Some(token);
+        self
+    }
+
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Client {
+        self.retry = retry;
+        self
+    }
+
+    fn url(&self, segments: &[&str]) -> String {
+        let mut url = format!("{}/v1", self.base_url);
+        for segment in segments {
+            url.push('/');
+            url.push_str(&utf8_percent_encode(segment, NON_ALPHANUMERIC).to_string());
+        }
+        url
+    }
+
+    /// Sends the request `build` makes, retrying as the policy allows. Mutating calls carry one
+    /// `Idempotency-Key` across attempts, so a retried call takes effect once.
+    async fn send(
+        &self,
+        method: Method,
+        url: String,
+        build: impl Fn(RequestBuilder) -> RequestBuilder,
+    ) -> Result<reqwest::Response, Error> {
+        let idempotency_key = match method {
+            Method::GET => None,
+            _ => Some(Uuid::new_v4().to_string()),
+        };
+        let mut retries = 0;
+        loop {
+            let mut request = build(self.http.request(method.clone(), &url));
+            if let Some(token) = &self.token {
+                request = request.bearer_auth(token.expose());
+            }
+            if let Some(key) = &idempotency_key {
+                request = request.header(IDEMPOTENCY_KEY_HEADER, key.as_str());
+            }
+            let err = match request.send().await {
+                Ok(response) if response.status().is_success() => return Ok(response),
+                Ok(response) => error_of(response).await,
+                Err(err) => Error::from(err),
+            };
+            if retries >= self.retry.max_retries || !err.is_retryable() {
+                return Err(err);
+            }
+            let delay = match &err {
+                Error::RateLimited { retry_after: Some(retry_after), .. } => *retry_after,
+                _ => self.retry.backoff * 2u32.saturating_pow(retries),
+            };
+            retries += 1;
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    pub async fn list_accounts(&self, query: &ListAccountsQuery) -> Result<AccountPageDto, Error> {
+        let response = self.send(Method::GET, self.url(&["accounts"]), |request| request.query(query)).await?;
+        json(response).await
+    }
+
+    pub async fn create_account(&self, account: &CreateAccountDto) -> Result<AccountRefDto, Error> {
+        let response = self.send(Method::POST, self.url(&["accounts"]), |request| request.json(account)).await?;
+        json(response).await
+    }
+
+    /// Metadata of the account; the exchange of accounts with several is unspecified.
+    pub async fn get_account(&self, uid: &str) -> Result<AccountDetailsDto, Error> {
+        let response = self.send(Method::GET, self.url(&["accounts", uid]), |request| request).await?;
+        json(response).await
+    }
+
+    pub async fn get_exchange_account(&self, uid: &str, exchange: &ExchangeName) -> Result<AccountDetailsDto, Error> {
+        let url = self.url(&["accounts", uid, &exchange.to_string()]);
+        let response = self.send(Method::GET, url, |request| request).await?;
+        json(response).await
+    }
+
+    /// Deletes the account; it can be restored until the service's retention period ends.
+    pub async fn remove_account(&self, uid: &str, expected_version: Option<i64>) -> Result<(), Error> {
+        let url = self.url(&["accounts", uid]);
+        self.send(Method::DELETE, url, |request| if_match(request, expected_version)).await?;
+        Ok(())
+    }
+
+    pub async fn restore_account(&self, uid: &str) -> Result<Versioned<AccountRefDto>, Error> {
+        let response = self.send(Method::POST, self.url(&["accounts", uid, "restore"]), |request| request).await?;
+        versioned(response).await
+    }
+
+    pub async fn update_account(
+        &self,
+        uid: &str,
+        exchange: &ExchangeName,
+        changes: &AccountChangesDto,
+        expected_version: Option<i64>,
+    ) -> Result<Versioned<AccountRefDto>, Error> {
+        let url = self.url(&["accounts", uid, &exchange.to_string()]);
+        let response = self
+            .send(Method::PATCH, url, |request| if_match(request.json(changes), expected_version))
+            .await?;
+        versioned(response).await
+    }
+
+    /// Returns the account's api key with a fresh nonce; nothing is signed. `data_to_sign` is only
+    /// recorded by servers with payload retention on. Use `sign_request` for signatures.
+    pub async fn create_signature(
+        &self,
+        uid: &str,
+        exchange: &ExchangeName,
+        data_to_sign: &[u8],
+    ) -> Result<SignatureDto, Error> {
+        let url = self.url(&["accounts", uid, &exchange.to_string(), "signatures"]);
+        let response = self
+            .send(Method::POST, url, |request| {
+                request.header(CONTENT_TYPE, "application/octet-stream").body(data_to_sign.to_vec())
+            })
+            .await?;
+        json(response).await
+    }
+
+    /// Adds the exchange's authentication to `request` with the account's credentials.
+    pub async fn sign_request(
+        &self,
+        uid: &str,
+        exchange: &ExchangeName,
+        request: &SignRequestDto,
+    ) -> Result<SignedRequestDto, Error> {
+        let url = self.url(&["accounts", uid, &exchange.to_string(), "requests"]);
+        let response = self.send(Method::POST, url, |builder| builder.json(request)).await?;
+        json(response).await
+    }
+
+    /// Generates a key pair kept by the service and returns the public key to register at the exchange.
+    pub async fn generate_key_pair(
+        &self,
+        uid: &str,
+        exchange: &ExchangeName,
+        key_type: KeyType,
+        expected_version: Option<i64>,
+    ) -> Result<Versioned<PublicKeyDto>, Error> {
+        let url = self.url(&["accounts", uid, &exchange.to_string(), "keypair"]);
+        let body = KeyPairRequestDto { key_type };
+        let response = self
+            .send(Method::POST, url, |request| if_match(request.json(&body), expected_version))
+            .await?;
+        versioned(response).await
+    }
+
+    pub async fn get_api_key(&self, uid: &str, exchange: &ExchangeName) -> Result<Secret, Error> {
+        let url = self.url(&["accounts", uid, &exchange.to_string(), "api-key"]);
+        let response = self.send(Method::GET, url, |request| request).await?;
+        let dto: ApiKeyDto = json(response).await?;
+        Ok(dto.api_key)
+    }
+
+    pub async fn remove_api_key(
+        &self,
+        uid: &str,
+        exchange: &ExchangeName,
+        expected_version: Option<i64>,
+    ) -> Result<(), Error> {
+        let url = self.url(&["accounts", uid, &exchange.to_string(), "api-key"]);
+        self.send(Method::DELETE, url, |request| if_match(request, expected_version)).await?;
+        Ok(())
+    }
+}
+
+fn if_match(request: RequestBuilder, expected_version: Option<i64>) -> RequestBuilder {
+    match expected_version {
+        Some(version) => request.header(IF_MATCH, format!("\"{}\"", version)),
+        None => request,
+    }
+}
+
+async fn json<T: DeserializeOwned>(response: reqwest::Response) -> Result<T, Error> {
+    Ok(response.json().await?)
+}
+
+async fn versioned<T: DeserializeOwned>(response: reqwest::Response) -> Result<Versioned<T>, Error> {
+    let version = response.headers()
+        .get(ETAG)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim_start_matches("W/").trim_matches('"').parse().ok());
+    Ok(Versioned { value: response.json().await?, version })
+}
+
+/// Reads the `ErrorDto` or `ValidationErrorDto` body of an error response.
+async fn error_of(response: reqwest::Response) -> Error {
+    let status = response.status();
+    let retry_after = response.headers()
+        .get(RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok())
+        .map(Duration::from_secs);
+    let body = match response.bytes().await {
+        Ok(body) => body,
+        Err(err) => return Error::Http(err),
+    };
+    if status == StatusCode::UNPROCESSABLE_ENTITY {
+        if let Ok(dto) = serde_json::from_slice::<ValidationErrorDto>(&body) {
+            return Error::Validation { message: dto.error, errors: dto.errors };
+        }
+    }
+    let message = match serde_json::from_slice::<ErrorDto>(&body) {
+        Ok(dto) => dto.error,
+        Err(_) => String::from_utf8_lossy(&body).into_owned(),
+    };
+    match status {
+        StatusCode::NOT_FOUND => Error::NotFound(message),
+        StatusCode::CONFLICT => Error::Conflict(message),
+        StatusCode::PRECONDITION_FAILED => Error::VersionMismatch(message),
+        StatusCode::TOO_MANY_REQUESTS => Error::RateLimited { message, retry_after },
+        _ => Error::Api { status: status.as_u16(), message },
+    }
+}
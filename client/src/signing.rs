@@ -0,0 +1,56 @@
+use reqwest::Method;
+use try_api::dto::SignRequestDto;
+use try_api::models::ExchangeName;
+use crate::{Client, Error};
+
+impl Client {
+    /// Signs `request` with the account's credentials through the service, sends it to the exchange
+    /// at `exchange_url` (e.g. `https://api.binance.com`) and returns the exchange's answer.
+    /// Only the signing call is retried: a repeated exchange call could act twice.
+    pub async fn send_signed(
+        &self,
+        uid: &str,
+        exchange: &ExchangeName,
+        exchange_url: &str,
+        request: &SignRequestDto,
+    ) -> Result<reqwest::Response, Error> {
+        let method = Method::from_bytes(request.method.to_ascii_uppercase().as_bytes())
+            .map_err(|_| Error::UnsupportedMethod(request.method.clone()))?;
+        let signed = self.sign_request(uid, exchange, request).await?;
+        let mut url = format!("{}{}", exchange_url.trim_end_matches('/'), request.path);
+        if !signed.query_string.is_empty() {
+            url.push('?');
+            url.push_str(&signed.query_string);
+        }
+        let mut builder = self.http.request(method, &url);
+        for header in &signed.headers {
+            builder = builder.header(header.name.as_str(), header.value.as_str());
+        }
+        if let Some(body) = signed.body {
+            builder = builder.body(body);
+        }
+        Ok(builder.send().await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn rejects_unsupported_methods_before_signing() {
+        // Nothing listens here: the call must fail before reaching the service.
+        let client = Client::new("http://127.0.0.1:9").unwrap();
+        let request = SignRequestDto {
+            method: "GE T".to_string(),
+            path: "/api/v3/account".to_string(),
+            query: Vec::new(),
+            body: None,
+            host: None,
+        };
+        let err = client.send_signed("alice", &ExchangeName::Binance, "https://api.binance.com", &request)
+            .await
+            .unwrap_err();
+        assert!(matches!(&err, Error::UnsupportedMethod(method) if method == "GE T"), "{:?}", err);
+    }
+}
@@ -0,0 +1,186 @@
+//! Runs the client against a stub of the service answering with scripted statuses.
+
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use serde_json::json;
+use try_api_client::dto::CreateAccountDto;
+use try_api_client::models::ExchangeName;
+use try_api_client::{Client, Error, RetryPolicy, Secret};
+use warp::http::{HeaderMap, Method};
+use warp::Filter;
+
+/// Status, an extra header and body of a scripted answer.
+type Reply = (u16, Option<(&'static str, &'static str)>, String);
+
+/// A request the stub received.
+struct Received {
+    method: Method,
+    idempotency_key: Option<String>,
+}
+
+/// Answers each request with the next of its replies.
+struct Stub {
+    addr: SocketAddr,
+    received: Arc<Mutex<Vec<Received>>>,
+}
+
+impl Stub {
+    fn start(replies: Vec<Reply>) -> Stub {
+        let replies = Arc::new(Mutex::new(VecDeque::from(replies)));
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let log = received.clone();
+        let route = warp::method()
+            .and(warp::header::headers_cloned())
+            .map(move |method: Method, headers: HeaderMap| {
+                let idempotency_key = headers
+                    .get("idempotency-key")
+                    .and_then(|value| value.to_str().ok())
+                    .map(str::to_string);
+                log.lock().unwrap().push(Received { method, idempotency_key });
+                let (status, header, body) = replies.lock().unwrap().pop_front().expect("an unscripted request");
+                let mut response = warp::http::Response::builder()
+                    .status(status)
+                    .header("content-type", "application/json");
+                if let Some((name, value)) = header {
+                    response = response.header(name, value);
+                }
+                response.body(body).unwrap()
+            });
+        let (addr, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        Stub { addr, received }
+    }
+
+    fn client(&self, retry: RetryPolicy) -> Client {
+        Client::new(&format!("http://{}", self.addr)).unwrap().with_retry(retry)
+    }
+
+    fn idempotency_keys(&self) -> Vec<Option<String>> {
+        self.received.lock().unwrap().iter().map(|received| received.idempotency_key.clone()).collect()
+    }
+}
+
+fn error(status: u16, message: &str) -> Reply {
+    (status, None, json!({ "error": message }).to_string())
+}
+
+fn created() -> Reply {
+    (201, None, json!({ "uid": "alice", "exchange": "kraken" }).to_string())
+}
+
+fn account() -> CreateAccountDto {
+    CreateAccountDto {
+        uid: "alice".to_string(),
+        exchange: ExchangeName::Kraken,
+        api_key: Secret::from("api-key".to_string()),
+        sign_key: None,
+        credentials: None,
+    }
+}
+
+fn retry(max_retries: u32, backoff_millis: u64) -> RetryPolicy {
+    RetryPolicy { max_retries, backoff: Duration::from_millis(backoff_millis) }
+}
+
+#[tokio::test]
+async fn retries_gateway_errors_with_backoff_under_one_idempotency_key() {
+    let stub = Stub::start(vec![
+        error(502, "bad gateway"),
+        error(503, "unavailable"),
+        error(504, "gateway timeout"),
+        created(),
+    ]);
+    let started = Instant::now();
+
+    let created = stub.client(retry(3, 50)).create_account(&account()).await.unwrap();
+    assert_eq!(created.uid, "alice");
+    // Waits 50, 100 and 200 ms between the attempts.
+    assert!(started.elapsed() >= Duration::from_millis(350), "{:?}", started.elapsed());
+
+    let keys = stub.idempotency_keys();
+    assert_eq!(keys.len(), 4);
+    assert!(keys[0].is_some());
+    assert!(keys.iter().all(|key| *key == keys[0]), "{:?}", keys);
+}
+
+#[tokio::test]
+async fn gives_up_when_retries_run_out() {
+    let stub = Stub::start(vec![error(503, "unavailable"); 3]);
+
+    let err = stub.client(retry(2, 1)).create_account(&account()).await.unwrap_err();
+    assert!(matches!(&err, Error::Api { status: 503, .. }), "{:?}", err);
+    assert_eq!(stub.idempotency_keys().len(), 3);
+}
+
+#[tokio::test]
+async fn waits_as_long_as_rate_limits_ask() {
+    let limited = (429, Some(("retry-after", "1")), json!({ "error": "slow down" }).to_string());
+    let stub = Stub::start(vec![limited, created()]);
+    let started = Instant::now();
+
+    stub.client(retry(1, 1)).create_account(&account()).await.unwrap();
+    assert!(started.elapsed() >= Duration::from_secs(1), "{:?}", started.elapsed());
+}
+
+#[tokio::test]
+async fn does_not_retry_other_errors() {
+    for status in [400, 401, 404, 409, 412, 422, 500, 501, 505] {
+        let stub = Stub::start(vec![error(status, "failed"), created()]);
+
+        assert!(stub.client(retry(3, 1)).create_account(&account()).await.is_err(), "{}", status);
+        assert_eq!(stub.idempotency_keys().len(), 1, "{} was retried", status);
+    }
+}
+
+#[tokio::test]
+async fn sends_no_idempotency_key_with_reads() {
+    let stub = Stub::start(vec![(200, None, json!({ "api_key": "k" }).to_string())]);
+
+    let api_key = stub.client(RetryPolicy::none()).get_api_key("alice", &ExchangeName::Kraken).await.unwrap();
+    assert_eq!(api_key.expose(), "k");
+    let received = stub.received.lock().unwrap();
+    assert_eq!(received[0].method, Method::GET);
+    assert_eq!(received[0].idempotency_key, None);
+}
+
+#[tokio::test]
+async fn maps_each_status_to_its_error() {
+    let validation = json!({
+        "error": "Request validation failed",
+        "errors": [{ "field": "uid", "message": "must not be empty" }],
+    });
+    let stub = Stub::start(vec![
+        error(404, "Account not found"),
+        error(409, "Account exists"),
+        error(412, "Account changed"),
+        (422, None, validation.to_string()),
+        (429, Some(("retry-after", "7")), json!({ "error": "Rate limit per caller exceeded" }).to_string()),
+        error(500, "Internal server error"),
+        (502, None, "upstream down".to_string()),
+    ]);
+    let client = stub.client(RetryPolicy::none());
+    let mut errors = Vec::new();
+    for _ in 0..7 {
+        errors.push(client.create_account(&account()).await.unwrap_err());
+    }
+
+    assert!(matches!(&errors[0], Error::NotFound(message) if message == "Account not found"), "{:?}", errors[0]);
+    assert!(matches!(&errors[1], Error::Conflict(message) if message == "Account exists"), "{:?}", errors[1]);
+    assert!(matches!(&errors[2], Error::VersionMismatch(message) if message == "Account changed"), "{:?}", errors[2]);
+    match &errors[3] {
+        Error::Validation { message, errors } => {
+            assert_eq!(message, "Request validation failed");
+            assert_eq!((errors[0].field.as_str(), errors[0].message.as_str()), ("uid", "must not be empty"));
+        }
+        err => panic!("expected a validation error, got {:?}", err),
+    }
+    match &errors[4] {
+        Error::RateLimited { retry_after, .. } => assert_eq!(*retry_after, Some(Duration::from_secs(7))),
+        err => panic!("expected a rate limit error, got {:?}", err),
+    }
+    assert!(matches!(&errors[5], Error::Api { status: 500, .. }), "{:?}", errors[5]);
+    // Bodies that aren't an ErrorDto are passed on as they are.
+    assert!(matches!(&errors[6], Error::Api { status: 502, message } if message == "upstream down"), "{:?}", errors[6]);
+}
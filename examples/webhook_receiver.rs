@@ -0,0 +1,49 @@
+//! Local receiver for account event webhooks, printing each delivery after checking its signature.
+//!
+//!     WEBHOOK_SECRET=dev cargo run --example webhook_receiver
+//!
+//! and start the service with `WEBHOOK_URLS=http://127.0.0.1:4040/` and the same `WEBHOOK_SECRET`.
+//! Set `FAIL_EVERY` to answer every n-th delivery with a 500 to watch the retries.
+use std::env;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use chrono::Utc;
+use try_api::events::verify_signature;
+use warp::http::StatusCode;
+use warp::hyper::body::Bytes;
+use warp::Filter;
+
+/// Deliveries with timestamps further off than this are refused, so captured requests can't be replayed.
+const MAX_SKEW_SECS: i64 = 5 * 60;
+
+#[tokio::main]
+async fn main() {
+    let secret = Arc::new(env::var("WEBHOOK_SECRET").expect("WEBHOOK_SECRET must be set"));
+    let fail_every: u64 = env::var("FAIL_EVERY").ok().and_then(|value| value.parse().ok()).unwrap_or(0);
+    let received = Arc::new(AtomicU64::new(0));
+
+    let receiver = warp::post()
+        .and(warp::header::<String>("x-try-api-event-id"))
+        .and(warp::header::<i64>("x-try-api-timestamp"))
+        .and(warp::header::<String>("x-try-api-signature"))
+        .and(warp::body::bytes())
+        .map(move |id: String, timestamp: i64, signature: String, body: Bytes| {
+            if (Utc::now().timestamp() - timestamp).abs() > MAX_SKEW_SECS {
+                println!("event {}: timestamp too far off", id);
+                return StatusCode::BAD_REQUEST;
+            }
+            if !verify_signature(secret.as_bytes(), timestamp, &body, &signature) {
+                println!("event {}: invalid signature", id);
+                return StatusCode::UNAUTHORIZED;
+            }
+            let count = received.fetch_add(1, Ordering::SeqCst) + 1;
+            if fail_every > 0 && count.is_multiple_of(fail_every) {
+                println!("event {}: failing on purpose", id);
+                return StatusCode::INTERNAL_SERVER_ERROR;
+            }
+            println!("event {}: {}", id, String::from_utf8_lossy(&body));
+            StatusCode::OK
+        });
+
+    warp::serve(receiver).run(([127, 0, 0, 1], 4040)).await;
+}
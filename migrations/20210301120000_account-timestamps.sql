@@ -0,0 +1,10 @@
+-- Add migration script here
+alter table accounts
+    add column created_at TIMESTAMPTZ not null default now(),
+    add column updated_at TIMESTAMPTZ not null default now();
+
+create index accounts_created_at_index
+	on accounts (created_at, uid);
+
+create index accounts_updated_at_index
+	on accounts (updated_at, uid);
@@ -0,0 +1,19 @@
+-- Add migration script here
+create table idempotency_keys
+(
+    caller VARCHAR(255) not null,
+    key VARCHAR(255) not null,
+    fingerprint TEXT not null,
+    status_code INTEGER,
+    content_type TEXT,
+    etag TEXT,
+    body BYTEA,
+    created_at TIMESTAMPTZ not null default now()
+);
+
+alter table idempotency_keys
+    add constraint idempotency_keys_pk
+        primary key (caller, key);
+
+create index idempotency_keys_created_at_index
+	on idempotency_keys (created_at);
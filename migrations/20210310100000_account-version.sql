@@ -0,0 +1,3 @@
+-- Add migration script here
+alter table accounts
+    add column version BIGINT not null default 1;
@@ -0,0 +1,6 @@
+-- Add migration script here
+alter table accounts
+    drop column data_to_sign;
+
+alter table accounts
+    add column signed_payload BYTEA;
@@ -0,0 +1,11 @@
+-- Add migration script here
+create table account_nonces
+(
+    uid VARCHAR(255) not null,
+    exchange TEXT not null,
+    value BIGINT not null
+);
+
+alter table account_nonces
+    add constraint account_nonces_pk
+        primary key (uid, exchange);
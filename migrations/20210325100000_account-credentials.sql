@@ -0,0 +1,3 @@
+-- Add migration script here
+alter table accounts
+    add column credentials BYTEA;
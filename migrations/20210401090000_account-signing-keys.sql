@@ -0,0 +1,4 @@
+-- Add migration script here
+alter table accounts
+    add column signing_key BYTEA,
+    add column key_type    TEXT;
@@ -0,0 +1,7 @@
+-- Add migration script here
+alter table accounts
+    add column deleted_at TIMESTAMPTZ;
+
+create index accounts_deleted_at_index
+	on accounts (deleted_at)
+	where deleted_at is not null;
@@ -0,0 +1,24 @@
+-- Add migration script here
+create table account_events
+(
+    id BIGSERIAL not null,
+    kind TEXT not null,
+    uid VARCHAR(255) not null,
+    exchange TEXT,
+    data TEXT not null,
+    created_at TIMESTAMPTZ not null default now(),
+    attempts INTEGER not null default 0,
+    next_attempt_at TIMESTAMPTZ not null default now(),
+    pending_urls TEXT[],
+    delivered_at TIMESTAMPTZ,
+    failed_at TIMESTAMPTZ,
+    last_error TEXT
+);
+
+alter table account_events
+    add constraint account_events_pk
+        primary key (id);
+
+create index account_events_due_index
+	on account_events (next_attempt_at)
+	where delivered_at is null and failed_at is null;
@@ -1,27 +1,75 @@
-use crate::models::{AccountId, ExchangeName};
+use crate::models::{AccountId, ExchangeName, AccountPatch, ExchangeCredentials, KeyType};
+use crate::crypto::CredentialCipher;
+use crate::key_provider;
+use crate::secret::Secret;
 use sqlx::{Pool, Postgres};
-use crate::db::AccountOrm;
+use crate::config::{Config, NonceBackend};
+use crate::db::{AccountOrm, AccountSummary, AccountEntity, AccountSecrets};
+use crate::exchange_auth::{self, ExchangeRequest, SignedRequest};
+use crate::keys::{self, SigningKey};
+use crate::stream::EventHub;
+use crate::nonce::{NonceStore, NonceStyle, PgNonceStore, MemoryNonceStore};
+use chrono::{Duration, Utc};
+use crate::dto::ListAccountsQuery;
+use std::collections::HashMap;
+use std::sync::Arc;
 
 
 #[derive(Clone)]
 pub struct AccountRepo {
     pub account_orm: AccountOrm,
+    nonce_store: Arc<dyn NonceStore>,
+    nonce_styles: HashMap<ExchangeName, NonceStyle>,
+    deleted_retention: Duration,
+    /// Without webhooks nothing delivers outbox events, so they are pruned with deleted accounts.
+    prune_events: bool,
 }
 
 impl AccountRepo {
-    pub async fn new(pg_pool: Pool<Postgres>) -> AccountRepo {
-        let account_orm = AccountOrm::new(pg_pool).await;
-        AccountRepo { account_orm }
+    /// Account changes are streamed through `hub` when given.
+    pub async fn new(pg_pool: Pool<Postgres>, config: &Config, hub: Option<Arc<EventHub>>) -> AccountRepo {
+        let nonce_store: Arc<dyn NonceStore> = match config.nonce_backend {
+            NonceBackend::Postgres => Arc::new(PgNonceStore::new(pg_pool.clone())),
+            NonceBackend::Memory => Arc::new(MemoryNonceStore::default()),
+        };
+        let cipher = match key_provider::from_config(&config.key_provider) {
+            Ok(provider) => CredentialCipher::new(provider),
+            Err(err) => panic!("{}", err)
+        };
+        let account_orm = AccountOrm::new(
+            pg_pool,
+            config.retain_sign_payloads,
+            cipher,
+            hub,
+        ).await;
+        AccountRepo {
+            account_orm,
+            nonce_store,
+            nonce_styles: config.nonce_styles.clone(),
+            deleted_retention: Duration::seconds(config.deleted_retention_secs),
+            prune_events: config.webhook.is_none(),
+        }
+    }
+
+    pub async fn next_nonce(&self, uid: &AccountId, exchange: &ExchangeName) -> Result<u64, anyhow::Error> {
+        let style = self.nonce_styles.get(exchange)
+            .copied()
+            .unwrap_or_else(|| NonceStyle::default_for(exchange));
+        self.nonce_store.next(uid, exchange, style).await
     }
 
+    /// Returns the account uid, its api key and a fresh nonce.
     pub async fn sign_and_get_key(
         &self,
         uid: &AccountId,
         exchange: &ExchangeName,
         data_to_sign: &[u8],
-    ) -> Result<(String, String), anyhow::Error> {
+    ) -> Result<(String, Secret, u64), anyhow::Error> {
         match self.account_orm.sign_and_get_key(uid, exchange, data_to_sign).await {
-            Ok(result) => Ok(result),
+            Ok((uid, api_key)) => {
+                let nonce = self.next_nonce(&AccountId(uid.clone()), exchange).await?;
+                Ok((uid, api_key, nonce))
+            }
             Err(err) => Err(err)
         }
     }
@@ -30,20 +78,26 @@ impl AccountRepo {
         &self,
         uid: &AccountId,
         exchange: &ExchangeName,
-        api_key: &str,
-        sign_key: Option<String>,
+        api_key: &Secret,
+        sign_key: Option<&Secret>,
+        credentials: Option<&ExchangeCredentials>,
     ) -> Result<(), anyhow::Error> {
-        match self.account_orm.create_account(uid, exchange, api_key, sign_key).await {
+        match self.account_orm.create_account(uid, exchange, api_key, sign_key, credentials).await {
             Ok(account_id) => {
-                println!("account with uid \"{}\" created", account_id);
+                log::info!("account with uid \"{}\" created", account_id);
                 Ok(())
             }
             Err(err) => Err(err)
         }
     }
 
-    pub async fn remove_key(&self, uid: &AccountId) -> Result<(), anyhow::Error> {
-        match self.account_orm.remove_key(uid).await {
+    pub async fn remove_key(
+        &self,
+        uid: &AccountId,
+        exchange: Option<&ExchangeName>,
+        expected_version: Option<i64>,
+    ) -> Result<(), anyhow::Error> {
+        match self.account_orm.remove_key(uid, exchange, expected_version).await {
             Ok(()) => Ok(()),
             Err(err) => Err(err)
         }
@@ -53,10 +107,10 @@ impl AccountRepo {
         &self,
         uid: &AccountId,
         exchange: &ExchangeName,
-        api_key: Option<String>,
-        sign_key: Option<String>,
-    ) -> Result<String, anyhow::Error> {
-        match self.account_orm.update_account(uid, exchange, api_key, sign_key).await {
+        patch: &AccountPatch,
+        expected_version: Option<i64>,
+    ) -> Result<(String, i64), anyhow::Error> {
+        match self.account_orm.update_account(uid, exchange, patch, expected_version).await {
             Ok(res) => Ok(res),
             Err(err) => Err(err)
         }
@@ -66,17 +120,171 @@ impl AccountRepo {
         &self,
         uid: &AccountId,
         exchange: &ExchangeName,
-    ) -> Result<String, anyhow::Error> {
+    ) -> Result<Secret, anyhow::Error> {
         match self.account_orm.get_api_key(uid, exchange).await {
             Ok(res) => Ok(res),
             Err(err) => Err(err)
         }
     }
 
-    pub async fn remove_account(&self, uid: &AccountId) -> Result<(), anyhow::Error> {
-        match self.account_orm.remove_account(uid).await {
+    pub async fn remove_account(
+        &self,
+        uid: &AccountId,
+        expected_version: Option<i64>,
+    ) -> Result<(), anyhow::Error> {
+        match self.account_orm.remove_account(uid, expected_version).await {
             Ok(()) => Ok(()),
             Err(err) => Err(err)
         }
     }
-}
\ No newline at end of file
+
+    pub async fn restore_account(
+        &self,
+        uid: &AccountId,
+    ) -> Result<(String, ExchangeName, i64), anyhow::Error> {
+        match self.account_orm.restore_account(uid, Utc::now() - self.deleted_retention).await {
+            Ok(res) => Ok(res),
+            Err(err) => Err(err)
+        }
+    }
+
+    /// Removes accounts deleted longer ago than the retention period.
+    pub async fn purge_deleted(&self) -> Result<u64, anyhow::Error> {
+        match self.account_orm.purge_deleted(Utc::now() - self.deleted_retention).await {
+            Ok(purged) => {
+                if purged > 0 {
+                    log::info!("{} deleted accounts purged", purged);
+                }
+                Ok(purged)
+            }
+            Err(err) => Err(err)
+        }
+    }
+
+    /// Runs `purge_deleted` every `interval` for as long as the process lives.
+    pub fn spawn_purge_task(self: Arc<Self>, interval: std::time::Duration) {
+        tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(interval);
+            loop {
+                ticks.tick().await;
+                if let Err(err) = self.purge_deleted().await {
+                    log::error!("purging deleted accounts failed: {}", err);
+                }
+                if self.prune_events {
+                    if let Err(err) = self.account_orm.prune_events().await {
+                        log::error!("pruning account events failed: {}", err);
+                    }
+                }
+            }
+        });
+    }
+
+    pub async fn deleted_accounts(&self) -> Result<Vec<(String, ExchangeName)>, anyhow::Error> {
+        match self.account_orm.deleted_accounts().await {
+            Ok(res) => Ok(res),
+            Err(err) => Err(err)
+        }
+    }
+
+    pub async fn list_accounts(
+        &self,
+        query: &ListAccountsQuery,
+    ) -> Result<(Vec<AccountSummary>, Option<String>), anyhow::Error> {
+        match self.account_orm.list_accounts(query).await {
+            Ok(res) => Ok(res),
+            Err(err) => Err(err)
+        }
+    }
+
+    pub async fn get_account(
+        &self,
+        uid: &AccountId,
+        exchange: Option<&ExchangeName>,
+    ) -> Result<AccountEntity, anyhow::Error> {
+        match self.account_orm.get_account(uid, exchange).await {
+            Ok(res) => Ok(res),
+            Err(err) => Err(err)
+        }
+    }
+
+    /// Generates a key pair for the account, keeping the private key, and returns the account uid,
+    /// its new version and the public key to register at the exchange.
+    pub async fn generate_key_pair(
+        &self,
+        uid: &AccountId,
+        exchange: &ExchangeName,
+        key_type: KeyType,
+        expected_version: Option<i64>,
+    ) -> Result<(String, i64, String), anyhow::Error> {
+        let der = tokio::task::spawn_blocking(move || keys::generate(key_type)).await??;
+        let public_key = SigningKey::from_pkcs8(key_type, &der)?.public_key_pem()?;
+        let (uid, version) = self.account_orm
+            .store_signing_key(uid, exchange, key_type, &der, expected_version)
+            .await?;
+        log::info!("key pair for account with uid \"{}\" generated", uid);
+        Ok((uid, version, public_key))
+    }
+
+    pub async fn sign_request(
+        &self,
+        uid: &AccountId,
+        exchange: &ExchangeName,
+        request: ExchangeRequest,
+    ) -> Result<SignedRequest, anyhow::Error> {
+        let result = match self.account_orm.get_credentials(uid, exchange).await {
+            Ok(credentials) => match self.next_nonce(uid, exchange).await {
+                Ok(nonce) => exchange_auth::sign_request(exchange, &credentials, request, Utc::now(), nonce),
+                Err(err) => Err(err),
+            },
+            Err(err) => Err(err),
+        };
+        if let Err(err) = &result {
+            if let Err(record_err) = self.account_orm.record_signing_failure(uid, exchange, err).await {
+                log::error!("recording signing failure for account with uid \"{}\" failed: {}", uid.0, record_err);
+            }
+        }
+        result
+    }
+
+    pub async fn account_version(
+        &self,
+        uid: &AccountId,
+        exchange: &ExchangeName,
+    ) -> Result<Option<i64>, anyhow::Error> {
+        match self.account_orm.account_version(uid, exchange).await {
+            Ok(res) => Ok(res),
+            Err(err) => Err(err)
+        }
+    }
+
+    pub async fn is_deleted(&self, uid: &AccountId) -> Result<bool, anyhow::Error> {
+        match self.account_orm.is_deleted(uid).await {
+            Ok(res) => Ok(res),
+            Err(err) => Err(err)
+        }
+    }
+
+    /// Decrypted secrets of the account, for tooling that moves accounts between deployments.
+    pub async fn get_secrets(
+        &self,
+        uid: &AccountId,
+        exchange: &ExchangeName,
+    ) -> Result<AccountSecrets, anyhow::Error> {
+        match self.account_orm.get_secrets(uid, exchange).await {
+            Ok(res) => Ok(res),
+            Err(err) => Err(err)
+        }
+    }
+
+    pub async fn reencrypt(
+        &self,
+        uid: &AccountId,
+        exchange: &ExchangeName,
+        target: &CredentialCipher,
+    ) -> Result<bool, anyhow::Error> {
+        match self.account_orm.reencrypt(uid, exchange, target).await {
+            Ok(res) => Ok(res),
+            Err(err) => Err(err)
+        }
+    }
+}
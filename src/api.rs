@@ -0,0 +1,776 @@
+use percent_encoding::percent_decode_str;
+use serde_json::Value;
+use warp::http::Method;
+use warp::path::FullPath;
+use warp::{Filter, Rejection};
+use crate::docs::{self, Schemas};
+use crate::dto::{
+    CreateAccountDto, SignAndGetDto, UpdateAccountDto, GetApiKeyDto, AccountChangesDto, SignatureRequestDto,
+    SignatureDto, ApiKeyDto, AccountRefDto, ErrorDto, ValidationErrorDto, SignRequestDto, SignedRequestDto,
+    KeyPairRequestDto, PublicKeyDto, PublishedEventDto,
+};
+use crate::models::ExchangeName;
+
+/// Describes a schema, adding any models it names to the document's components.
+pub type Schema = fn(&mut Schemas) -> Value;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Security {
+    Public,
+    /// An optional token of `API_TOKENS`, identifying the caller to the rate limits.
+    Caller,
+    /// A token of `STREAM_TOKENS`, as bearer token or `token` query parameter.
+    StreamToken,
+}
+
+pub struct Content {
+    pub media_type: &'static str,
+    pub schema: Schema,
+    /// JSON of an example value.
+    pub example: Option<&'static str>,
+}
+
+pub struct Reply {
+    pub status: u16,
+    pub description: &'static str,
+    pub content: Option<Content>,
+    /// Carries the account version in `ETag`.
+    pub etag: bool,
+}
+
+pub struct QueryParam {
+    pub name: &'static str,
+    pub schema: Schema,
+    pub description: &'static str,
+}
+
+/// A route of the service, matched by `routes` and described by the OpenAPI document.
+pub struct Endpoint {
+    pub operation_id: &'static str,
+    pub method: Method,
+    /// Path template, with `{uid}` and `{exchange}` standing for path segments.
+    pub path: &'static str,
+    pub tag: &'static str,
+    pub summary: &'static str,
+    pub description: Option<&'static str>,
+    pub deprecated: bool,
+    pub security: Security,
+    pub query: &'static [QueryParam],
+    /// Accepts an account version in `If-Match`.
+    pub if_match: bool,
+    /// Replays the stored response for a repeated `Idempotency-Key`.
+    pub idempotent: bool,
+    /// Accepted request bodies, one per media type.
+    pub body: &'static [Content],
+    pub replies: &'static [Reply],
+}
+
+impl Endpoint {
+    /// Names of the `{...}` segments of the path, in order.
+    pub fn params(&self) -> impl Iterator<Item=&'static str> {
+        self.path
+            .split('/')
+            .filter_map(|segment| segment.strip_prefix('{').and_then(|segment| segment.strip_suffix('}')))
+    }
+
+    /// The percent-decoded values of the path parameters, if `path` matches the template.
+    fn match_path(&self, path: &str) -> Option<Vec<String>> {
+        let template: Vec<&str> = self.path.trim_matches('/').split('/').collect();
+        let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+        if template.len() != segments.len() {
+            return None;
+        }
+        let mut params = Vec::new();
+        for (expected, segment) in template.iter().zip(segments) {
+            if expected.starts_with('{') {
+                params.push(percent_decode_str(segment).decode_utf8().ok()?.into_owned());
+            } else if *expected != segment {
+                return None;
+            }
+        }
+        Some(params)
+    }
+}
+
+/// The endpoint of `ENDPOINTS` serving `method` and `path`, with its path parameters in order.
+pub fn find(method: &Method, path: &str) -> Option<(&'static Endpoint, Vec<String>)> {
+    ENDPOINTS.iter()
+        .filter(|endpoint| endpoint.method == *method)
+        .find_map(|endpoint| endpoint.match_path(path).map(|params| (*endpoint, params)))
+}
+
+/// Matches the method and path of `endpoint`, extracting its path parameters in order.
+/// Panics for endpoints missing from `ENDPOINTS`, which the OpenAPI document is made of.
+pub fn route(endpoint: &'static Endpoint) -> impl Filter<Extract=(Vec<String>, ), Error=Rejection> + Clone {
+    assert!(
+        ENDPOINTS.iter().any(|listed| std::ptr::eq(*listed, endpoint)),
+        "{} is not listed in api::ENDPOINTS",
+        endpoint.operation_id,
+    );
+    warp::method()
+        .and(warp::path::full())
+        .and_then(move |method: Method, path: FullPath| async move {
+            if method != endpoint.method {
+                return Err(warp::reject::not_found());
+            }
+            endpoint.match_path(path.as_str()).ok_or_else(warp::reject::not_found)
+        })
+}
+
+const JSON: &str = "application/json";
+const TEXT: &str = "text/plain";
+
+macro_rules! json_content {
+    ($schema:expr) => {
+        Some(Content { media_type: JSON, schema: $schema, example: None })
+    };
+}
+
+macro_rules! reply {
+    ($status:expr, $description:expr, $content:expr) => {
+        Reply { status: $status, description: $description, content: $content, etag: false }
+    };
+}
+
+macro_rules! versioned {
+    ($status:expr, $description:expr, $content:expr) => {
+        Reply { status: $status, description: $description, content: $content, etag: true }
+    };
+}
+
+const TEXT_CONTENT: Option<Content> = Some(Content { media_type: TEXT, schema: docs::string, example: None });
+const YAML_CONTENT: Option<Content> = Some(Content { media_type: "application/yaml", schema: docs::string, example: None });
+const ERROR: Option<Content> = json_content!(docs::model::<ErrorDto>);
+const NOT_FOUND: Reply = reply!(404, "Account not found", ERROR);
+const BAD_REQUEST: Reply = reply!(400, "Invalid request or `If-Match` header", ERROR);
+const VERSION_MISMATCH: Reply = reply!(412, "Account changed since the version in `If-Match`", ERROR);
+const INVALID: Reply = reply!(422, "Request validation failed", json_content!(docs::model::<ValidationErrorDto>));
+
+const LIST_ACCOUNTS_QUERY: &[QueryParam] = &[
+    QueryParam { name: "exchange", schema: docs::model::<ExchangeName>, description: "Only accounts for this exchange" },
+    QueryParam { name: "has_api_key", schema: docs::boolean, description: "Only accounts with or without an api key" },
+    QueryParam { name: "has_sign_key", schema: docs::boolean, description: "Only accounts with or without a sign key" },
+    QueryParam { name: "created_after", schema: docs::date_time, description: "" },
+    QueryParam { name: "created_before", schema: docs::date_time, description: "" },
+    QueryParam { name: "updated_after", schema: docs::date_time, description: "" },
+    QueryParam { name: "updated_before", schema: docs::date_time, description: "" },
+    QueryParam { name: "uid_prefix", schema: docs::string, description: "Only uids starting with this prefix" },
+    QueryParam { name: "sort", schema: docs::account_sort, description: "Defaults to `uid`" },
+    QueryParam { name: "order", schema: docs::sort_order, description: "Defaults to `asc`" },
+    QueryParam { name: "cursor", schema: docs::string, description: "`next_cursor` of the previous page" },
+    QueryParam { name: "limit", schema: docs::page_limit, description: "Page size" },
+];
+
+const KEY_FORMATS: &str = "Binance api and sign keys are 64 ASCII letters and digits. The other exchanges don't \
+    publish a fixed format, so their keys are accepted as any non-empty printable ASCII of at most 512 characters.";
+
+const CREATE_ACCOUNT_EXAMPLE: &str = r#"{
+    "uid": "alice",
+    "exchange": "okex",
+    "api_key": "5a7b2c9d-0e1f-4a3b-8c5d-6e7f8a9b0c1d",
+    "sign_key": "C4D5E6F7A8B9C0D1E2F3A4B5C6D7E8F9",
+    "credentials": { "exchange": "okex", "passphrase": "correct horse" }
+}"#;
+
+pub static V1_LIST_ACCOUNTS: Endpoint = Endpoint {
+    operation_id: "listAccounts",
+    method: Method::GET,
+    path: "/v1/accounts",
+    tag: "accounts",
+    summary: "List accounts",
+    description: Some("Pages through accounts; pass `next_cursor` of a page as `cursor` for the next one."),
+    deprecated: false,
+    security: Security::Caller,
+    query: LIST_ACCOUNTS_QUERY,
+    if_match: false,
+    idempotent: false,
+    body: &[],
+    replies: &[
+        reply!(200, "A page of accounts", json_content!(docs::account_page)),
+        reply!(400, "Invalid query", ERROR),
+    ],
+};
+
+pub static V1_CREATE_ACCOUNT: Endpoint = Endpoint {
+    operation_id: "createAccount",
+    method: Method::POST,
+    path: "/v1/accounts",
+    tag: "accounts",
+    summary: "Create account",
+    description: Some(KEY_FORMATS),
+    deprecated: false,
+    security: Security::Caller,
+    query: &[],
+    if_match: false,
+    idempotent: true,
+    body: &[Content { media_type: JSON, schema: docs::model::<CreateAccountDto>, example: Some(CREATE_ACCOUNT_EXAMPLE) }],
+    replies: &[
+        reply!(201, "Account created", json_content!(docs::model::<AccountRefDto>)),
+        reply!(400, "Account not created", ERROR),
+        reply!(409, "Account exists, or was deleted and awaits restore or purge", ERROR),
+        INVALID,
+    ],
+};
+
+pub static V1_GET_ACCOUNT: Endpoint = Endpoint {
+    operation_id: "getAccount",
+    method: Method::GET,
+    path: "/v1/accounts/{uid}",
+    tag: "accounts",
+    summary: "Get account metadata",
+    description: None,
+    deprecated: false,
+    security: Security::Caller,
+    query: &[],
+    if_match: false,
+    idempotent: false,
+    body: &[],
+    replies: &[
+        versioned!(200, "Account metadata, without secrets", json_content!(docs::account_details)),
+        NOT_FOUND,
+        INVALID,
+    ],
+};
+
+pub static V1_REMOVE_ACCOUNT: Endpoint = Endpoint {
+    operation_id: "removeAccount",
+    method: Method::DELETE,
+    path: "/v1/accounts/{uid}",
+    tag: "accounts",
+    summary: "Delete account, restorable until the retention period ends",
+    description: None,
+    deprecated: false,
+    security: Security::Caller,
+    query: &[],
+    if_match: true,
+    idempotent: true,
+    body: &[],
+    replies: &[
+        reply!(204, "Account deleted", None),
+        BAD_REQUEST,
+        NOT_FOUND,
+        VERSION_MISMATCH,
+        INVALID,
+    ],
+};
+
+pub static V1_RESTORE_ACCOUNT: Endpoint = Endpoint {
+    operation_id: "restoreAccount",
+    method: Method::POST,
+    path: "/v1/accounts/{uid}/restore",
+    tag: "accounts",
+    summary: "Restore deleted account",
+    description: None,
+    deprecated: false,
+    security: Security::Caller,
+    query: &[],
+    if_match: false,
+    idempotent: true,
+    body: &[],
+    replies: &[
+        versioned!(200, "Account restored", json_content!(docs::model::<AccountRefDto>)),
+        reply!(404, "No deleted account with this uid within the retention period", ERROR),
+        INVALID,
+    ],
+};
+
+pub static V1_GET_EXCHANGE_ACCOUNT: Endpoint = Endpoint {
+    operation_id: "getExchangeAccount",
+    method: Method::GET,
+    path: "/v1/accounts/{uid}/{exchange}",
+    tag: "accounts",
+    summary: "Get account metadata for exchange",
+    description: None,
+    deprecated: false,
+    security: Security::Caller,
+    query: &[],
+    if_match: false,
+    idempotent: false,
+    body: &[],
+    replies: &[
+        versioned!(200, "Account metadata, without secrets", json_content!(docs::account_details)),
+        NOT_FOUND,
+        INVALID,
+    ],
+};
+
+pub static V1_UPDATE_ACCOUNT: Endpoint = Endpoint {
+    operation_id: "updateAccount",
+    method: Method::PATCH,
+    path: "/v1/accounts/{uid}/{exchange}",
+    tag: "accounts",
+    summary: "Update account",
+    description: Some(
+        "Omitted fields are left unchanged, `null` clears the stored value. Keys follow the formats of `createAccount`.",
+    ),
+    deprecated: false,
+    security: Security::Caller,
+    query: &[],
+    if_match: true,
+    idempotent: true,
+    body: &[Content {
+        media_type: JSON,
+        schema: docs::model::<AccountChangesDto>,
+        example: Some(r#"{ "api_key": "0f1e2d3c-4b5a-6978-8796-a5b4c3d2e1f0", "sign_key": null }"#),
+    }],
+    replies: &[
+        versioned!(200, "Account updated", json_content!(docs::model::<AccountRefDto>)),
+        BAD_REQUEST,
+        NOT_FOUND,
+        VERSION_MISMATCH,
+        INVALID,
+    ],
+};
+
+pub static V1_CREATE_SIGNATURE: Endpoint = Endpoint {
+    operation_id: "createSignature",
+    method: Method::POST,
+    path: "/v1/accounts/{uid}/{exchange}/signatures",
+    tag: "signing",
+    summary: "Sign and get key",
+    description: Some("Returns the api key with a fresh nonce; the payload is only recorded when retention is on."),
+    deprecated: false,
+    security: Security::Caller,
+    query: &[],
+    if_match: false,
+    idempotent: true,
+    body: &[
+        Content {
+            media_type: JSON,
+            schema: docs::model::<SignatureRequestDto>,
+            example: Some(r#"{ "data_to_sign": "c3ltYm9sPUJUQ1VTRFQ=", "encoding": "base64" }"#),
+        },
+        Content { media_type: "application/octet-stream", schema: docs::binary, example: None },
+        Content { media_type: TEXT, schema: docs::string, example: None },
+    ],
+    replies: &[
+        reply!(201, "Payload signed", json_content!(docs::model::<SignatureDto>)),
+        NOT_FOUND,
+        INVALID,
+    ],
+};
+
+pub static V1_SIGN_REQUEST: Endpoint = Endpoint {
+    operation_id: "signRequest",
+    method: Method::POST,
+    path: "/v1/accounts/{uid}/{exchange}/requests",
+    tag: "signing",
+    summary: "Build signed headers and query for an exchange request",
+    description: Some("Declare the exchange weight of the request in `X-Exchange-Weight` to track the account's budget."),
+    deprecated: false,
+    security: Security::Caller,
+    query: &[],
+    if_match: false,
+    idempotent: true,
+    body: &[Content {
+        media_type: JSON,
+        schema: docs::model::<SignRequestDto>,
+        example: Some(r#"{
+    "method": "GET",
+    "path": "/api/v3/account",
+    "query": [{ "name": "recvWindow", "value": "5000" }]
+}"#),
+    }],
+    replies: &[
+        reply!(200, "Request signed", json_content!(docs::model::<SignedRequestDto>)),
+        reply!(400, "Request not signed", ERROR),
+        NOT_FOUND,
+        INVALID,
+    ],
+};
+
+pub static V1_GENERATE_KEY_PAIR: Endpoint = Endpoint {
+    operation_id: "generateKeyPair",
+    method: Method::POST,
+    path: "/v1/accounts/{uid}/{exchange}/keypair",
+    tag: "signing",
+    summary: "Generate a key pair, keeping the private key and returning the public key",
+    description: None,
+    deprecated: false,
+    security: Security::Caller,
+    query: &[],
+    if_match: true,
+    idempotent: true,
+    body: &[Content {
+        media_type: JSON,
+        schema: docs::model::<KeyPairRequestDto>,
+        example: Some(r#"{ "key_type": "ed25519" }"#),
+    }],
+    replies: &[
+        versioned!(201, "Key pair generated", json_content!(docs::model::<PublicKeyDto>)),
+        BAD_REQUEST,
+        NOT_FOUND,
+        VERSION_MISMATCH,
+        INVALID,
+    ],
+};
+
+pub static V1_GET_API_KEY: Endpoint = Endpoint {
+    operation_id: "getApiKey",
+    method: Method::GET,
+    path: "/v1/accounts/{uid}/{exchange}/api-key",
+    tag: "accounts",
+    summary: "Get api key",
+    description: None,
+    deprecated: false,
+    security: Security::Caller,
+    query: &[],
+    if_match: false,
+    idempotent: false,
+    body: &[],
+    replies: &[
+        reply!(200, "The api key", json_content!(docs::model::<ApiKeyDto>)),
+        NOT_FOUND,
+        INVALID,
+    ],
+};
+
+pub static V1_REMOVE_API_KEY: Endpoint = Endpoint {
+    operation_id: "removeApiKey",
+    method: Method::DELETE,
+    path: "/v1/accounts/{uid}/{exchange}/api-key",
+    tag: "accounts",
+    summary: "Delete api key",
+    description: None,
+    deprecated: false,
+    security: Security::Caller,
+    query: &[],
+    if_match: true,
+    idempotent: true,
+    body: &[],
+    replies: &[
+        reply!(204, "Api key deleted", None),
+        BAD_REQUEST,
+        NOT_FOUND,
+        VERSION_MISMATCH,
+        INVALID,
+    ],
+};
+
+const EXECUTOR_EVENT_EXAMPLE: &str = r#"{
+    "GetOrderResponse": {
+        "id": "6f1e2d3c-4b5a-4978-8a6b-5c4d3e2f1a0b",
+        "res": {
+            "Ok": {
+                "order_type": "Limit",
+                "price": "41000.5",
+                "volume": "0.25",
+                "side": "Buy",
+                "base": "BTC",
+                "counter": "USDT",
+                "user_id": "6f1e2d3c-4b5a-4978-8a6b-5c4d3e2f1a0b",
+                "market_id": "8401273",
+                "state": "PartiallyFilled",
+                "filled_volume": "0.1"
+            }
+        }
+    }
+}"#;
+
+pub static V1_PUBLISH_EXECUTOR_EVENT: Endpoint = Endpoint {
+    operation_id: "publishExecutorEvent",
+    method: Method::POST,
+    path: "/v1/accounts/{uid}/executor-events",
+    tag: "events",
+    summary: "Stream an executor response to subscribers of the account's orders, trades or balances",
+    description: Some(
+        "Takes an `ExecutorResponse` as serialized by the executor. Balances go to `balances:<uid>`, trades to \
+        `trades:<uid>` and placed, cancelled or fetched orders to `orders:<uid>`. Only served when `STREAM_TOKENS` is set.",
+    ),
+    deprecated: false,
+    security: Security::Caller,
+    query: &[],
+    if_match: false,
+    idempotent: false,
+    body: &[Content { media_type: JSON, schema: docs::object, example: Some(EXECUTOR_EVENT_EXAMPLE) }],
+    replies: &[
+        reply!(202, "Event published", json_content!(docs::model::<PublishedEventDto>)),
+        reply!(400, "Not an executor response", ERROR),
+        reply!(404, "The event stream is disabled", ERROR),
+        INVALID,
+    ],
+};
+
+pub static STREAM: Endpoint = Endpoint {
+    operation_id: "stream",
+    method: Method::GET,
+    path: "/ws",
+    tag: "events",
+    summary: "Open a websocket streaming the account, order, trade and balance events of subscribed topics",
+    description: Some(
+        "Topics are written as `<kind>:<uid>`, with kind `accounts`, `orders`, `trades` or `balances`. Only served \
+        when `STREAM_TOKENS` is set; each token may only subscribe to the accounts it names.",
+    ),
+    deprecated: false,
+    security: Security::StreamToken,
+    query: &[],
+    if_match: false,
+    idempotent: false,
+    body: &[],
+    replies: &[
+        reply!(101, "Switched to the websocket protocol", None),
+        reply!(401, "Missing or unknown token", ERROR),
+    ],
+};
+
+pub static OPENAPI_JSON: Endpoint = Endpoint {
+    operation_id: "openApiJson",
+    method: Method::GET,
+    path: "/openapi.json",
+    tag: "docs",
+    summary: "This document, as JSON",
+    description: None,
+    deprecated: false,
+    security: Security::Public,
+    query: &[],
+    if_match: false,
+    idempotent: false,
+    body: &[],
+    replies: &[reply!(200, "OpenAPI document", json_content!(docs::object))],
+};
+
+pub static OPENAPI_YAML: Endpoint = Endpoint {
+    operation_id: "openApiYaml",
+    method: Method::GET,
+    path: "/openapi.yaml",
+    tag: "docs",
+    summary: "This document, as YAML",
+    description: None,
+    deprecated: false,
+    security: Security::Public,
+    query: &[],
+    if_match: false,
+    idempotent: false,
+    body: &[],
+    replies: &[reply!(200, "OpenAPI document", YAML_CONTENT)],
+};
+
+pub static SWAGGER_YAML: Endpoint = Endpoint {
+    operation_id: "swaggerYaml",
+    method: Method::GET,
+    path: "/swagger.yaml",
+    tag: "docs",
+    summary: "This document, as YAML",
+    description: Some("Use `/openapi.yaml`."),
+    deprecated: true,
+    security: Security::Public,
+    query: &[],
+    if_match: false,
+    idempotent: false,
+    body: &[],
+    replies: &[reply!(200, "OpenAPI document", YAML_CONTENT)],
+};
+
+pub static DOCS_UI: Endpoint = Endpoint {
+    operation_id: "docsUi",
+    method: Method::GET,
+    path: "/docs",
+    tag: "docs",
+    summary: "Browse and try this document in Swagger UI",
+    description: Some("Served unless `DOCS_UI` is off."),
+    deprecated: false,
+    security: Security::Public,
+    query: &[],
+    if_match: false,
+    idempotent: false,
+    body: &[],
+    replies: &[
+        reply!(200, "Swagger UI page", Some(Content { media_type: "text/html", schema: docs::string, example: None })),
+        reply!(301, "Redirect to `/docs/`, which the page's assets are relative to", None),
+    ],
+};
+
+pub static DOCS_UI_ASSET: Endpoint = Endpoint {
+    operation_id: "docsUiAsset",
+    method: Method::GET,
+    path: "/docs/{file}",
+    tag: "docs",
+    summary: "Script, style or image of the Swagger UI page",
+    description: None,
+    deprecated: false,
+    security: Security::Public,
+    query: &[],
+    if_match: false,
+    idempotent: false,
+    body: &[],
+    replies: &[
+        reply!(200, "The asset", Some(Content { media_type: "application/octet-stream", schema: docs::binary, example: None })),
+        reply!(404, "No such asset", None),
+    ],
+};
+
+pub static LEGACY_CREATE_ACCOUNT: Endpoint = Endpoint {
+    operation_id: "legacyCreateAccount",
+    method: Method::POST,
+    path: "/account",
+    tag: "legacy",
+    summary: "Create account",
+    description: Some("Use `POST /v1/accounts`."),
+    deprecated: true,
+    security: Security::Caller,
+    query: &[],
+    if_match: false,
+    idempotent: true,
+    body: &[Content { media_type: JSON, schema: docs::model::<CreateAccountDto>, example: Some(CREATE_ACCOUNT_EXAMPLE) }],
+    replies: &[reply!(200, "Account created", TEXT_CONTENT), reply!(400, "Account not created", TEXT_CONTENT), INVALID],
+};
+
+pub static LEGACY_SIGN_AND_GET_KEY: Endpoint = Endpoint {
+    operation_id: "legacySignAndGetKey",
+    method: Method::PUT,
+    path: "/account",
+    tag: "legacy",
+    summary: "Sign and get key",
+    description: Some("Use `POST /v1/accounts/{uid}/{exchange}/signatures`."),
+    deprecated: true,
+    security: Security::Caller,
+    query: &[],
+    if_match: false,
+    idempotent: true,
+    body: &[Content { media_type: JSON, schema: docs::model::<SignAndGetDto>, example: None }],
+    replies: &[reply!(200, "Uid and api key", TEXT_CONTENT), reply!(400, "Payload not signed", TEXT_CONTENT), INVALID],
+};
+
+pub static LEGACY_UPDATE_ACCOUNT: Endpoint = Endpoint {
+    operation_id: "legacyUpdateAccount",
+    method: Method::PATCH,
+    path: "/account",
+    tag: "legacy",
+    summary: "Update account",
+    description: Some("Use `PATCH /v1/accounts/{uid}/{exchange}`."),
+    deprecated: true,
+    security: Security::Caller,
+    query: &[],
+    if_match: false,
+    idempotent: true,
+    body: &[Content { media_type: JSON, schema: docs::model::<UpdateAccountDto>, example: None }],
+    replies: &[reply!(200, "Account updated", TEXT_CONTENT), reply!(400, "Account not updated", TEXT_CONTENT), INVALID],
+};
+
+pub static LEGACY_REMOVE_ACCOUNT: Endpoint = Endpoint {
+    operation_id: "legacyRemoveAccount",
+    method: Method::DELETE,
+    path: "/account/{uid}",
+    tag: "legacy",
+    summary: "Delete account",
+    description: Some("Use `DELETE /v1/accounts/{uid}`."),
+    deprecated: true,
+    security: Security::Caller,
+    query: &[],
+    if_match: false,
+    idempotent: true,
+    body: &[],
+    replies: &[reply!(200, "Account deleted", TEXT_CONTENT), reply!(400, "Account not deleted", TEXT_CONTENT)],
+};
+
+pub static LEGACY_REMOVE_KEY: Endpoint = Endpoint {
+    operation_id: "legacyRemoveKey",
+    method: Method::DELETE,
+    path: "/key/account/{uid}",
+    tag: "legacy",
+    summary: "Delete api keys of all exchanges",
+    description: Some("Use `DELETE /v1/accounts/{uid}/{exchange}/api-key`."),
+    deprecated: true,
+    security: Security::Caller,
+    query: &[],
+    if_match: false,
+    idempotent: true,
+    body: &[],
+    replies: &[reply!(200, "Keys deleted", TEXT_CONTENT), reply!(400, "Keys not deleted", TEXT_CONTENT)],
+};
+
+pub static LEGACY_GET_API_KEY: Endpoint = Endpoint {
+    operation_id: "legacyGetApiKey",
+    method: Method::PUT,
+    path: "/key/account",
+    tag: "legacy",
+    summary: "Get api key",
+    description: Some("Use `GET /v1/accounts/{uid}/{exchange}/api-key`."),
+    deprecated: true,
+    security: Security::Caller,
+    query: &[],
+    if_match: false,
+    idempotent: true,
+    body: &[Content { media_type: JSON, schema: docs::model::<GetApiKeyDto>, example: None }],
+    replies: &[reply!(200, "The api key", TEXT_CONTENT), reply!(404, "Account not found", TEXT_CONTENT), INVALID],
+};
+
+pub static LEGACY_LIST_ACCOUNTS: Endpoint = Endpoint {
+    operation_id: "legacyListAccounts",
+    method: Method::GET,
+    path: "/accounts",
+    tag: "legacy",
+    summary: "List accounts",
+    description: Some("Use `GET /v1/accounts`, which takes the same query."),
+    deprecated: true,
+    security: Security::Caller,
+    query: LIST_ACCOUNTS_QUERY,
+    if_match: false,
+    idempotent: false,
+    body: &[],
+    replies: &[reply!(200, "A page of accounts", json_content!(docs::account_page)), reply!(400, "Invalid query", ERROR)],
+};
+
+pub static LEGACY_GET_ACCOUNT: Endpoint = Endpoint {
+    operation_id: "legacyGetAccount",
+    method: Method::GET,
+    path: "/account/{uid}",
+    tag: "legacy",
+    summary: "Get account metadata",
+    description: Some("Use `GET /v1/accounts/{uid}`."),
+    deprecated: true,
+    security: Security::Caller,
+    query: &[],
+    if_match: false,
+    idempotent: false,
+    body: &[],
+    replies: &[versioned!(200, "Account metadata, without secrets", json_content!(docs::account_details)), NOT_FOUND],
+};
+
+pub static LEGACY_GET_EXCHANGE_ACCOUNT: Endpoint = Endpoint {
+    operation_id: "legacyGetExchangeAccount",
+    method: Method::GET,
+    path: "/account/{uid}/{exchange}",
+    tag: "legacy",
+    summary: "Get account metadata for exchange",
+    description: Some("Use `GET /v1/accounts/{uid}/{exchange}`."),
+    deprecated: true,
+    security: Security::Caller,
+    query: &[],
+    if_match: false,
+    idempotent: false,
+    body: &[],
+    replies: &[versioned!(200, "Account metadata, without secrets", json_content!(docs::account_details)), NOT_FOUND],
+};
+
+/// Every route of the service, in the order of the document.
+pub static ENDPOINTS: &[&Endpoint] = &[
+    &V1_LIST_ACCOUNTS,
+    &V1_CREATE_ACCOUNT,
+    &V1_GET_ACCOUNT,
+    &V1_REMOVE_ACCOUNT,
+    &V1_RESTORE_ACCOUNT,
+    &V1_GET_EXCHANGE_ACCOUNT,
+    &V1_UPDATE_ACCOUNT,
+    &V1_CREATE_SIGNATURE,
+    &V1_SIGN_REQUEST,
+    &V1_GENERATE_KEY_PAIR,
+    &V1_GET_API_KEY,
+    &V1_REMOVE_API_KEY,
+    &V1_PUBLISH_EXECUTOR_EVENT,
+    &STREAM,
+    &OPENAPI_JSON,
+    &OPENAPI_YAML,
+    &SWAGGER_YAML,
+    &DOCS_UI,
+    &DOCS_UI_ASSET,
+    &LEGACY_CREATE_ACCOUNT,
+    &LEGACY_SIGN_AND_GET_KEY,
+    &LEGACY_UPDATE_ACCOUNT,
+    &LEGACY_REMOVE_ACCOUNT,
+    &LEGACY_REMOVE_KEY,
+    &LEGACY_GET_API_KEY,
+    &LEGACY_LIST_ACCOUNTS,
+    &LEGACY_GET_ACCOUNT,
+    &LEGACY_GET_EXCHANGE_ACCOUNT,
+];
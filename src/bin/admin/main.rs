@@ -0,0 +1,464 @@
+use anyhow::{anyhow, bail};
+use serde::Serialize;
+use std::fs;
+use std::str::FromStr;
+use structopt::StructOpt;
+use try_api::account::AccountRepo;
+use try_api::config::{Config, KeyProviderConfig};
+use try_api::crypto::CredentialCipher;
+use try_api::db::{db_connect, db_migrate, AccountSummary};
+use try_api::dto::{
+    AccountChangesDto, AccountDetailsDto, AccountSummaryDto, CreateAccountDto, KeyFingerprintDto, KeyPairRequestDto,
+    ListAccountsQuery,
+};
+use try_api::key_provider;
+use try_api::models::{AccountId, AccountPatch, ExchangeCredentials, ExchangeName, KeyType};
+use try_api::secret::Secret;
+use try_api::transfer::{self, ArchiveIdentity, ArchiveKey, ImportAction, ImportStrategy};
+use try_api::validation::{Validate, ValidateForExchange, Validator};
+
+mod output;
+
+use output::Table;
+
+/// Manages accounts directly in the database, using the same environment as the service.
+#[derive(StructOpt)]
+#[structopt(name = "try_api-admin")]
+struct Opt {
+    /// Output format: `table` or `json`
+    #[structopt(long, default_value = "table", global = true)]
+    format: Format,
+    #[structopt(subcommand)]
+    command: Command,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Format {
+    Table,
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "table" => Ok(Format::Table),
+            "json" => Ok(Format::Json),
+            _ => Err(anyhow!("unknown format \"{}\", expected \"table\" or \"json\"", value)),
+        }
+    }
+}
+
+/// Secret arguments also take `@<path>` to read the value from a file, keeping it out of shell history.
+#[derive(StructOpt)]
+enum Command {
+    /// Create an account
+    Create {
+        uid: String,
+        exchange: ExchangeName,
+        #[structopt(long)]
+        api_key: String,
+        /// Shared secret, or a PEM Ed25519/RSA private key
+        #[structopt(long)]
+        sign_key: Option<String>,
+        /// Exchange specific credentials as JSON, e.g. `{"exchange":"okex","passphrase":"..."}`
+        #[structopt(long)]
+        credentials: Option<String>,
+    },
+    /// Change or clear an account's keys and credentials
+    Update {
+        uid: String,
+        exchange: ExchangeName,
+        #[structopt(long)]
+        api_key: Option<String>,
+        #[structopt(long, conflicts_with = "clear-sign-key")]
+        sign_key: Option<String>,
+        #[structopt(long)]
+        clear_sign_key: bool,
+        #[structopt(long, conflicts_with = "clear-credentials")]
+        credentials: Option<String>,
+        #[structopt(long)]
+        clear_credentials: bool,
+        /// Only update if the account is still at this version
+        #[structopt(long)]
+        if_version: Option<i64>,
+    },
+    /// List accounts
+    List {
+        #[structopt(long)]
+        exchange: Option<ExchangeName>,
+        #[structopt(long)]
+        uid_prefix: Option<String>,
+        /// Stop after this many accounts
+        #[structopt(long)]
+        limit: Option<usize>,
+    },
+    /// Show an account, with keys reduced to fingerprints
+    Show {
+        uid: String,
+        exchange: Option<ExchangeName>,
+    },
+    /// Delete an account; it can be restored until the retention period ends
+    Delete {
+        uid: String,
+        #[structopt(long)]
+        if_version: Option<i64>,
+        /// Confirm the deletion
+        #[structopt(long)]
+        yes: bool,
+    },
+    /// Restore a deleted account
+    Restore {
+        uid: String,
+    },
+    /// Remove accounts deleted longer ago than the retention period now, instead of waiting
+    /// for the service's purge task
+    Purge,
+    /// Replace an account's key pair: a new api key and sign key, or a generated asymmetric key
+    RotateKey {
+        uid: String,
+        exchange: ExchangeName,
+        #[structopt(long, required_unless = "generate")]
+        api_key: Option<String>,
+        #[structopt(long, required_unless = "generate")]
+        sign_key: Option<String>,
+        /// Generate an `ed25519` or `rsa` key pair and print its public key
+        #[structopt(long, conflicts_with_all = &["api-key", "sign-key"])]
+        generate: Option<KeyType>,
+        #[structopt(long)]
+        if_version: Option<i64>,
+    },
+    /// Re-encrypt stored credentials under the key provider configured with `NEW_`-prefixed
+    /// variables, e.g. `NEW_MASTER_KEY` or `NEW_KEY_PROVIDER=vault`
+    Reencrypt {
+        #[structopt(long)]
+        exchange: Option<ExchangeName>,
+    },
+    /// Write accounts with their secrets into an age encrypted archive
+    Export {
+        file: String,
+        #[structopt(long)]
+        exchange: Option<ExchangeName>,
+        #[structopt(long)]
+        uid_prefix: Option<String>,
+        /// Passphrase to encrypt the archive with
+        #[structopt(long, required_unless = "recipient")]
+        passphrase: Option<String>,
+        /// age public key (`age1...`) to encrypt the archive to; may be repeated
+        #[structopt(long, conflicts_with = "passphrase")]
+        recipient: Vec<String>,
+    },
+    /// Import the accounts in an archive written by `export`
+    Import {
+        file: String,
+        #[structopt(long, required_unless = "identity")]
+        passphrase: Option<String>,
+        /// age identity file with the key the archive was encrypted to
+        #[structopt(long, conflicts_with = "passphrase")]
+        identity: Option<String>,
+        /// `merge` creates missing accounts and reports existing ones as conflicts,
+        /// `overwrite` replaces the secrets of existing accounts
+        #[structopt(long, default_value = "merge")]
+        strategy: ImportStrategy,
+        /// Report what would be imported without writing anything
+        #[structopt(long)]
+        dry_run: bool,
+    },
+    /// Apply pending database migrations
+    Migrate,
+}
+
+fn read_secret(value: String) -> Result<Secret, anyhow::Error> {
+    match value.strip_prefix('@') {
+        Some(path) => fs::read_to_string(path)
+            .map(|value| Secret::from(value.trim_end().to_string()))
+            .map_err(|err| anyhow!("can't read {}: {}", path, err)),
+        None => Ok(Secret::from(value)),
+    }
+}
+
+fn read_credentials(value: String) -> Result<ExchangeCredentials, anyhow::Error> {
+    let value = read_secret(value)?;
+    serde_json::from_str(value.expose()).map_err(|err| anyhow!("invalid credentials: {}", err))
+}
+
+/// Runs the service's validation rules, reporting all broken rules at once.
+fn check(validate: impl FnOnce(&mut Validator)) -> Result<(), anyhow::Error> {
+    let mut validator = Validator::default();
+    validate(&mut validator);
+    let errors = validator.into_errors();
+    if errors.is_empty() {
+        return Ok(());
+    }
+    let errors: Vec<String> = errors.iter().map(|error| format!("{}: {}", error.field, error.message)).collect();
+    bail!("invalid input: {}", errors.join("; "))
+}
+
+/// Pages through all accounts matching the filters.
+async fn all_accounts(
+    repo: &AccountRepo,
+    exchange: Option<ExchangeName>,
+    uid_prefix: Option<String>,
+    limit: Option<usize>,
+) -> Result<Vec<AccountSummary>, anyhow::Error> {
+    let mut query = ListAccountsQuery { exchange, uid_prefix, ..ListAccountsQuery::default() };
+    let mut accounts = Vec::new();
+    loop {
+        let (page, next_cursor) = repo.list_accounts(&query).await?;
+        accounts.extend(page);
+        if let Some(limit) = limit {
+            if accounts.len() >= limit {
+                accounts.truncate(limit);
+                break;
+            }
+        }
+        match next_cursor {
+            Some(cursor) => query.cursor = Some(cursor),
+            None => break,
+        }
+    }
+    Ok(accounts)
+}
+
+fn fingerprint(fingerprint: Option<&KeyFingerprintDto>) -> String {
+    match fingerprint {
+        Some(KeyFingerprintDto { last4: Some(last4), sha256_prefix }) => format!("...{} (sha256 {})", last4, sha256_prefix),
+        Some(KeyFingerprintDto { last4: None, sha256_prefix }) => format!("sha256 {}", sha256_prefix),
+        None => "-".to_string(),
+    }
+}
+
+fn print_json<T: Serialize>(value: &T) -> Result<(), anyhow::Error> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<(), anyhow::Error> {
+    // Commands report on stdout; the library only logs what went wrong on the way.
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).init();
+    let opt = Opt::from_args();
+    let config = Config::from_env();
+    let db = db_connect(&config.database_url).await?;
+    if let Command::Migrate = opt.command {
+        db_migrate(&db).await?;
+        println!("migrations applied");
+        return Ok(());
+    }
+    let repo = AccountRepo::new(db, &config, None).await;
+
+    match opt.command {
+        Command::Create { uid, exchange, api_key, sign_key, credentials } => {
+            let dto = CreateAccountDto {
+                uid,
+                exchange,
+                api_key: read_secret(api_key)?,
+                sign_key: sign_key.map(read_secret).transpose()?,
+                credentials: credentials.map(read_credentials).transpose()?,
+            };
+            check(|validator| dto.validate(validator))?;
+            repo.create_account(
+                &AccountId(dto.uid.clone()),
+                &dto.exchange,
+                &dto.api_key,
+                dto.sign_key.as_ref(),
+                dto.credentials.as_ref(),
+            ).await?;
+        }
+        Command::Update { uid, exchange, api_key, sign_key, clear_sign_key, credentials, clear_credentials, if_version } => {
+            let dto = AccountChangesDto {
+                api_key: api_key.map(read_secret).transpose()?.map(Some),
+                sign_key: match sign_key {
+                    Some(sign_key) => Some(Some(read_secret(sign_key)?)),
+                    None if clear_sign_key => Some(None),
+                    None => None,
+                },
+                credentials: match credentials {
+                    Some(credentials) => Some(Some(read_credentials(credentials)?)),
+                    None if clear_credentials => Some(None),
+                    None => None,
+                },
+            };
+            check(|validator| dto.validate_for(&exchange, validator))?;
+            let (uid, version) = repo
+                .update_account(&AccountId(uid), &exchange, &AccountPatch::from(dto), if_version)
+                .await?;
+            println!("account with uid \"{}\" updated to version {}", uid, version);
+        }
+        Command::List { exchange, uid_prefix, limit } => {
+            let accounts = all_accounts(&repo, exchange, uid_prefix, limit).await?;
+            match opt.format {
+                Format::Json => {
+                    let accounts: Vec<AccountSummaryDto> = accounts.into_iter().map(AccountSummaryDto::from).collect();
+                    print_json(&accounts)?;
+                }
+                Format::Table => {
+                    let mut table = Table::new(&["UID", "EXCHANGE", "API KEY", "SIGN KEY", "CREATED", "UPDATED"]);
+                    for account in accounts {
+                        table.row(vec![
+                            account.uid,
+                            account.exchange.to_string(),
+                            account.has_api_key.to_string(),
+                            account.has_sign_key.to_string(),
+                            account.created_at.to_rfc3339(),
+                            account.updated_at.to_rfc3339(),
+                        ]);
+                    }
+                    table.print();
+                }
+            }
+        }
+        Command::Show { uid, exchange } => {
+            let account = AccountDetailsDto::from(repo.get_account(&AccountId(uid), exchange.as_ref()).await?);
+            match opt.format {
+                Format::Json => print_json(&account)?,
+                Format::Table => {
+                    let mut table = Table::new(&["FIELD", "VALUE"]);
+                    table.row(vec!["uid".to_string(), account.uid.clone()]);
+                    table.row(vec!["exchange".to_string(), account.exchange.to_string()]);
+                    table.row(vec!["version".to_string(), account.version.to_string()]);
+                    table.row(vec!["api_key".to_string(), fingerprint(account.api_key_fingerprint.as_ref())]);
+                    table.row(vec!["sign_key".to_string(), fingerprint(account.sign_key_fingerprint.as_ref())]);
+                    table.row(vec![
+                        "key_type".to_string(),
+                        account.key_type.map(|key_type| key_type.to_string()).unwrap_or_else(|| "-".to_string()),
+                    ]);
+                    table.row(vec!["credentials".to_string(), account.has_credentials.to_string()]);
+                    table.row(vec!["created_at".to_string(), account.created_at.to_rfc3339()]);
+                    table.row(vec!["updated_at".to_string(), account.updated_at.to_rfc3339()]);
+                    table.print();
+                }
+            }
+        }
+        Command::Delete { uid, if_version, yes } => {
+            if !yes {
+                bail!("deleting account with uid \"{}\" needs --yes", uid);
+            }
+            repo.remove_account(&AccountId(uid), if_version).await?;
+        }
+        Command::Restore { uid } => {
+            let (uid, exchange, version) = repo.restore_account(&AccountId(uid)).await?;
+            println!("account with uid \"{}\" on {} restored at version {}", uid, exchange, version);
+        }
+        Command::Purge => {
+            if repo.purge_deleted().await? == 0 {
+                println!("no deleted accounts past the retention period");
+            }
+        }
+        Command::RotateKey { uid, exchange, api_key, sign_key, generate, if_version } => {
+            let uid = AccountId(uid);
+            match generate {
+                Some(key_type) => {
+                    let dto = KeyPairRequestDto { key_type };
+                    check(|validator| dto.validate_for(&exchange, validator))?;
+                    let (_, version, public_key) = repo.generate_key_pair(&uid, &exchange, key_type, if_version).await?;
+                    match opt.format {
+                        Format::Json => print_json(&serde_json::json!({
+                            "uid": uid.0,
+                            "exchange": exchange,
+                            "key_type": key_type,
+                            "version": version,
+                            "public_key": public_key,
+                        }))?,
+                        Format::Table => print!("{}", public_key),
+                    }
+                }
+                None => {
+                    let dto = AccountChangesDto {
+                        api_key: api_key.map(read_secret).transpose()?.map(Some),
+                        sign_key: sign_key.map(read_secret).transpose()?.map(Some),
+                        credentials: None,
+                    };
+                    check(|validator| dto.validate_for(&exchange, validator))?;
+                    let (uid, version) = repo
+                        .update_account(&uid, &exchange, &AccountPatch::from(dto), if_version)
+                        .await?;
+                    println!("keys of account with uid \"{}\" rotated, now at version {}", uid, version);
+                }
+            }
+        }
+        Command::Reencrypt { exchange } => {
+            let target = key_provider::from_config(&KeyProviderConfig::from_env_prefixed("NEW_"))?
+                .ok_or_else(|| anyhow!("no new master key configured, set NEW_MASTER_KEY or NEW_KEY_PROVIDER"))?;
+            let target = CredentialCipher::new(Some(target));
+            let (mut reencrypted, mut skipped, mut failed) = (0, 0, 0);
+            // Deleted accounts can still be restored, so their values move to the new key too.
+            let mut accounts: Vec<(String, ExchangeName)> = all_accounts(&repo, exchange.clone(), None, None)
+                .await?
+                .into_iter()
+                .map(|account| (account.uid, account.exchange))
+                .collect();
+            accounts.extend(repo.deleted_accounts().await?
+                .into_iter()
+                .filter(|(_, deleted)| exchange.as_ref().is_none_or(|exchange| exchange == deleted)));
+            for (uid, account_exchange) in accounts {
+                match repo.reencrypt(&AccountId(uid.clone()), &account_exchange, &target).await {
+                    Ok(true) => reencrypted += 1,
+                    Ok(false) => skipped += 1,
+                    Err(err) => {
+                        failed += 1;
+                        eprintln!("account with uid \"{}\" on {}: {}", uid, account_exchange, err);
+                    }
+                }
+            }
+            println!("{} accounts re-encrypted, {} without encrypted values, {} failed", reencrypted, skipped, failed);
+            if failed > 0 {
+                bail!("re-encryption incomplete, rerun it after fixing the failed accounts");
+            }
+        }
+        Command::Export { file, exchange, uid_prefix, passphrase, recipient } => {
+            let key = match passphrase {
+                Some(passphrase) => ArchiveKey::Passphrase(read_secret(passphrase)?),
+                None => ArchiveKey::Recipients(recipient
+                    .iter()
+                    .map(|recipient| recipient.parse().map_err(|err| anyhow!("invalid recipient \"{}\": {}", recipient, err)))
+                    .collect::<Result<_, anyhow::Error>>()?),
+            };
+            let accounts = all_accounts(&repo, exchange, uid_prefix, None).await?;
+            let archive = transfer::export(&repo, &accounts).await?;
+            let exported = archive.accounts.len();
+            fs::write(&file, transfer::seal(archive, key)?)?;
+            println!("{} accounts exported to {}", exported, file);
+        }
+        Command::Import { file, passphrase, identity, strategy, dry_run } => {
+            let identity = match (passphrase, identity) {
+                (Some(passphrase), _) => ArchiveIdentity::Passphrase(read_secret(passphrase)?),
+                (None, Some(path)) => ArchiveIdentity::Identities(age::IdentityFile::from_file(path)?.into_identities()),
+                (None, None) => unreachable!(),
+            };
+            let archive = transfer::open(&fs::read(&file)?, &identity)?;
+            let results = transfer::import(&repo, &archive, strategy, dry_run).await;
+            match opt.format {
+                Format::Json => print_json(&results)?,
+                Format::Table => {
+                    let mut table = Table::new(&["UID", "EXCHANGE", "ACTION", "DETAIL"]);
+                    for result in &results {
+                        table.row(vec![
+                            result.uid.clone(),
+                            result.exchange.clone(),
+                            result.action.to_string(),
+                            result.detail.clone().unwrap_or_default(),
+                        ]);
+                    }
+                    table.print();
+                }
+            }
+            let count = |action| results.iter().filter(|result| result.action == action).count();
+            eprintln!(
+                "{}{} created, {} overwritten, {} conflicts, {} invalid, {} failed",
+                if dry_run { "dry run: " } else { "" },
+                count(ImportAction::Created),
+                count(ImportAction::Overwritten),
+                count(ImportAction::Conflict),
+                count(ImportAction::Invalid),
+                count(ImportAction::Failed),
+            );
+            if count(ImportAction::Failed) > 0 {
+                bail!("import incomplete, some accounts failed");
+            }
+        }
+        Command::Migrate => unreachable!(),
+    }
+    Ok(())
+}
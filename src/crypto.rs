@@ -0,0 +1,139 @@
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use aes_gcm::aead::{Aead, NewAead, Payload};
+use anyhow::{anyhow, bail};
+use rand::RngCore;
+use std::convert::TryFrom;
+use std::sync::Arc;
+use crate::key_provider::KeyProvider;
+use zeroize::Zeroizing;
+
+pub const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+const WRAPPED_LEN_LEN: usize = 2;
+
+/// AES-256-GCM under `key`, returned as `nonce || ciphertext`.
+pub fn aes_seal(key: &[u8], plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>, anyhow::Error> {
+    let mut nonce = [0u8; NONCE_LEN];
+    rand::thread_rng().fill_bytes(&mut nonce);
+    let ciphertext = Aes256Gcm::new(Key::from_slice(key))
+        .encrypt(Nonce::from_slice(&nonce), Payload { msg: plaintext, aad })
+        .map_err(|_| anyhow!("encryption failed"))?;
+    let mut sealed = nonce.to_vec();
+    sealed.extend_from_slice(&ciphertext);
+    Ok(sealed)
+}
+
+pub fn aes_open(key: &[u8], sealed: &[u8], aad: &[u8]) -> Result<Vec<u8>, anyhow::Error> {
+    if sealed.len() < NONCE_LEN {
+        bail!("ciphertext is truncated");
+    }
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+    Aes256Gcm::new(Key::from_slice(key))
+        .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad })
+        .map_err(|_| anyhow!("decryption failed"))
+}
+
+/// Envelope encryption of stored credentials: each value gets a fresh AES-256-GCM data key,
+/// wrapped by the configured `KeyProvider` so the master key can live outside the process.
+/// Ciphertexts are `wrapped key length (u16 BE) || wrapped key || nonce || ciphertext`
+/// and bound to the row they belong to through `aad`.
+#[derive(Clone)]
+pub struct CredentialCipher {
+    provider: Option<Arc<dyn KeyProvider>>,
+}
+
+impl CredentialCipher {
+    /// Without a provider, credentials can't be stored or read.
+    pub fn new(provider: Option<Arc<dyn KeyProvider>>) -> CredentialCipher {
+        CredentialCipher { provider }
+    }
+
+    /// Whether a master key is configured, so values can be encrypted at all.
+    pub fn is_configured(&self) -> bool {
+        self.provider.is_some()
+    }
+
+    fn provider(&self) -> Result<&dyn KeyProvider, anyhow::Error> {
+        self.provider.as_deref().ok_or_else(|| anyhow!("no master key configured, credentials can't be used"))
+    }
+
+    pub async fn encrypt(&self, plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>, anyhow::Error> {
+        let mut data_key = Zeroizing::new([0u8; KEY_LEN]);
+        rand::thread_rng().fill_bytes(data_key.as_mut());
+        let wrapped = self.provider()?.wrap(data_key.as_ref()).await?;
+        let wrapped_len = u16::try_from(wrapped.len()).map_err(|_| anyhow!("wrapped data key is too long"))?;
+        let mut sealed = wrapped_len.to_be_bytes().to_vec();
+        sealed.extend_from_slice(&wrapped);
+        sealed.extend_from_slice(&aes_seal(data_key.as_ref(), plaintext, aad)?);
+        Ok(sealed)
+    }
+
+    pub async fn decrypt(&self, sealed: &[u8], aad: &[u8]) -> Result<Vec<u8>, anyhow::Error> {
+        if sealed.len() < WRAPPED_LEN_LEN {
+            bail!("stored credentials are truncated");
+        }
+        let (wrapped_len, rest) = sealed.split_at(WRAPPED_LEN_LEN);
+        let wrapped_len = u16::from_be_bytes([wrapped_len[0], wrapped_len[1]]) as usize;
+        if rest.len() < wrapped_len {
+            bail!("stored credentials are truncated");
+        }
+        let (wrapped, ciphertext) = rest.split_at(wrapped_len);
+        let data_key = Zeroizing::new(self.provider()?.unwrap(wrapped).await?);
+        if data_key.len() != KEY_LEN {
+            bail!("unwrapped data key must be {} bytes long", KEY_LEN);
+        }
+        aes_open(&data_key, ciphertext, aad).map_err(|_| anyhow!("failed to decrypt credentials"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key_provider::LocalKeyProvider;
+
+    fn local_cipher() -> CredentialCipher {
+        let provider = LocalKeyProvider::new(&base64::encode([7u8; KEY_LEN])).unwrap();
+        CredentialCipher::new(Some(Arc::new(provider)))
+    }
+
+    #[tokio::test]
+    async fn round_trips_through_local_provider() {
+        let cipher = local_cipher();
+        let sealed = cipher.encrypt(b"{\"apiSecret\":\"s\"}", b"acc-1:Kraken").await.unwrap();
+        assert_ne!(&sealed[..], &b"{\"apiSecret\":\"s\"}"[..]);
+        assert_eq!(cipher.decrypt(&sealed, b"acc-1:Kraken").await.unwrap(), b"{\"apiSecret\":\"s\"}");
+    }
+
+    #[tokio::test]
+    async fn uses_fresh_data_key_per_value() {
+        let cipher = local_cipher();
+        let first = cipher.encrypt(b"same", b"aad").await.unwrap();
+        let second = cipher.encrypt(b"same", b"aad").await.unwrap();
+        assert_ne!(first, second);
+    }
+
+    #[tokio::test]
+    async fn rejects_other_row_or_master_key() {
+        let sealed = local_cipher().encrypt(b"secret", b"acc-1:Kraken").await.unwrap();
+        assert!(local_cipher().decrypt(&sealed, b"acc-2:Kraken").await.is_err());
+
+        let other = LocalKeyProvider::new(&base64::encode([8u8; KEY_LEN])).unwrap();
+        let other = CredentialCipher::new(Some(Arc::new(other)));
+        assert!(other.decrypt(&sealed, b"acc-1:Kraken").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn rejects_truncated_values() {
+        let cipher = local_cipher();
+        let sealed = cipher.encrypt(b"secret", b"aad").await.unwrap();
+        assert!(cipher.decrypt(&sealed[..1], b"aad").await.is_err());
+        assert!(cipher.decrypt(&sealed[..sealed.len() - 1], b"aad").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn refuses_without_master_key() {
+        let cipher = CredentialCipher::new(None);
+        assert!(!cipher.is_configured());
+        assert!(cipher.encrypt(b"secret", b"aad").await.is_err());
+    }
+}
@@ -1,59 +1,440 @@
-use crate::dto::{CreateAccountDto, SignAndGetDto, UpdateAccountDto, GetApiKeyDto};
-use opg::*;
-
-pub fn swagger() -> String {
-    let api = describe_api! {
-    info: {
-            title: "My super API",
-            version: "0.0.0",
-    },
-    servers: {
-        "http://127.0.0.1:3030",
-    },
-    paths: {
-            ("account"): {
-                POST: {
-                    summary: "Create account",
-                    body: CreateAccountDto,
-                    200: String,
-                    400: String,
+use std::collections::BTreeMap;
+use opg::{Components, ContextParams, OpgModel};
+use serde_json::{json, Map, Value};
+use warp::http::StatusCode;
+use warp::path::FullPath;
+use warp::reply::Response;
+use warp::{Filter, Rejection, Reply};
+use crate::api::{self, Endpoint, Security};
+use crate::dto::ErrorDto;
+use crate::models::{ExchangeName, KeyType};
+
+const TITLE: &str = "try_api";
+const DESCRIPTION: &str = "Keeps exchange api keys and signing secrets, and signs exchange requests with them.";
+
+/// Schemas named by the document, either derived with `OpgModel` or described here.
+pub struct Schemas {
+    components: Components,
+    described: BTreeMap<&'static str, Value>,
+}
+
+impl Schemas {
+    fn new() -> Schemas {
+        Schemas { components: Components::new(), described: BTreeMap::new() }
+    }
+
+    fn reference(name: &str) -> Value {
+        json!({ "$ref": format!("#/components/schemas/{}", name) })
+    }
+
+    /// A reference to `name`, described by `describe` the first time it is named.
+    fn named(&mut self, name: &'static str, describe: fn(&mut Schemas) -> Value) -> Value {
+        if !self.described.contains_key(name) {
+            // Claims the name before describing, so schemas naming each other terminate.
+            self.described.insert(name, Value::Null);
+            let schema = describe(self);
+            self.described.insert(name, schema);
+        }
+        Schemas::reference(name)
+    }
+
+    fn into_json(self) -> Value {
+        let mut schemas = match serde_json::to_value(&self.components) {
+            Ok(Value::Object(mut components)) => match components.remove("schemas") {
+                Some(Value::Object(schemas)) => schemas,
+                _ => Map::new(),
+            },
+            _ => Map::new(),
+        };
+        for (name, schema) in self.described {
+            schemas.insert(name.to_string(), schema);
+        }
+        Value::Object(schemas)
+    }
+}
+
+/// Schema of a type deriving `OpgModel`.
+pub fn model<T: OpgModel>(schemas: &mut Schemas) -> Value {
+    let reference = schemas.components.mention_schema::<T>(false, &ContextParams::default());
+    serde_json::to_value(reference).expect("opg models serialize to JSON")
+}
+
+pub fn string(_: &mut Schemas) -> Value {
+    json!({ "type": "string" })
+}
+
+pub fn boolean(_: &mut Schemas) -> Value {
+    json!({ "type": "boolean" })
+}
+
+pub fn binary(_: &mut Schemas) -> Value {
+    json!({ "type": "string", "format": "binary" })
+}
+
+pub fn object(_: &mut Schemas) -> Value {
+    json!({ "type": "object" })
+}
+
+pub fn date_time(_: &mut Schemas) -> Value {
+    json!({ "type": "string", "format": "date-time" })
+}
+
+pub fn page_limit(_: &mut Schemas) -> Value {
+    json!({ "type": "integer", "format": "uint32", "minimum": 1 })
+}
+
+pub fn account_sort(_: &mut Schemas) -> Value {
+    json!({ "type": "string", "enum": ["uid", "createdAt", "updatedAt"] })
+}
+
+pub fn sort_order(_: &mut Schemas) -> Value {
+    json!({ "type": "string", "enum": ["asc", "desc"] })
+}
+
+// The DTOs below carry chrono timestamps, which opg can't describe, so their schemas are kept here.
+
+pub fn account_page(schemas: &mut Schemas) -> Value {
+    schemas.named("AccountPageDto", |schemas| json!({
+        "type": "object",
+        "properties": {
+            "items": { "type": "array", "items": schemas.named("AccountSummaryDto", account_summary) },
+            "next_cursor": { "type": "string", "nullable": true },
+        },
+        "required": ["items"],
+    }))
+}
+
+fn account_summary(schemas: &mut Schemas) -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "uid": string(schemas),
+            "exchange": model::<ExchangeName>(schemas),
+            "has_api_key": boolean(schemas),
+            "has_sign_key": boolean(schemas),
+            "created_at": date_time(schemas),
+            "updated_at": date_time(schemas),
+        },
+        "required": ["uid", "exchange", "has_api_key", "has_sign_key", "created_at", "updated_at"],
+    })
+}
+
+pub fn account_details(schemas: &mut Schemas) -> Value {
+    schemas.named("AccountDetailsDto", |schemas| json!({
+        "type": "object",
+        "description": "Account metadata; keys are only identified by fingerprints.",
+        "properties": {
+            "uid": string(schemas),
+            "exchange": model::<ExchangeName>(schemas),
+            "has_api_key": boolean(schemas),
+            "has_sign_key": boolean(schemas),
+            "api_key_fingerprint": nullable(schemas.named("KeyFingerprintDto", key_fingerprint)),
+            "sign_key_fingerprint": nullable(schemas.named("KeyFingerprintDto", key_fingerprint)),
+            "key_type": nullable(model::<KeyType>(schemas)),
+            "has_credentials": boolean(schemas),
+            "created_at": date_time(schemas),
+            "updated_at": date_time(schemas),
+            "version": { "type": "integer", "format": "int64" },
+            "policy": schemas.named("AccountPolicyDto", account_policy),
+        },
+        "required": [
+            "uid", "exchange", "has_api_key", "has_sign_key", "has_credentials", "created_at", "updated_at",
+            "version", "policy",
+        ],
+    }))
+}
+
+fn key_fingerprint(_: &mut Schemas) -> Value {
+    json!({
+        "type": "object",
+        "description": "The last characters of long keys plus a short SHA-256 prefix of the key.",
+        "properties": {
+            "last4": { "type": "string", "nullable": true },
+            "sha256_prefix": { "type": "string", "example": "9f86d081" },
+        },
+        "required": ["sha256_prefix"],
+    })
+}
+
+fn account_policy(schemas: &mut Schemas) -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "can_sign": boolean(schemas),
+            "api_key_retrievable": boolean(schemas),
+        },
+        "required": ["can_sign", "api_key_retrievable"],
+    })
+}
+
+fn nullable(schema: Value) -> Value {
+    json!({ "allOf": [schema], "nullable": true })
+}
+
+fn example(endpoint: &Endpoint, example: &str) -> Value {
+    match serde_json::from_str(example) {
+        Ok(example) => example,
+        Err(err) => panic!("example of {} is not JSON: {}", endpoint.operation_id, err),
+    }
+}
+
+fn parameters(endpoint: &Endpoint, schemas: &mut Schemas) -> Vec<Value> {
+    let mut parameters = Vec::new();
+    for name in endpoint.params() {
+        let schema = match name {
+            "exchange" => model::<ExchangeName>(schemas),
+            _ => string(schemas),
+        };
+        parameters.push(json!({ "name": name, "in": "path", "required": true, "schema": schema }));
+    }
+    for param in endpoint.query {
+        let mut parameter = json!({ "name": param.name, "in": "query", "schema": (param.schema)(schemas) });
+        if !param.description.is_empty() {
+            parameter["description"] = json!(param.description);
+        }
+        parameters.push(parameter);
+    }
+    if endpoint.if_match {
+        parameters.push(json!({
+            "name": "If-Match",
+            "in": "header",
+            "description": "Only apply the change to this account version, taken from an `ETag`.",
+            "schema": string(schemas),
+            "example": "\"3\"",
+        }));
+    }
+    if endpoint.idempotent {
+        parameters.push(json!({
+            "name": "Idempotency-Key",
+            "in": "header",
+            "description": "Repeating a request with the same key replays the first response instead of acting again. \
+                            Reusing a key for a different request is rejected with 422.",
+            "schema": { "type": "string", "maxLength": 255 },
+        }));
+    }
+    parameters
+}
+
+fn operation(endpoint: &Endpoint, schemas: &mut Schemas) -> Value {
+    let mut operation = json!({
+        "operationId": endpoint.operation_id,
+        "tags": [endpoint.tag],
+        "summary": endpoint.summary,
+    });
+    if let Some(description) = endpoint.description {
+        operation["description"] = json!(description);
+    }
+    if endpoint.deprecated {
+        operation["deprecated"] = json!(true);
+    }
+    let parameters = parameters(endpoint, schemas);
+    if !parameters.is_empty() {
+        operation["parameters"] = json!(parameters);
+    }
+    if !endpoint.body.is_empty() {
+        let mut content = Map::new();
+        for body in endpoint.body {
+            let mut media = json!({ "schema": (body.schema)(schemas) });
+            if let Some(body_example) = body.example {
+                media["example"] = example(endpoint, body_example);
+            }
+            content.insert(body.media_type.to_string(), media);
+        }
+        operation["requestBody"] = json!({ "required": true, "content": content });
+    }
+
+    let mut responses = Map::new();
+    for reply in endpoint.replies {
+        let mut response = json!({ "description": reply.description });
+        if let Some(content) = &reply.content {
+            let mut media = Map::new();
+            media.insert(content.media_type.to_string(), json!({ "schema": (content.schema)(schemas) }));
+            response["content"] = Value::Object(media);
+        }
+        if reply.etag {
+            response["headers"] = json!({ "ETag": { "$ref": "#/components/headers/ETag" } });
+        }
+        responses.insert(reply.status.to_string(), response);
+    }
+    match endpoint.security {
+        Security::Public => {
+            operation["security"] = json!([]);
+        }
+        Security::Caller => {
+            responses.insert("429".to_string(), json!({ "$ref": "#/components/responses/RateLimited" }));
+        }
+        Security::StreamToken => {
+            operation["security"] = json!([{ "streamToken": [] }, { "streamTokenQuery": [] }]);
+        }
+    }
+    operation["responses"] = Value::Object(responses);
+    operation
+}
+
+/// The OpenAPI document of `api::ENDPOINTS`, naming `server_url` as the service's address.
+pub fn document(server_url: &str) -> Value {
+    let mut schemas = Schemas::new();
+    let mut paths = Map::new();
+    for endpoint in api::ENDPOINTS {
+        let operation = operation(endpoint, &mut schemas);
+        let path = paths.entry(endpoint.path.to_string()).or_insert_with(|| json!({}));
+        path[endpoint.method.as_str().to_ascii_lowercase()] = operation;
+    }
+    let rate_limited_body = model::<ErrorDto>(&mut schemas);
+
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": TITLE,
+            "description": DESCRIPTION,
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "servers": [{ "url": server_url }],
+        "tags": [
+            { "name": "accounts", "description": "Accounts and their keys" },
+            { "name": "signing", "description": "Signing with the stored keys" },
+            { "name": "events", "description": "Streamed account, order, trade and balance events" },
+            { "name": "docs", "description": "This document" },
+            { "name": "legacy", "description": "Routes preceding `/v1`, served while `LEGACY_ROUTES` is on" },
+        ],
+        "security": [{}, { "bearerAuth": [] }],
+        "paths": paths,
+        "components": {
+            "schemas": schemas.into_json(),
+            "securitySchemes": {
+                "bearerAuth": {
+                    "type": "http",
+                    "scheme": "bearer",
+                    "description": "Optional token of `API_TOKENS`; identifies the caller to the rate limits instead of its address.",
+                },
+                "streamToken": {
+                    "type": "http",
+                    "scheme": "bearer",
+                    "description": "One of `STREAM_TOKENS`.",
                 },
-                PUT: {
-                    summary: "Sign and get key",
-                    body: SignAndGetDto,
-                    200: String,
-                    400: String,
+                "streamTokenQuery": {
+                    "type": "apiKey",
+                    "in": "query",
+                    "name": "token",
+                    "description": "One of `STREAM_TOKENS`, for clients that can't set headers on websockets.",
                 },
-                PATCH: {
-                    summary: "Update account",
-                    body: UpdateAccountDto,
-                    200: String,
-                    400: String,
-                }
             },
-            ("account" / {account_id: String}): {
-                DELETE: {
-                    summary: "Delete account",
-                    200: String,
-                    400: String,
-                }
+            "headers": {
+                "ETag": {
+                    "description": "Account version, to send as `If-Match` with the next change.",
+                    "schema": { "type": "string" },
+                    "example": "\"3\"",
+                },
             },
-            ("key"/ "account" / {account_id: String}): {
-                DELETE: {
-                    summary: "Delete key",
-                    200: String,
-                    400: String,
-                }
+            "responses": {
+                "RateLimited": {
+                    "description": "Rate limit of the caller, route or uid exceeded",
+                    "headers": {
+                        "Retry-After": {
+                            "description": "Seconds until the request can be repeated.",
+                            "schema": { "type": "integer" },
+                        },
+                    },
+                    "content": { "application/json": { "schema": rate_limited_body } },
+                },
             },
-            ("key" / "account"): {
-                PUT: {
-                    summary: "Get api key",
-                    body: GetApiKeyDto,
-                    200: String,
-                    400: String,
-                }
+        },
+    })
+}
+
+pub fn json(document: &Value) -> String {
+    serde_json::to_string_pretty(document).expect("documents serialize to JSON")
+}
+
+pub fn yaml(document: &Value) -> String {
+    serde_yaml::to_string(document).expect("documents serialize to YAML")
+}
+
+/// Swagger UI 5.3.1, vendored in `assets/swagger-ui` and compiled into the binary, browsing
+/// `/openapi.json`. The spec URL in `swagger-initializer.js` is relative to `/docs/` so the page
+/// keeps working behind a proxy serving the API under a path prefix.
+pub fn ui_route() -> impl Filter<Extract=(Response, ), Error=Rejection> + Clone {
+    let index = api::route(&api::DOCS_UI)
+        .and(warp::path::full())
+        .map(|_, path: FullPath| {
+            if path.as_str().ends_with('/') {
+                ui_file("index.html")
+            } else {
+                warp::reply::with_header(StatusCode::MOVED_PERMANENTLY, "Location", "docs/").into_response()
             }
+        });
+    let asset = api::route(&api::DOCS_UI_ASSET)
+        .map(|params: Vec<String>| ui_file(&params[0]));
+    index.or(asset).unify()
+}
+
+/// Files of `assets/swagger-ui` served at `/docs/<name>`, with their content types.
+const UI_FILES: &[(&str, &str, &[u8])] = &[
+    ("index.html", "text/html; charset=utf-8", include_bytes!("../assets/swagger-ui/index.html")),
+    ("index.css", "text/css; charset=utf-8", include_bytes!("../assets/swagger-ui/index.css")),
+    ("swagger-ui.css", "text/css; charset=utf-8", include_bytes!("../assets/swagger-ui/swagger-ui.css")),
+    (
+        "swagger-ui-bundle.js",
+        "application/javascript; charset=utf-8",
+        include_bytes!("../assets/swagger-ui/swagger-ui-bundle.js"),
+    ),
+    (
+        "swagger-ui-standalone-preset.js",
+        "application/javascript; charset=utf-8",
+        include_bytes!("../assets/swagger-ui/swagger-ui-standalone-preset.js"),
+    ),
+    (
+        "swagger-initializer.js",
+        "application/javascript; charset=utf-8",
+        include_bytes!("../assets/swagger-ui/swagger-initializer.js"),
+    ),
+    (
+        "oauth2-redirect.html",
+        "text/html; charset=utf-8",
+        include_bytes!("../assets/swagger-ui/oauth2-redirect.html"),
+    ),
+    ("favicon-16x16.png", "image/png", include_bytes!("../assets/swagger-ui/favicon-16x16.png")),
+    ("favicon-32x32.png", "image/png", include_bytes!("../assets/swagger-ui/favicon-32x32.png")),
+];
+
+fn ui_file(name: &str) -> Response {
+    match UI_FILES.iter().find(|(file, _, _)| *file == name) {
+        Some((_, content_type, bytes)) => {
+            warp::reply::with_header(*bytes, "Content-Type", *content_type).into_response()
         }
-    };
-    serde_yaml::to_string(&api).unwrap()
-}
\ No newline at end of file
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn get(path: &str) -> warp::http::Response<warp::hyper::body::Bytes> {
+        warp::test::request().path(path).reply(&ui_route()).await
+    }
+
+    #[tokio::test]
+    async fn serves_the_vendored_page() {
+        let page = get("/docs/").await;
+        assert_eq!(page.status(), StatusCode::OK);
+        assert_eq!(page.headers()["Content-Type"], "text/html; charset=utf-8");
+
+        let initializer = get("/docs/swagger-initializer.js").await;
+        assert_eq!(initializer.status(), StatusCode::OK);
+        assert!(String::from_utf8_lossy(initializer.body()).contains("\"../openapi.json\""));
+    }
+
+    #[tokio::test]
+    async fn redirects_to_the_trailing_slash() {
+        let redirect = get("/docs").await;
+        assert_eq!(redirect.status(), StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(redirect.headers()["Location"], "docs/");
+    }
+
+    #[tokio::test]
+    async fn unknown_files_are_not_found() {
+        assert_eq!(get("/docs/LICENSE").await.status(), StatusCode::NOT_FOUND);
+        assert_eq!(get("/docs/swagger-ui-bundle.js.map").await.status(), StatusCode::NOT_FOUND);
+    }
+}
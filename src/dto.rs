@@ -1,13 +1,22 @@
-use crate::models::{ExchangeName};
-use serde::{Deserialize, Serialize};
+use crate::models::{ExchangeName, AccountSort, SortOrder, AccountPatch, Patch, ExchangeCredentials, KeyType};
+#[cfg(feature = "server")]
+use crate::db::{AccountSummary, AccountEntity};
+use crate::secret::Secret;
+#[cfg(feature = "server")]
+use crate::exchange_auth::{ExchangeRequest, SignedRequest};
+use serde::{Deserialize, Deserializer, Serialize};
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
 use opg::*;
 
 #[derive(Serialize, Deserialize, Debug, OpgModel)]
 pub struct CreateAccountDto {
     pub uid: String,
     pub exchange: ExchangeName,
-    pub api_key: String,
-    pub sign_key: Option<String>,
+    pub api_key: Secret,
+    /// Shared secret, or a PEM Ed25519/RSA private key for exchanges supporting them.
+    pub sign_key: Option<Secret>,
+    pub credentials: Option<ExchangeCredentials>,
 }
 
 #[derive(Serialize, Deserialize, Debug, OpgModel)]
@@ -21,8 +30,9 @@ pub struct SignAndGetDto {
 pub struct UpdateAccountDto {
     pub uid: String,
     pub exchange: ExchangeName,
-    pub api_key: Option<String>,
-    pub sign_key: Option<String>,
+    pub api_key: Option<Secret>,
+    pub sign_key: Option<Secret>,
+    pub credentials: Option<ExchangeCredentials>,
 }
 
 #[derive(Serialize, Deserialize, Debug, OpgModel)]
@@ -30,3 +40,319 @@ pub struct GetApiKeyDto {
     pub uid: String,
     pub exchange: ExchangeName,
 }
+
+impl From<UpdateAccountDto> for AccountPatch {
+    fn from(dto: UpdateAccountDto) -> Self {
+        AccountPatch {
+            api_key: dto.api_key.map_or(Patch::Leave, Patch::Set),
+            sign_key: dto.sign_key.map_or(Patch::Leave, Patch::Set),
+            credentials: dto.credentials.map_or(Patch::Leave, Patch::Set),
+        }
+    }
+}
+
+/// Distinguishes a missing field (`None`) from an explicit `null` (`Some(None)`).
+fn nullable<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+    where
+        D: Deserializer<'de>,
+        T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+/// Omitted fields are left unchanged, `null` clears the stored value.
+#[derive(Serialize, Deserialize, Debug, OpgModel)]
+pub struct AccountChangesDto {
+    #[serde(default, deserialize_with = "nullable", skip_serializing_if = "Option::is_none")]
+    pub api_key: Option<Option<Secret>>,
+    #[serde(default, deserialize_with = "nullable", skip_serializing_if = "Option::is_none")]
+    pub sign_key: Option<Option<Secret>>,
+    #[serde(default, deserialize_with = "nullable", skip_serializing_if = "Option::is_none")]
+    pub credentials: Option<Option<ExchangeCredentials>>,
+}
+
+impl From<AccountChangesDto> for AccountPatch {
+    fn from(dto: AccountChangesDto) -> Self {
+        AccountPatch {
+            api_key: Patch::from(dto.api_key),
+            sign_key: Patch::from(dto.sign_key),
+            credentials: Patch::from(dto.credentials),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, Serialize, Deserialize, PartialEq, OpgModel)]
+#[serde(rename_all = "lowercase")]
+pub enum PayloadEncoding {
+    #[default]
+    Utf8,
+    Base64,
+    Hex,
+}
+
+#[derive(Serialize, Deserialize, Debug, OpgModel)]
+pub struct SignatureRequestDto {
+    pub data_to_sign: String,
+    #[serde(default)]
+    pub encoding: PayloadEncoding,
+}
+
+impl SignatureRequestDto {
+    pub fn decode(&self) -> Result<Vec<u8>, String> {
+        match self.encoding {
+            PayloadEncoding::Utf8 => Ok(self.data_to_sign.as_bytes().to_vec()),
+            PayloadEncoding::Base64 => base64::decode(&self.data_to_sign)
+                .map_err(|_| "is not valid base64".to_string()),
+            PayloadEncoding::Hex => hex::decode(&self.data_to_sign)
+                .map_err(|_| "is not valid hex".to_string()),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, OpgModel)]
+pub struct SignatureDto {
+    pub uid: String,
+    pub api_key: Secret,
+    pub nonce: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, OpgModel)]
+pub struct ApiKeyDto {
+    pub api_key: Secret,
+}
+
+#[derive(Serialize, Deserialize, Debug, OpgModel)]
+pub struct AccountRefDto {
+    pub uid: String,
+    pub exchange: ExchangeName,
+}
+
+/// The stream position of a published event, for resuming from just before it.
+#[derive(Serialize, Deserialize, Debug, OpgModel)]
+pub struct PublishedEventDto {
+    pub seq: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, OpgModel)]
+pub struct ErrorDto {
+    pub error: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct ListAccountsQuery {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exchange: Option<ExchangeName>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub has_api_key: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub has_sign_key: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_after: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_before: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub updated_after: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub updated_before: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uid_prefix: Option<String>,
+    #[serde(default)]
+    pub sort: AccountSort,
+    #[serde(default)]
+    pub order: SortOrder,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cursor: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AccountSummaryDto {
+    pub uid: String,
+    pub exchange: ExchangeName,
+    pub has_api_key: bool,
+    pub has_sign_key: bool,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[cfg(feature = "server")]
+impl From<AccountSummary> for AccountSummaryDto {
+    fn from(summary: AccountSummary) -> Self {
+        AccountSummaryDto {
+            uid: summary.uid,
+            exchange: summary.exchange,
+            has_api_key: summary.has_api_key,
+            has_sign_key: summary.has_sign_key,
+            created_at: summary.created_at,
+            updated_at: summary.updated_at,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AccountPageDto {
+    pub items: Vec<AccountSummaryDto>,
+    pub next_cursor: Option<String>,
+}
+
+/// Identifies a stored key without revealing it: the last characters of long keys
+/// plus a short SHA-256 prefix that can be compared against a locally computed hash.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct KeyFingerprintDto {
+    pub last4: Option<String>,
+    pub sha256_prefix: String,
+}
+
+impl KeyFingerprintDto {
+    const REVEAL_MIN_LEN: usize = 16;
+    const SHA256_PREFIX_LEN: usize = 8;
+
+    pub fn of(key: &str) -> KeyFingerprintDto {
+        let chars: Vec<char> = key.chars().collect();
+        let last4 = if chars.len() >= Self::REVEAL_MIN_LEN {
+            Some(chars[chars.len() - 4..].iter().collect())
+        } else {
+            None
+        };
+        let mut sha256_prefix = hex::encode(Sha256::digest(key.as_bytes()));
+        sha256_prefix.truncate(Self::SHA256_PREFIX_LEN);
+        KeyFingerprintDto { last4, sha256_prefix }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AccountPolicyDto {
+    pub can_sign: bool,
+    pub api_key_retrievable: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AccountDetailsDto {
+    pub uid: String,
+    pub exchange: ExchangeName,
+    pub has_api_key: bool,
+    pub has_sign_key: bool,
+    pub api_key_fingerprint: Option<KeyFingerprintDto>,
+    pub sign_key_fingerprint: Option<KeyFingerprintDto>,
+    pub key_type: Option<KeyType>,
+    pub has_credentials: bool,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub version: i64,
+    pub policy: AccountPolicyDto,
+}
+
+#[cfg(feature = "server")]
+impl From<AccountEntity> for AccountDetailsDto {
+    fn from(account: AccountEntity) -> Self {
+        let key_type = match account.sign_key {
+            Some(_) => Some(KeyType::Hmac),
+            None => account.key_type,
+        };
+        AccountDetailsDto {
+            has_api_key: account.api_key.is_some(),
+            has_sign_key: key_type.is_some(),
+            api_key_fingerprint: account.api_key.as_ref().map(|key| KeyFingerprintDto::of(key.expose())),
+            sign_key_fingerprint: account.sign_key.as_ref().map(|key| KeyFingerprintDto::of(key.expose())),
+            key_type,
+            has_credentials: account.credentials.is_some(),
+            policy: AccountPolicyDto {
+                can_sign: key_type.is_some(),
+                api_key_retrievable: account.api_key.is_some(),
+            },
+            uid: account.uid,
+            exchange: account.exchange,
+            created_at: account.created_at,
+            updated_at: account.updated_at,
+            version: account.version,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, OpgModel)]
+pub struct FieldErrorDto {
+    pub field: String,
+    pub message: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, OpgModel)]
+pub struct ValidationErrorDto {
+    pub error: String,
+    pub errors: Vec<FieldErrorDto>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, OpgModel)]
+pub struct KeyValueDto {
+    pub name: String,
+    pub value: String,
+}
+
+#[cfg(feature = "server")]
+fn to_pairs(pairs: Vec<KeyValueDto>) -> Vec<(String, String)> {
+    pairs.into_iter().map(|pair| (pair.name, pair.value)).collect()
+}
+
+#[cfg(feature = "server")]
+fn from_pairs(pairs: Vec<(String, String)>) -> Vec<KeyValueDto> {
+    pairs.into_iter().map(|(name, value)| KeyValueDto { name, value }).collect()
+}
+
+/// A private exchange API call to authenticate with the account's stored credentials.
+#[derive(Serialize, Deserialize, Debug, OpgModel)]
+pub struct SignRequestDto {
+    pub method: String,
+    pub path: String,
+    #[serde(default)]
+    pub query: Vec<KeyValueDto>,
+    pub body: Option<String>,
+    /// Only used by exchanges that sign the host name (Huobi).
+    pub host: Option<String>,
+}
+
+#[cfg(feature = "server")]
+impl From<SignRequestDto> for ExchangeRequest {
+    fn from(dto: SignRequestDto) -> Self {
+        ExchangeRequest {
+            method: dto.method,
+            path: dto.path,
+            query: to_pairs(dto.query),
+            body: dto.body,
+            host: dto.host,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, OpgModel)]
+pub struct KeyPairRequestDto {
+    pub key_type: KeyType,
+}
+
+/// The public half of a generated key pair, as SubjectPublicKeyInfo PEM.
+#[derive(Serialize, Deserialize, Debug, OpgModel)]
+pub struct PublicKeyDto {
+    pub uid: String,
+    pub exchange: ExchangeName,
+    pub key_type: KeyType,
+    pub public_key: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, OpgModel)]
+pub struct SignedRequestDto {
+    pub headers: Vec<KeyValueDto>,
+    pub query: Vec<KeyValueDto>,
+    pub query_string: String,
+    pub body: Option<String>,
+}
+
+#[cfg(feature = "server")]
+impl From<SignedRequest> for SignedRequestDto {
+    fn from(signed: SignedRequest) -> Self {
+        SignedRequestDto {
+            headers: from_pairs(signed.headers),
+            query: from_pairs(signed.query),
+            query_string: signed.query_string,
+            body: signed.body,
+        }
+    }
+}
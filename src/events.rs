@@ -0,0 +1,337 @@
+use anyhow::{anyhow, bail};
+use chrono::{DateTime, Duration, Utc};
+use hmac::{Hmac, Mac, NewMac};
+use serde::Serialize;
+use serde_json::json;
+use sha2::Sha256;
+use sqlx::{Executor, Pool, Postgres};
+use std::fmt;
+use std::str::FromStr;
+use crate::config::WebhookConfig;
+use crate::models::ExchangeName;
+use crate::secret::Secret;
+
+pub const EVENT_ID_HEADER: &str = "X-Try-Api-Event-Id";
+pub const TIMESTAMP_HEADER: &str = "X-Try-Api-Timestamp";
+pub const SIGNATURE_HEADER: &str = "X-Try-Api-Signature";
+
+const BATCH_SIZE: i64 = 100;
+/// Claimed events become due again after this long, in case the dispatcher dies mid-delivery.
+const LEASE_SECS: i64 = 60;
+const MAX_BACKOFF_SECS: i64 = 60 * 60;
+const DELIVERED_RETENTION_DAYS: i64 = 7;
+const REQUEST_TIMEOUT_SECS: u64 = 10;
+
+/// What happened to an account, named in webhook payloads as e.g. `key.rotated`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum EventKind {
+    AccountCreated,
+    AccountDeleted,
+    AccountRestored,
+    KeyAdded,
+    KeyRotated,
+    KeyRemoved,
+    SigningFailed,
+}
+
+impl fmt::Display for EventKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EventKind::AccountCreated => write!(f, "account.created"),
+            EventKind::AccountDeleted => write!(f, "account.deleted"),
+            EventKind::AccountRestored => write!(f, "account.restored"),
+            EventKind::KeyAdded => write!(f, "key.added"),
+            EventKind::KeyRotated => write!(f, "key.rotated"),
+            EventKind::KeyRemoved => write!(f, "key.removed"),
+            EventKind::SigningFailed => write!(f, "signing.failed"),
+        }
+    }
+}
+
+impl FromStr for EventKind {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "account.created" => Ok(EventKind::AccountCreated),
+            "account.deleted" => Ok(EventKind::AccountDeleted),
+            "account.restored" => Ok(EventKind::AccountRestored),
+            "key.added" => Ok(EventKind::KeyAdded),
+            "key.rotated" => Ok(EventKind::KeyRotated),
+            "key.removed" => Ok(EventKind::KeyRemoved),
+            "signing.failed" => Ok(EventKind::SigningFailed),
+            _ => Err(anyhow!("unknown event kind \"{}\"", value)),
+        }
+    }
+}
+
+/// An account lifecycle event, written to the outbox with the change it describes.
+/// `data` describes the change and never holds secret values.
+#[derive(Clone, Debug)]
+pub struct AccountEvent {
+    pub kind: EventKind,
+    pub uid: String,
+    pub exchange: Option<ExchangeName>,
+    pub data: serde_json::Value,
+}
+
+impl AccountEvent {
+    pub fn new(kind: EventKind, uid: &str, exchange: Option<&ExchangeName>) -> AccountEvent {
+        AccountEvent { kind, uid: uid.to_string(), exchange: exchange.cloned(), data: json!({}) }
+    }
+
+    pub fn with(mut self, name: &str, value: impl Serialize) -> AccountEvent {
+        self.data[name] = json!(value);
+        self
+    }
+
+    /// The JSON form sent to webhooks and websocket subscribers, without the delivery metadata.
+    pub fn payload(&self) -> serde_json::Value {
+        json!({
+            "type": self.kind.to_string(),
+            "uid": self.uid,
+            "exchange": self.exchange,
+            "data": self.data,
+        })
+    }
+
+    /// The event for a key slot going from `had` a key to `has` one after a write that touched it.
+    pub fn key_change(uid: &str, exchange: &ExchangeName, key: &str, had: bool, has: bool) -> Option<AccountEvent> {
+        let kind = match (had, has) {
+            (false, true) => EventKind::KeyAdded,
+            (true, true) => EventKind::KeyRotated,
+            (true, false) => EventKind::KeyRemoved,
+            (false, false) => return None,
+        };
+        Some(AccountEvent::new(kind, uid, Some(exchange)).with("key", key))
+    }
+}
+
+pub async fn record<'e, E: Executor<'e, Database = Postgres>>(
+    executor: E,
+    event: &AccountEvent,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"INSERT INTO test.public.account_events (kind, uid, exchange, data)
+         VALUES ($1, $2, $3, $4);"#,
+        event.kind.to_string(),
+        event.uid,
+        event.exchange.as_ref().map(|exchange| exchange.to_string()),
+        event.data.to_string(),
+    )
+        .execute(executor)
+        .await?;
+    Ok(())
+}
+
+/// Deletes events older than the delivered ones are kept for, whether delivered or not.
+pub async fn prune_expired(pg_pool: &Pool<Postgres>) -> Result<u64, anyhow::Error> {
+    let result = sqlx::query!(
+        r#"DELETE FROM test.public.account_events
+         WHERE created_at < $1;"#,
+        Utc::now() - Duration::days(DELIVERED_RETENTION_DAYS),
+    )
+        .execute(pg_pool)
+        .await?;
+    Ok(result.rows_affected())
+}
+
+/// HMAC-SHA256 of `<timestamp>.<body>`, hex encoded, sent as `sha256=<signature>`.
+pub fn signature(secret: &[u8], timestamp: i64, body: &[u8]) -> String {
+    hex::encode(webhook_mac(secret, timestamp, body).finalize().into_bytes())
+}
+
+/// Checks a `X-Try-Api-Signature` header value in constant time.
+pub fn verify_signature(secret: &[u8], timestamp: i64, body: &[u8], header: &str) -> bool {
+    match header.strip_prefix("sha256=").and_then(|signature| hex::decode(signature).ok()) {
+        Some(signature) => webhook_mac(secret, timestamp, body).verify(&signature).is_ok(),
+        None => false,
+    }
+}
+
+fn webhook_mac(secret: &[u8], timestamp: i64, body: &[u8]) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC takes keys of any length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    mac
+}
+
+#[derive(Serialize)]
+struct WebhookPayload<'a> {
+    id: i64,
+    #[serde(rename = "type")]
+    kind: &'a str,
+    uid: &'a str,
+    exchange: Option<&'a str>,
+    occurred_at: DateTime<Utc>,
+    data: serde_json::Value,
+}
+
+struct PendingEvent {
+    id: i64,
+    kind: String,
+    uid: String,
+    exchange: Option<String>,
+    data: String,
+    created_at: DateTime<Utc>,
+    attempts: i32,
+    pending_urls: Option<Vec<String>>,
+}
+
+/// Delivers outbox events to the configured webhook URLs. Each URL gets each event at least once;
+/// failed URLs are retried with exponential backoff, and receivers deduplicate by event id.
+/// Retries can reorder events, so receivers needing order should sort by id.
+pub struct WebhookDispatcher {
+    pg_pool: Pool<Postgres>,
+    client: reqwest::Client,
+    urls: Vec<String>,
+    secret: Secret,
+    max_attempts: i32,
+    backoff: Duration,
+}
+
+impl WebhookDispatcher {
+    pub fn new(pg_pool: Pool<Postgres>, config: &WebhookConfig) -> Result<WebhookDispatcher, anyhow::Error> {
+        let client = reqwest::Client::builder()
+            .timeout(std::time::Duration::from_secs(REQUEST_TIMEOUT_SECS))
+            .build()?;
+        Ok(WebhookDispatcher {
+            pg_pool,
+            client,
+            urls: config.urls.clone(),
+            secret: config.secret.clone(),
+            max_attempts: config.max_attempts,
+            backoff: Duration::seconds(config.backoff_secs),
+        })
+    }
+
+    /// Polls the outbox every `interval` for as long as the process lives.
+    pub fn spawn(self, interval: std::time::Duration) {
+        tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(interval);
+            loop {
+                ticks.tick().await;
+                if let Err(err) = self.dispatch_pending().await {
+                    log::error!("dispatching account events failed: {}", err);
+                }
+            }
+        });
+    }
+
+    /// Delivers the events that are due and returns how many were claimed.
+    pub async fn dispatch_pending(&self) -> Result<usize, anyhow::Error> {
+        sqlx::query!(
+        r#"DELETE FROM test.public.account_events
+         WHERE delivered_at < $1;"#,
+        Utc::now() - Duration::days(DELIVERED_RETENTION_DAYS),
+    )
+            .execute(&self.pg_pool)
+            .await?;
+
+        let events = sqlx::query_as!(
+            PendingEvent,
+            r#"UPDATE test.public.account_events SET next_attempt_at = $1
+         WHERE id IN (
+             SELECT id FROM test.public.account_events
+             WHERE delivered_at IS NULL AND failed_at IS NULL AND next_attempt_at <= now()
+             ORDER BY id
+             LIMIT $2
+             FOR UPDATE SKIP LOCKED
+         )
+         RETURNING id, kind, uid, exchange, data, created_at, attempts, pending_urls;"#,
+            Utc::now() + Duration::seconds(LEASE_SECS),
+            BATCH_SIZE,
+        )
+            .fetch_all(&self.pg_pool)
+            .await?;
+
+        for event in &events {
+            self.dispatch(event).await?;
+        }
+        Ok(events.len())
+    }
+
+    async fn dispatch(&self, event: &PendingEvent) -> Result<(), anyhow::Error> {
+        let body = serde_json::to_vec(&WebhookPayload {
+            id: event.id,
+            kind: &event.kind,
+            uid: &event.uid,
+            exchange: event.exchange.as_deref(),
+            occurred_at: event.created_at,
+            data: serde_json::from_str(&event.data)?,
+        })?;
+        let urls = event.pending_urls.as_ref().unwrap_or(&self.urls);
+        let mut failed = Vec::new();
+        let mut last_error = None;
+        for url in urls {
+            if let Err(err) = self.deliver(event.id, url, &body).await {
+                failed.push(url.clone());
+                last_error = Some(format!("{}: {}", url, err));
+            }
+        }
+
+        let attempts = event.attempts + 1;
+        if failed.is_empty() {
+            sqlx::query!(
+            r#"UPDATE test.public.account_events
+             SET delivered_at = now(), attempts = $1, pending_urls = NULL, last_error = NULL
+             WHERE id = $2;"#,
+            attempts,
+            event.id,
+        )
+                .execute(&self.pg_pool)
+                .await?;
+        } else if attempts >= self.max_attempts {
+            log::error!("giving up on account event {} after {} attempts: {:?}", event.id, attempts, last_error);
+            sqlx::query!(
+            r#"UPDATE test.public.account_events
+             SET failed_at = now(), attempts = $1, pending_urls = $2, last_error = $3
+             WHERE id = $4;"#,
+            attempts,
+            &failed,
+            last_error,
+            event.id,
+        )
+                .execute(&self.pg_pool)
+                .await?;
+        } else {
+            sqlx::query!(
+            r#"UPDATE test.public.account_events
+             SET next_attempt_at = $1, attempts = $2, pending_urls = $3, last_error = $4
+             WHERE id = $5;"#,
+            Utc::now() + self.backoff_after(attempts),
+            attempts,
+            &failed,
+            last_error,
+            event.id,
+        )
+                .execute(&self.pg_pool)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// `backoff`, doubled for each attempt after the first, up to an hour.
+    fn backoff_after(&self, attempts: i32) -> Duration {
+        let factor = 1i64 << (attempts - 1).clamp(0, 20);
+        Duration::seconds((self.backoff.num_seconds() * factor).min(MAX_BACKOFF_SECS))
+    }
+
+    async fn deliver(&self, id: i64, url: &str, body: &[u8]) -> Result<(), anyhow::Error> {
+        let timestamp = Utc::now().timestamp();
+        let response = self.client
+            .post(url)
+            .header("Content-Type", "application/json")
+            .header(EVENT_ID_HEADER, id.to_string())
+            .header(TIMESTAMP_HEADER, timestamp.to_string())
+            .header(SIGNATURE_HEADER, format!("sha256={}", signature(self.secret.expose().as_bytes(), timestamp, body)))
+            .body(body.to_vec())
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            bail!("receiver answered with status {}", response.status());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,666 @@
+use anyhow::{anyhow, bail};
+use chrono::{DateTime, SecondsFormat, Utc};
+use hmac::{Hmac, Mac, NewMac};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use sha2::{Digest, Sha256, Sha384, Sha512};
+use crate::db::AccountCredentials;
+use crate::models::{ExchangeCredentials, ExchangeName};
+
+const HUOBI_DEFAULT_HOST: &str = "api.huobi.pro";
+
+/// Everything but RFC 3986 unreserved characters, so spaces become `%20` rather than `+`.
+const RFC3986: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'.').remove(b'_').remove(b'~');
+
+/// A private exchange API call as the bot is about to send it.
+#[derive(Clone, Debug)]
+pub struct ExchangeRequest {
+    pub method: String,
+    pub path: String,
+    pub query: Vec<(String, String)>,
+    pub body: Option<String>,
+    pub host: Option<String>,
+}
+
+/// Everything that has to be added to an `ExchangeRequest` to authenticate it.
+/// `query` is the complete query in send order, `query_string` its exact encoding that was signed.
+#[derive(Clone, Debug, Default)]
+pub struct SignedRequest {
+    pub headers: Vec<(String, String)>,
+    pub query: Vec<(String, String)>,
+    pub query_string: String,
+    pub body: Option<String>,
+}
+
+fn sign_hmac<M: Mac + NewMac>(key: &[u8], parts: &[&[u8]]) -> Result<Vec<u8>, anyhow::Error> {
+    let mut mac = M::new_from_slice(key).map_err(|_| anyhow!("invalid sign_key length"))?;
+    for part in parts {
+        mac.update(part);
+    }
+    Ok(mac.finalize().into_bytes().to_vec())
+}
+
+fn encode_query(query: &[(String, String)]) -> Result<String, anyhow::Error> {
+    Ok(serde_urlencoded::to_string(query)?)
+}
+
+/// Query encoding of Huobi's signature version 2, which signs the RFC 3986 form.
+fn encode_query_rfc3986(query: &[(String, String)]) -> String {
+    query
+        .iter()
+        .map(|(name, value)| format!("{}={}", utf8_percent_encode(name, RFC3986), utf8_percent_encode(value, RFC3986)))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+fn with_query(path: &str, query_string: &str) -> String {
+    if query_string.is_empty() {
+        path.to_string()
+    } else {
+        format!("{}?{}", path, query_string)
+    }
+}
+
+/// Builds the authentication of `request` for `exchange` from the stored credentials.
+/// `nonce` comes from the account's nonce counter and is used by exchanges that require one.
+pub fn sign_request(
+    exchange: &ExchangeName,
+    credentials: &AccountCredentials,
+    request: ExchangeRequest,
+    now: DateTime<Utc>,
+    nonce: u64,
+) -> Result<SignedRequest, anyhow::Error> {
+    let method = request.method.to_ascii_uppercase();
+    match exchange {
+        ExchangeName::Binance => sign_binance(credentials, request, now),
+        ExchangeName::HitBtc => sign_hitbtc(credentials, &method, request, now),
+        ExchangeName::Kraken => sign_kraken(credentials, request, nonce),
+        ExchangeName::Okex => sign_okex(credentials, &method, request, now),
+        ExchangeName::Kucoin => sign_kucoin(credentials, &method, request, now),
+        ExchangeName::Bitfinex => sign_bitfinex(credentials, request, nonce),
+        ExchangeName::Huobi => sign_huobi(credentials, &method, request, now),
+        ExchangeName::Quoine => sign_quoine(credentials, request, nonce),
+    }
+}
+
+/// `timestamp` and a `signature` over query string and body: hex HMAC-SHA256 for secret keys,
+/// base64 for Ed25519 and RSA keys.
+fn sign_binance(
+    credentials: &AccountCredentials,
+    request: ExchangeRequest,
+    now: DateTime<Utc>,
+) -> Result<SignedRequest, anyhow::Error> {
+    let mut query = request.query;
+    query.push(("timestamp".to_string(), now.timestamp_millis().to_string()));
+    let query_string = encode_query(&query)?;
+    let body = request.body.unwrap_or_default();
+    let signature = match &credentials.signing_key {
+        Some(signing_key) => base64::encode(signing_key.sign(format!("{}{}", query_string, body).as_bytes())?),
+        None => hex::encode(sign_hmac::<Hmac<Sha256>>(
+            credentials.secret()?.as_bytes(),
+            &[query_string.as_bytes(), body.as_bytes()],
+        )?),
+    };
+    query.push(("signature".to_string(), signature));
+
+    Ok(SignedRequest {
+        headers: vec![("X-MBX-APIKEY".to_string(), credentials.api_key.expose().to_string())],
+        query_string: encode_query(&query)?,
+        query,
+        body: Some(body).filter(|body| !body.is_empty()),
+    })
+}
+
+/// HS256 `Authorization` header carrying the api key, signature and timestamp.
+fn sign_hitbtc(
+    credentials: &AccountCredentials,
+    method: &str,
+    request: ExchangeRequest,
+    now: DateTime<Utc>,
+) -> Result<SignedRequest, anyhow::Error> {
+    let query_string = encode_query(&request.query)?;
+    let body = request.body.unwrap_or_default();
+    let timestamp = now.timestamp_millis().to_string();
+    let signature = hex::encode(sign_hmac::<Hmac<Sha256>>(
+        credentials.secret()?.as_bytes(),
+        &[
+            method.as_bytes(),
+            with_query(&request.path, &query_string).as_bytes(),
+            body.as_bytes(),
+            timestamp.as_bytes(),
+        ],
+    )?);
+    let token = base64::encode(format!("{}:{}:{}", credentials.api_key.expose(), signature, timestamp));
+
+    Ok(SignedRequest {
+        headers: vec![("Authorization".to_string(), format!("HS256 {}", token))],
+        query: request.query,
+        query_string,
+        body: Some(body).filter(|body| !body.is_empty()),
+    })
+}
+
+/// `API-Sign` over the path and the SHA-256 of nonce and form body, keyed by the decoded secret.
+fn sign_kraken(
+    credentials: &AccountCredentials,
+    request: ExchangeRequest,
+    nonce: u64,
+) -> Result<SignedRequest, anyhow::Error> {
+    let body = match request.body.as_deref() {
+        None | Some("") => format!("nonce={}", nonce),
+        Some(body) => format!("nonce={}&{}", nonce, body),
+    };
+    let secret = base64::decode(credentials.secret()?)
+        .map_err(|_| anyhow!("Kraken sign_key must be base64"))?;
+    let digest = Sha256::new()
+        .chain(nonce.to_string().as_bytes())
+        .chain(body.as_bytes())
+        .finalize();
+    let signature = base64::encode(sign_hmac::<Hmac<Sha512>>(&secret, &[request.path.as_bytes(), &digest])?);
+
+    Ok(SignedRequest {
+        headers: vec![
+            ("API-Key".to_string(), credentials.api_key.expose().to_string()),
+            ("API-Sign".to_string(), signature),
+        ],
+        query_string: encode_query(&request.query)?,
+        query: request.query,
+        body: Some(body),
+    })
+}
+
+fn sign_okex(
+    credentials: &AccountCredentials,
+    method: &str,
+    request: ExchangeRequest,
+    now: DateTime<Utc>,
+) -> Result<SignedRequest, anyhow::Error> {
+    let passphrase = match credentials.passphrase() {
+        Some(passphrase) => passphrase,
+        None => bail!("Okex requests need a passphrase, which is not stored for this account"),
+    };
+    let query_string = encode_query(&request.query)?;
+    let body = request.body.unwrap_or_default();
+    let timestamp = now.to_rfc3339_opts(SecondsFormat::Millis, true);
+    let signature = base64::encode(sign_hmac::<Hmac<Sha256>>(
+        credentials.secret()?.as_bytes(),
+        &[
+            timestamp.as_bytes(),
+            method.as_bytes(),
+            with_query(&request.path, &query_string).as_bytes(),
+            body.as_bytes(),
+        ],
+    )?);
+
+    Ok(SignedRequest {
+        headers: vec![
+            ("OK-ACCESS-KEY".to_string(), credentials.api_key.expose().to_string()),
+            ("OK-ACCESS-SIGN".to_string(), signature),
+            ("OK-ACCESS-TIMESTAMP".to_string(), timestamp),
+            ("OK-ACCESS-PASSPHRASE".to_string(), passphrase.to_string()),
+        ],
+        query: request.query,
+        query_string,
+        body: Some(body).filter(|body| !body.is_empty()),
+    })
+}
+
+/// Key version 2 sends the passphrase signed with the secret, version 1 sends it as is.
+fn sign_kucoin(
+    credentials: &AccountCredentials,
+    method: &str,
+    request: ExchangeRequest,
+    now: DateTime<Utc>,
+) -> Result<SignedRequest, anyhow::Error> {
+    let (passphrase, key_version) = match &credentials.extra {
+        Some(ExchangeCredentials::Kucoin { passphrase, key_version }) => (passphrase, *key_version),
+        _ => bail!("Kucoin requests need a passphrase, which is not stored for this account"),
+    };
+    let query_string = encode_query(&request.query)?;
+    let body = request.body.unwrap_or_default();
+    let timestamp = now.timestamp_millis().to_string();
+    let secret = credentials.secret()?.as_bytes();
+    let signature = base64::encode(sign_hmac::<Hmac<Sha256>>(
+        secret,
+        &[
+            timestamp.as_bytes(),
+            method.as_bytes(),
+            with_query(&request.path, &query_string).as_bytes(),
+            body.as_bytes(),
+        ],
+    )?);
+    let passphrase = if key_version >= 2 {
+        base64::encode(sign_hmac::<Hmac<Sha256>>(secret, &[passphrase.expose().as_bytes()])?)
+    } else {
+        passphrase.expose().to_string()
+    };
+
+    Ok(SignedRequest {
+        headers: vec![
+            ("KC-API-KEY".to_string(), credentials.api_key.expose().to_string()),
+            ("KC-API-SIGN".to_string(), signature),
+            ("KC-API-TIMESTAMP".to_string(), timestamp),
+            ("KC-API-PASSPHRASE".to_string(), passphrase),
+            ("KC-API-KEY-VERSION".to_string(), key_version.to_string()),
+        ],
+        query: request.query,
+        query_string,
+        body: Some(body).filter(|body| !body.is_empty()),
+    })
+}
+
+/// v2 `bfx-*` headers, signed over `/api/<path><nonce><body>` with HMAC-SHA384.
+fn sign_bitfinex(
+    credentials: &AccountCredentials,
+    request: ExchangeRequest,
+    nonce: u64,
+) -> Result<SignedRequest, anyhow::Error> {
+    let body = request.body.unwrap_or_default();
+    let nonce = nonce.to_string();
+    let signature = hex::encode(sign_hmac::<Hmac<Sha384>>(
+        credentials.secret()?.as_bytes(),
+        &[
+            b"/api/",
+            request.path.trim_start_matches('/').as_bytes(),
+            nonce.as_bytes(),
+            body.as_bytes(),
+        ],
+    )?);
+
+    Ok(SignedRequest {
+        headers: vec![
+            ("bfx-nonce".to_string(), nonce),
+            ("bfx-apikey".to_string(), credentials.api_key.expose().to_string()),
+            ("bfx-signature".to_string(), signature),
+        ],
+        query_string: encode_query(&request.query)?,
+        query: request.query,
+        body: Some(body).filter(|body| !body.is_empty()),
+    })
+}
+
+/// Signature version 2: auth parameters are merged into the sorted query and signed with the host.
+fn sign_huobi(
+    credentials: &AccountCredentials,
+    method: &str,
+    request: ExchangeRequest,
+    now: DateTime<Utc>,
+) -> Result<SignedRequest, anyhow::Error> {
+    let host = request.host.as_deref().unwrap_or(HUOBI_DEFAULT_HOST).to_ascii_lowercase();
+    let mut query = request.query;
+    query.push(("AccessKeyId".to_string(), credentials.api_key.expose().to_string()));
+    query.push(("SignatureMethod".to_string(), "HmacSHA256".to_string()));
+    query.push(("SignatureVersion".to_string(), "2".to_string()));
+    query.push(("Timestamp".to_string(), now.format("%Y-%m-%dT%H:%M:%S").to_string()));
+    query.sort();
+    let query_string = encode_query_rfc3986(&query);
+    let payload = format!("{}\n{}\n{}\n{}", method, host, request.path, query_string);
+    let signature = base64::encode(sign_hmac::<Hmac<Sha256>>(
+        credentials.secret()?.as_bytes(),
+        &[payload.as_bytes()],
+    )?);
+    query.push(("Signature".to_string(), signature));
+
+    Ok(SignedRequest {
+        headers: Vec::new(),
+        query_string: encode_query_rfc3986(&query),
+        query,
+        body: request.body,
+    })
+}
+
+/// `X-Quoine-Auth` JWT (HS256) over path, nonce and token id.
+fn sign_quoine(
+    credentials: &AccountCredentials,
+    request: ExchangeRequest,
+    nonce: u64,
+) -> Result<SignedRequest, anyhow::Error> {
+    let query_string = encode_query(&request.query)?;
+    let header = serde_json::json!({ "typ": "JWT", "alg": "HS256" });
+    let claims = serde_json::json!({
+        "path": with_query(&request.path, &query_string),
+        "nonce": nonce,
+        "token_id": credentials.api_key.expose(),
+    });
+    let signing_input = format!(
+        "{}.{}",
+        base64::encode_config(serde_json::to_vec(&header)?, base64::URL_SAFE_NO_PAD),
+        base64::encode_config(serde_json::to_vec(&claims)?, base64::URL_SAFE_NO_PAD),
+    );
+    let signature = sign_hmac::<Hmac<Sha256>>(credentials.secret()?.as_bytes(), &[signing_input.as_bytes()])?;
+    let token = format!("{}.{}", signing_input, base64::encode_config(signature, base64::URL_SAFE_NO_PAD));
+
+    Ok(SignedRequest {
+        headers: vec![
+            ("X-Quoine-API-Version".to_string(), "2".to_string()),
+            ("X-Quoine-Auth".to_string(), token),
+            ("Content-Type".to_string(), "application/json".to_string()),
+        ],
+        query: request.query,
+        query_string,
+        body: request.body,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keys::SigningKey;
+    use crate::models::KeyType;
+    use crate::secret::Secret;
+    use chrono::TimeZone;
+    use ring::rand::SystemRandom;
+    use ring::signature::{Ed25519KeyPair, UnparsedPublicKey, ED25519};
+
+    fn credentials(api_key: &str, sign_key: &str, extra: Option<ExchangeCredentials>) -> AccountCredentials {
+        AccountCredentials {
+            api_key: Secret::from(api_key.to_string()),
+            sign_key: Some(Secret::from(sign_key.to_string())),
+            signing_key: None,
+            extra,
+        }
+    }
+
+    fn request(method: &str, path: &str, query: &[(&str, &str)], body: Option<&str>) -> ExchangeRequest {
+        ExchangeRequest {
+            method: method.to_string(),
+            path: path.to_string(),
+            query: query.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect(),
+            body: body.map(str::to_string),
+            host: None,
+        }
+    }
+
+    fn pairs(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+    }
+
+    /// 2020-12-08T09:08:57.715Z
+    fn now() -> DateTime<Utc> {
+        Utc.timestamp_millis_opt(1_607_418_537_715).unwrap()
+    }
+
+    /// The HMAC example of Binance's "SIGNED endpoint security" docs.
+    #[test]
+    fn binance_matches_the_documented_example() {
+        let credentials = credentials(
+            "vmPUZE6mv9SD5VNHk4HlWFsOr6aKE2zvsw0MuIgwCIPy6utIco14y7Ju91duEh8A",
+            "NhqPtmdSJYdKjVHjA7PZj4Mge3R5YNiP1e3UZjInClVN65XAbvqqM6A7H5fATj0j",
+            None,
+        );
+        let order = [
+            ("symbol", "LTCBTC"),
+            ("side", "BUY"),
+            ("type", "LIMIT"),
+            ("timeInForce", "GTC"),
+            ("quantity", "1"),
+            ("price", "0.1"),
+            ("recvWindow", "5000"),
+        ];
+        let now = Utc.timestamp_millis_opt(1_499_827_319_559).unwrap();
+        let signed = sign_request(&ExchangeName::Binance, &credentials, request("POST", "/api/v3/order", &order, None), now, 1)
+            .unwrap();
+
+        assert_eq!(signed.headers, pairs(&[(
+            "X-MBX-APIKEY",
+            "vmPUZE6mv9SD5VNHk4HlWFsOr6aKE2zvsw0MuIgwCIPy6utIco14y7Ju91duEh8A",
+        )]));
+        assert_eq!(
+            signed.query_string,
+            "symbol=LTCBTC&side=BUY&type=LIMIT&timeInForce=GTC&quantity=1&price=0.1&recvWindow=5000\
+             &timestamp=1499827319559&signature=c8db56825ae71d6d79447849e617115f4a920fa2acdcab2b053c4b2838bd6b71",
+        );
+        assert_eq!(signed.query.len(), 9);
+        assert_eq!(signed.body, None);
+    }
+
+    #[test]
+    fn binance_signs_with_ed25519_keys() {
+        let der = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        let signing_key = SigningKey::from_pkcs8(KeyType::Ed25519, der.as_ref()).unwrap();
+        let public_key = match &signing_key {
+            SigningKey::Ed25519(key) => ring::signature::KeyPair::public_key(key).as_ref().to_vec(),
+            SigningKey::Rsa(_) => unreachable!(),
+        };
+        let credentials = AccountCredentials {
+            api_key: Secret::from("binance-key".to_string()),
+            sign_key: None,
+            signing_key: Some(signing_key),
+            extra: None,
+        };
+        let signed = sign_request(
+            &ExchangeName::Binance,
+            &credentials,
+            request("GET", "/api/v3/account", &[("recvWindow", "5000")], None),
+            now(),
+            1,
+        ).unwrap();
+
+        let (name, signature) = signed.query.last().unwrap();
+        assert_eq!(name, "signature");
+        let signature = base64::decode(signature).unwrap();
+        UnparsedPublicKey::new(&ED25519, &public_key)
+            .verify(b"recvWindow=5000&timestamp=1607418537715", &signature)
+            .unwrap();
+    }
+
+    /// The `API-Sign` example of Kraken's REST authentication docs.
+    #[test]
+    fn kraken_matches_the_documented_example() {
+        let credentials = credentials(
+            "kraken-key",
+            "kQH5HW/8p1uGOVjbgWA7FunAmGO8lsSUXNsu3eow76sz84Q18fWxnyRzBHCd3pd5nE9qa99HAZtuZuj6F1huXg==",
+            None,
+        );
+        let body = "ordertype=limit&pair=XBTUSD&price=37500&type=buy&volume=1.25";
+        let signed = sign_request(
+            &ExchangeName::Kraken,
+            &credentials,
+            request("POST", "/0/private/AddOrder", &[], Some(body)),
+            now(),
+            1_616_492_376_594,
+        ).unwrap();
+
+        assert_eq!(signed.headers, pairs(&[
+            ("API-Key", "kraken-key"),
+            (
+                "API-Sign",
+                "4/dpxb3iT4tp/ZCVEwSnEsLxx0bqyhLpdfOpc6fn7OR8+UClSV5n9E6aSS8MPtnRfp32bAb0nmbRn6H8ndwLUQ==",
+            ),
+        ]));
+        assert_eq!(signed.query_string, "");
+        assert_eq!(signed.body.as_deref(), Some(format!("nonce=1616492376594&{}", body).as_str()));
+    }
+
+    /// Signed over the prehash of Okex's docs, `2020-12-08T09:08:57.715ZGET/api/v5/account/balance?ccy=BTC`.
+    #[test]
+    fn okex_signs_the_documented_prehash() {
+        let credentials = credentials(
+            "okex-key",
+            "okex-secret",
+            Some(ExchangeCredentials::Okex { passphrase: Secret::from("okex-passphrase".to_string()) }),
+        );
+        let signed = sign_request(
+            &ExchangeName::Okex,
+            &credentials,
+            request("get", "/api/v5/account/balance", &[("ccy", "BTC")], None),
+            now(),
+            1,
+        ).unwrap();
+
+        assert_eq!(signed.headers, pairs(&[
+            ("OK-ACCESS-KEY", "okex-key"),
+            ("OK-ACCESS-SIGN", "pkJWLgLpCp2aqMg4P5wtFGVUI7QSAKQvp3h6CR0A2Xw="),
+            ("OK-ACCESS-TIMESTAMP", "2020-12-08T09:08:57.715Z"),
+            ("OK-ACCESS-PASSPHRASE", "okex-passphrase"),
+        ]));
+        assert_eq!(signed.query_string, "ccy=BTC");
+        assert_eq!(signed.body, None);
+    }
+
+    #[test]
+    fn okex_needs_a_passphrase() {
+        let credentials = credentials("okex-key", "okex-secret", None);
+        let request = request("GET", "/api/v5/account/balance", &[], None);
+        assert!(sign_request(&ExchangeName::Okex, &credentials, request, now(), 1).is_err());
+    }
+
+    /// Signed over the prehash of Kucoin's docs, `1547015186532POST/api/v1/deposit-addresses{"currency":"BTC"}`.
+    #[test]
+    fn kucoin_signs_the_documented_prehash_and_passphrase() {
+        let credentials = credentials(
+            "kucoin-key",
+            "kucoin-secret",
+            Some(ExchangeCredentials::Kucoin {
+                passphrase: Secret::from("kucoin-passphrase".to_string()),
+                key_version: 2,
+            }),
+        );
+        let now = Utc.timestamp_millis_opt(1_547_015_186_532).unwrap();
+        let signed = sign_request(
+            &ExchangeName::Kucoin,
+            &credentials,
+            request("POST", "/api/v1/deposit-addresses", &[], Some(r#"{"currency":"BTC"}"#)),
+            now,
+            1,
+        ).unwrap();
+
+        assert_eq!(signed.headers, pairs(&[
+            ("KC-API-KEY", "kucoin-key"),
+            ("KC-API-SIGN", "4qmGJ8KQ3jE6hdBfsjseAskNxW/BYfkJQlZ95IjmHww="),
+            ("KC-API-TIMESTAMP", "1547015186532"),
+            ("KC-API-PASSPHRASE", "yBS0D3ATXupjwr4u/rQER6aiYjQSdX5r3qULPp/2shE="),
+            ("KC-API-KEY-VERSION", "2"),
+        ]));
+        assert_eq!(signed.query_string, "");
+        assert_eq!(signed.body.as_deref(), Some(r#"{"currency":"BTC"}"#));
+    }
+
+    #[test]
+    fn kucoin_sends_version_1_passphrases_as_is() {
+        let credentials = credentials(
+            "kucoin-key",
+            "kucoin-secret",
+            Some(ExchangeCredentials::Kucoin {
+                passphrase: Secret::from("kucoin-passphrase".to_string()),
+                key_version: 1,
+            }),
+        );
+        let signed = sign_request(&ExchangeName::Kucoin, &credentials, request("GET", "/api/v1/accounts", &[], None), now(), 1)
+            .unwrap();
+        assert!(signed.headers.contains(&("KC-API-PASSPHRASE".to_string(), "kucoin-passphrase".to_string())));
+        assert!(signed.headers.contains(&("KC-API-KEY-VERSION".to_string(), "1".to_string())));
+    }
+
+    /// Signed over `/api/v2/auth/r/wallets<nonce>{}`, the layout of Bitfinex's v2 authentication docs.
+    #[test]
+    fn bitfinex_signs_the_documented_layout() {
+        let credentials = credentials("bitfinex-key", "bitfinex-secret", None);
+        let signed = sign_request(
+            &ExchangeName::Bitfinex,
+            &credentials,
+            request("POST", "/v2/auth/r/wallets", &[], Some("{}")),
+            now(),
+            1_607_418_537_715_000,
+        ).unwrap();
+
+        assert_eq!(signed.headers, pairs(&[
+            ("bfx-nonce", "1607418537715000"),
+            ("bfx-apikey", "bitfinex-key"),
+            (
+                "bfx-signature",
+                "2678de89932f1a8b24a0170308bad59f5440f43475d2e7bedd7aee2f549dc805\
+                 4272303dc8ad180806cb2b8550c7e00c",
+            ),
+        ]));
+        assert_eq!(signed.body.as_deref(), Some("{}"));
+    }
+
+    /// The pre-sign text of Huobi's signature version 2 docs:
+    /// `GET\napi.huobi.pro\n/v1/order/orders\nAccessKeyId=e2xxxxxx-99xxxxxx-84xxxxxx-7xxxx&...&order-id=1234567890`.
+    #[test]
+    fn huobi_signs_the_documented_pre_sign_text() {
+        let credentials = credentials("e2xxxxxx-99xxxxxx-84xxxxxx-7xxxx", "b0xxxxxx-c6xxxxxx-94xxxxxx-dxxxx", None);
+        let now = Utc.with_ymd_and_hms(2017, 5, 11, 15, 19, 30).unwrap();
+        let signed = sign_request(
+            &ExchangeName::Huobi,
+            &credentials,
+            request("GET", "/v1/order/orders", &[("order-id", "1234567890")], None),
+            now,
+            1,
+        ).unwrap();
+
+        assert!(signed.headers.is_empty());
+        assert_eq!(
+            signed.query_string,
+            "AccessKeyId=e2xxxxxx-99xxxxxx-84xxxxxx-7xxxx&SignatureMethod=HmacSHA256&SignatureVersion=2\
+             &Timestamp=2017-05-11T15%3A19%3A30&order-id=1234567890\
+             &Signature=Nmd8AU8uAe0mkFpxNbiava0aeZzBEtYjCdie1ZYZjoM%3D",
+        );
+        assert_eq!(signed.query.last().unwrap().1, "Nmd8AU8uAe0mkFpxNbiava0aeZzBEtYjCdie1ZYZjoM=");
+    }
+
+    #[test]
+    fn huobi_encodes_spaces_as_percent_20() {
+        let credentials = credentials("huobi-key", "huobi-secret", None);
+        let now = Utc.with_ymd_and_hms(2017, 5, 11, 15, 19, 30).unwrap();
+        let signed = sign_request(
+            &ExchangeName::Huobi,
+            &credentials,
+            request("GET", "/v1/order/orders", &[("client-order-id", "a b")], None),
+            now,
+            1,
+        ).unwrap();
+
+        assert_eq!(
+            signed.query_string,
+            "AccessKeyId=huobi-key&SignatureMethod=HmacSHA256&SignatureVersion=2&Timestamp=2017-05-11T15%3A19%3A30\
+             &client-order-id=a%20b&Signature=UEnYUXEeQpCuHyiVpSk2okwq8ftdTDQBU1TTQxuJZuc%3D",
+        );
+    }
+
+    /// Signed over `GET/api/3/spot/balance?currency=BTC<timestamp>`, the layout of HitBTC's HS256 docs.
+    #[test]
+    fn hitbtc_signs_the_documented_layout() {
+        let credentials = credentials("hitbtc-key", "hitbtc-secret", None);
+        let signed = sign_request(
+            &ExchangeName::HitBtc,
+            &credentials,
+            request("GET", "/api/3/spot/balance", &[("currency", "BTC")], None),
+            now(),
+            1,
+        ).unwrap();
+
+        let token = "aGl0YnRjLWtleTpmOWI2ZjNiOWE3NTkxNTM4ZGI4MWE0MmI4NjZlYmI5YmQ1NGM1NjBjNjI1NWM4YTMzNTc5YjdlMmI3MDJmN2EzOjE2MDc0MTg1Mzc3MTU=";
+        assert_eq!(signed.headers, pairs(&[("Authorization", &format!("HS256 {}", token))]));
+        assert_eq!(
+            String::from_utf8(base64::decode(token).unwrap()).unwrap(),
+            "hitbtc-key:f9b6f3b9a7591538db81a42b866ebb9bd54c560c6255c8a33579b7e2b702f7a3:1607418537715",
+        );
+        assert_eq!(signed.query_string, "currency=BTC");
+    }
+
+    /// The `X-Quoine-Auth` JWT layout of Quoine's authentication docs.
+    #[test]
+    fn quoine_signs_the_documented_jwt() {
+        let credentials = credentials("quoine-token", "quoine-secret", None);
+        let signed = sign_request(
+            &ExchangeName::Quoine,
+            &credentials,
+            request("GET", "/orders", &[("product_id", "1")], None),
+            now(),
+            1_607_418_537_715,
+        ).unwrap();
+
+        assert_eq!(signed.headers, pairs(&[
+            ("X-Quoine-API-Version", "2"),
+            (
+                "X-Quoine-Auth",
+                "eyJhbGciOiJIUzI1NiIsInR5cCI6IkpXVCJ9\
+                 .eyJub25jZSI6MTYwNzQxODUzNzcxNSwicGF0aCI6Ii9vcmRlcnM_cHJvZHVjdF9pZD0xIiwidG9rZW5faWQiOiJxdW9pbmUtdG9rZW4ifQ\
+                 ._n-K583Bn8VwxIJCeYf_JrKpfIr49E23vlZRnrcpijo",
+            ),
+            ("Content-Type", "application/json"),
+        ]));
+        assert_eq!(signed.query_string, "product_id=1");
+    }
+}
@@ -0,0 +1,374 @@
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::Arc;
+use futures::{Stream, StreamExt};
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::metadata::MetadataValue;
+use tonic::{Code, Request, Response, Status};
+use crate::account::AccountRepo;
+use crate::db::AccountError;
+use crate::dto::{AccountChangesDto, CreateAccountDto, FieldErrorDto, KeyValueDto, SignRequestDto, SignedRequestDto};
+use crate::models::{AccountId, AccountPatch, ExchangeCredentials, ExchangeName, ExecutorResponse};
+use crate::rate_limit::{RateLimited, RateLimiter};
+use crate::secret::Secret;
+use crate::stream::{Delivery, EventHub, Topic};
+use crate::validation::{self, Validate, Validator};
+
+pub mod proto {
+    tonic::include_proto!("try_api.v1");
+}
+
+use proto::accounts_server::{Accounts, AccountsServer};
+use proto::field_change::Change;
+use proto::orders_server::{Orders, OrdersServer};
+
+/// Account errors get their own code, failures of the service a generic INTERNAL whose details
+/// are only logged, and any other error `code`.
+fn status(err: anyhow::Error, code: Code) -> Status {
+    log::error!("{}", err);
+    let code = match err.downcast_ref::<AccountError>() {
+        Some(AccountError::NotFound(_)) | Some(AccountError::NoApiKey(_)) => Code::NotFound,
+        Some(AccountError::VersionMismatch { .. }) => Code::FailedPrecondition,
+        Some(AccountError::AlreadyExists(_)) | Some(AccountError::Deleted(_)) => Code::AlreadyExists,
+        None if err.downcast_ref::<sqlx::Error>().is_some() => Code::Internal,
+        None => code,
+    };
+    if code == Code::Internal {
+        return Status::internal("Internal server error");
+    }
+    Status::new(code, err.to_string())
+}
+
+// tonic handlers must return `Status` errors, so `result_large_err` has no alternative to offer
+// for the helpers producing them.
+#[allow(clippy::result_large_err)]
+fn validated(errors: Vec<FieldErrorDto>) -> Result<(), Status> {
+    if errors.is_empty() {
+        return Ok(());
+    }
+    let errors: Vec<String> = errors.iter().map(|error| format!("{}: {}", error.field, error.message)).collect();
+    Err(Status::invalid_argument(format!("Request validation failed: {}", errors.join("; "))))
+}
+
+fn rate_limited(limited: RateLimited) -> Status {
+    let mut status = Status::resource_exhausted(format!("Rate limit per {} exceeded", limited.scope));
+    if let Ok(value) = MetadataValue::from_str(&limited.retry_after_secs().to_string()) {
+        status.metadata_mut().insert("retry-after", value);
+    }
+    status
+}
+
+/// Adds the exchange budget warning, if any, as `warning` metadata.
+fn with_warning<T>(message: T, warning: Option<String>) -> Response<T> {
+    let mut response = Response::new(message);
+    if let Some(warning) = warning {
+        if let Ok(value) = MetadataValue::from_str(&warning) {
+            response.metadata_mut().insert("warning", value);
+        }
+    }
+    response
+}
+
+#[allow(clippy::result_large_err)]
+fn account_key(key: Option<proto::AccountKey>) -> Result<(String, ExchangeName), Status> {
+    let key = key.ok_or_else(|| Status::invalid_argument("account is required"))?;
+    let exchange = ExchangeName::from_str(&key.exchange)
+        .map_err(|_| Status::invalid_argument(format!("unknown exchange \"{}\"", key.exchange)))?;
+    Ok((key.uid, exchange))
+}
+
+#[allow(clippy::result_large_err)]
+fn credentials(json: &str) -> Result<ExchangeCredentials, Status> {
+    serde_json::from_str(json).map_err(|err| Status::invalid_argument(format!("credentials_json: {}", err)))
+}
+
+/// Reads a `FieldChange` the way REST reads a nullable field: unset leaves, `clear` is `null`.
+#[allow(clippy::result_large_err)]
+fn change<T>(
+    change: Option<proto::FieldChange>,
+    parse: impl Fn(String) -> Result<T, Status>,
+) -> Result<Option<Option<T>>, Status> {
+    match change.and_then(|change| change.change) {
+        None => Ok(None),
+        Some(Change::Clear(_)) => Ok(Some(None)),
+        Some(Change::Set(value)) => Ok(Some(Some(parse(value)?))),
+    }
+}
+
+fn key_values(pairs: Vec<KeyValueDto>) -> Vec<proto::KeyValue> {
+    pairs.into_iter().map(|pair| proto::KeyValue { name: pair.name, value: pair.value }).collect()
+}
+
+/// The `AccountRepo` operations of the `/v1` routes, over gRPC.
+pub struct AccountService {
+    account_repo: Arc<AccountRepo>,
+    limiter: Arc<RateLimiter>,
+}
+
+impl AccountService {
+    pub fn new(account_repo: Arc<AccountRepo>, limiter: Arc<RateLimiter>) -> AccountService {
+        AccountService { account_repo, limiter }
+    }
+
+    /// Applies the rate limits REST calls get, sharing their buckets; `exchange` is set for
+    /// signing calls, which spend the exchange weight budget.
+    #[allow(clippy::result_large_err)]
+    fn limit<T>(
+        &self,
+        request: &Request<T>,
+        route: &str,
+        uid: &str,
+        exchange: Option<&ExchangeName>,
+    ) -> Result<Option<String>, Status> {
+        let authorization = request.metadata().get("authorization").and_then(|value| value.to_str().ok());
+        self.limiter
+            .check_call(route, authorization, request.remote_addr(), Some(uid), exchange)
+            .map_err(rate_limited)
+    }
+}
+
+#[tonic::async_trait]
+impl Accounts for AccountService {
+    async fn create_account(
+        &self,
+        request: Request<proto::CreateAccountRequest>,
+    ) -> Result<Response<proto::AccountRef>, Status> {
+        let (uid, exchange) = account_key(request.get_ref().account.clone())?;
+        self.limit(&request, "grpc CreateAccount", &uid, None)?;
+        let message = request.into_inner();
+        let dto = CreateAccountDto {
+            uid,
+            exchange,
+            api_key: Secret::from(message.api_key),
+            sign_key: message.sign_key.map(Secret::from),
+            credentials: message.credentials_json.as_deref().map(credentials).transpose()?,
+        };
+        let mut validator = Validator::default();
+        dto.validate(&mut validator);
+        validated(validator.into_errors())?;
+        match self.account_repo.create_account(
+            &AccountId(dto.uid.clone()),
+            &dto.exchange,
+            &dto.api_key,
+            dto.sign_key.as_ref(),
+            dto.credentials.as_ref(),
+        ).await {
+            Ok(()) => Ok(Response::new(proto::AccountRef {
+                uid: dto.uid,
+                exchange: dto.exchange.to_string(),
+                version: None,
+            })),
+            Err(err) => Err(status(err, Code::InvalidArgument)),
+        }
+    }
+
+    #[allow(clippy::result_large_err)]
+    async fn update_account(
+        &self,
+        request: Request<proto::UpdateAccountRequest>,
+    ) -> Result<Response<proto::AccountRef>, Status> {
+        let (uid, exchange) = account_key(request.get_ref().account.clone())?;
+        self.limit(&request, "grpc UpdateAccount", &uid, None)?;
+        let message = request.into_inner();
+        let dto = AccountChangesDto {
+            api_key: change(message.api_key, |value| Ok(Secret::from(value)))?,
+            sign_key: change(message.sign_key, |value| Ok(Secret::from(value)))?,
+            credentials: change(message.credentials_json, |value| credentials(&value))?,
+        };
+        validated(validation::errors_for_exchange(&uid, &exchange, &dto))?;
+        match self.account_repo.update_account(
+            &AccountId(uid),
+            &exchange,
+            &AccountPatch::from(dto),
+            message.expected_version,
+        ).await {
+            Ok((uid, version)) => Ok(Response::new(proto::AccountRef {
+                uid,
+                exchange: exchange.to_string(),
+                version: Some(version),
+            })),
+            Err(err) => Err(status(err, Code::InvalidArgument)),
+        }
+    }
+
+    async fn get_api_key(
+        &self,
+        request: Request<proto::AccountKey>,
+    ) -> Result<Response<proto::ApiKey>, Status> {
+        let (uid, exchange) = account_key(Some(request.get_ref().clone()))?;
+        self.limit(&request, "grpc GetApiKey", &uid, None)?;
+        validated(validation::uid_errors(&uid))?;
+        match self.account_repo.get_api_key(&AccountId(uid), &exchange).await {
+            Ok(api_key) => Ok(Response::new(proto::ApiKey { api_key: api_key.expose().to_string() })),
+            Err(err) => Err(status(err, Code::Internal)),
+        }
+    }
+
+    async fn sign_payload(
+        &self,
+        request: Request<proto::SignPayloadRequest>,
+    ) -> Result<Response<proto::Signature>, Status> {
+        let (uid, exchange) = account_key(request.get_ref().account.clone())?;
+        let warning = self.limit(&request, "grpc SignPayload", &uid, Some(&exchange))?;
+        validated(validation::uid_errors(&uid))?;
+        let message = request.into_inner();
+        match self.account_repo.sign_and_get_key(&AccountId(uid), &exchange, &message.data_to_sign).await {
+            Ok((uid, api_key, nonce)) => Ok(with_warning(
+                proto::Signature { uid, api_key: api_key.expose().to_string(), nonce },
+                warning,
+            )),
+            Err(err) => Err(status(err, Code::Internal)),
+        }
+    }
+
+    async fn sign_request(
+        &self,
+        request: Request<proto::SignRequestRequest>,
+    ) -> Result<Response<proto::SignedRequest>, Status> {
+        let (uid, exchange) = account_key(request.get_ref().account.clone())?;
+        let warning = self.limit(&request, "grpc SignRequest", &uid, Some(&exchange))?;
+        let message = request.into_inner();
+        let dto = SignRequestDto {
+            method: message.method,
+            path: message.path,
+            query: message.query
+                .into_iter()
+                .map(|pair| KeyValueDto { name: pair.name, value: pair.value })
+                .collect(),
+            body: message.body,
+            host: message.host,
+        };
+        validated(validation::errors_for_exchange(&uid, &exchange, &dto))?;
+        match self.account_repo.sign_request(&AccountId(uid), &exchange, dto.into()).await {
+            Ok(signed) => {
+                let signed = SignedRequestDto::from(signed);
+                Ok(with_warning(
+                    proto::SignedRequest {
+                        headers: key_values(signed.headers),
+                        query: key_values(signed.query),
+                        query_string: signed.query_string,
+                        body: signed.body,
+                    },
+                    warning,
+                ))
+            }
+            Err(err) => Err(status(err, Code::InvalidArgument)),
+        }
+    }
+
+    async fn remove_api_key(
+        &self,
+        request: Request<proto::RemoveApiKeyRequest>,
+    ) -> Result<Response<()>, Status> {
+        let (uid, exchange) = account_key(request.get_ref().account.clone())?;
+        self.limit(&request, "grpc RemoveApiKey", &uid, None)?;
+        validated(validation::uid_errors(&uid))?;
+        let expected_version = request.get_ref().expected_version;
+        match self.account_repo.remove_key(&AccountId(uid), Some(&exchange), expected_version).await {
+            Ok(()) => Ok(Response::new(())),
+            Err(err) => Err(status(err, Code::Internal)),
+        }
+    }
+
+    async fn remove_account(
+        &self,
+        request: Request<proto::RemoveAccountRequest>,
+    ) -> Result<Response<()>, Status> {
+        let uid = request.get_ref().uid.clone();
+        self.limit(&request, "grpc RemoveAccount", &uid, None)?;
+        validated(validation::uid_errors(&uid))?;
+        let expected_version = request.get_ref().expected_version;
+        match self.account_repo.remove_account(&AccountId(uid), expected_version).await {
+            Ok(()) => Ok(Response::new(())),
+            Err(err) => Err(status(err, Code::Internal)),
+        }
+    }
+}
+
+fn order_event(delivery: Delivery) -> proto::OrderEvent {
+    let kind = match delivery {
+        Delivery::Event(event) => proto::order_event::Kind::Event(proto::order_event::Event {
+            seq: event.seq,
+            topic: event.topic.to_string(),
+            event_json: event.event.to_string(),
+        }),
+        Delivery::Gap { from, to } => proto::order_event::Kind::Gap(proto::order_event::Gap { from, to }),
+    };
+    proto::OrderEvent { kind: Some(kind) }
+}
+
+/// The order events of the `/ws` stream, over gRPC.
+pub struct OrderService {
+    /// `None` while the event stream is disabled.
+    hub: Option<Arc<EventHub>>,
+    limiter: Arc<RateLimiter>,
+}
+
+impl OrderService {
+    pub fn new(hub: Option<Arc<EventHub>>, limiter: Arc<RateLimiter>) -> OrderService {
+        OrderService { hub, limiter }
+    }
+
+    /// The hub, after applying the rate limits of `route` for `uid` and validating it.
+    #[allow(clippy::result_large_err)]
+    fn hub<T>(&self, request: &Request<T>, route: &str, uid: &str) -> Result<Arc<EventHub>, Status> {
+        let hub = self.hub.clone().ok_or_else(|| Status::unimplemented("the event stream is disabled"))?;
+        let authorization = request.metadata().get("authorization").and_then(|value| value.to_str().ok());
+        self.limiter
+            .check_call(route, authorization, request.remote_addr(), Some(uid), None)
+            .map_err(rate_limited)?;
+        validated(validation::uid_errors(uid))?;
+        Ok(hub)
+    }
+}
+
+#[tonic::async_trait]
+impl Orders for OrderService {
+    async fn publish_executor_response(
+        &self,
+        request: Request<proto::PublishExecutorResponseRequest>,
+    ) -> Result<Response<proto::Published>, Status> {
+        let hub = self.hub(&request, "grpc PublishExecutorResponse", &request.get_ref().uid)?;
+        let message = request.into_inner();
+        let response: ExecutorResponse = serde_json::from_str(&message.executor_response_json)
+            .map_err(|err| Status::invalid_argument(format!("executor_response_json: {}", err)))?;
+        match hub.publish_response(&message.uid, &response) {
+            Ok(seq) => Ok(Response::new(proto::Published { seq })),
+            Err(err) => Err(status(err, Code::Internal)),
+        }
+    }
+
+    type WatchOrdersStream = Pin<Box<dyn Stream<Item=Result<proto::OrderEvent, Status>> + Send + Sync>>;
+
+    #[allow(clippy::result_large_err)]
+    async fn watch_orders(
+        &self,
+        request: Request<proto::WatchOrdersRequest>,
+    ) -> Result<Response<Self::WatchOrdersStream>, Status> {
+        let hub = self.hub(&request, "grpc WatchOrders", &request.get_ref().uid)?;
+        let message = request.into_inner();
+        let topics: HashSet<Topic> = vec![
+            Topic::Orders(message.uid.clone()),
+            Topic::Trades(message.uid.clone()),
+            Topic::Balances(message.uid),
+        ].into_iter().collect();
+        let deliveries = hub.watch(topics, message.resume_from);
+        Ok(Response::new(Box::pin(ReceiverStream::new(deliveries).map(|delivery| Ok(order_event(delivery))))))
+    }
+}
+
+/// Serves the account and order services on `addr`, next to the REST API.
+pub async fn serve(
+    addr: SocketAddr,
+    account_repo: Arc<AccountRepo>,
+    limiter: Arc<RateLimiter>,
+    hub: Option<Arc<EventHub>>,
+) -> Result<(), tonic::transport::Error> {
+    tonic::transport::Server::builder()
+        .add_service(AccountsServer::new(AccountService::new(account_repo, limiter.clone())))
+        .add_service(OrdersServer::new(OrderService::new(hub, limiter)))
+        .serve(addr)
+        .await
+}
@@ -0,0 +1,471 @@
+use warp::{http, Reply};
+use warp::reply::{Json, WithStatus};
+use anyhow::anyhow;
+use crate::models::{AccountId, ExchangeName, AccountPatch, ExecutorResponse};
+use crate::account::AccountRepo;
+use crate::db::AccountError;
+use crate::dto::{
+    CreateAccountDto, SignAndGetDto, UpdateAccountDto, GetApiKeyDto, ListAccountsQuery, AccountPageDto,
+    AccountSummaryDto, AccountDetailsDto, AccountChangesDto, SignatureDto, ApiKeyDto,
+    AccountRefDto, ErrorDto, SignRequestDto, SignedRequestDto, KeyPairRequestDto, PublicKeyDto, PublishedEventDto,
+};
+use crate::stream::EventHub;
+use std::sync::Arc;
+
+/// Account errors get their own status, failures of the service a generic 500 whose details are
+/// only logged, and any other error `status`.
+fn json_error(err: anyhow::Error, status: http::StatusCode) -> WithStatus<Json> {
+    log::error!("{}", err);
+    let status = match err.downcast_ref::<AccountError>() {
+        Some(AccountError::NotFound(_)) | Some(AccountError::NoApiKey(_)) => http::StatusCode::NOT_FOUND,
+        Some(AccountError::VersionMismatch { .. }) => http::StatusCode::PRECONDITION_FAILED,
+        Some(AccountError::AlreadyExists(_)) | Some(AccountError::Deleted(_)) => http::StatusCode::CONFLICT,
+        None if err.downcast_ref::<sqlx::Error>().is_some() => http::StatusCode::INTERNAL_SERVER_ERROR,
+        None => status,
+    };
+    let error = if status.is_server_error() {
+        "Internal server error".to_string()
+    } else {
+        err.to_string()
+    };
+    warp::reply::with_status(warp::reply::json(&ErrorDto { error }), status)
+}
+
+fn etag(version: i64) -> String {
+    format!("\"{}\"", version)
+}
+
+/// Reads the account version a client expects from an `If-Match` header; `*` matches any version.
+fn expected_version(if_match: Option<String>) -> Result<Option<i64>, anyhow::Error> {
+    match if_match {
+        None => Ok(None),
+        Some(value) if value.trim() == "*" => Ok(None),
+        Some(value) => value.trim()
+            .trim_start_matches("W/")
+            .trim_matches('"')
+            .parse()
+            .map(Some)
+            .map_err(|_| anyhow!("invalid If-Match header \"{}\"", value)),
+    }
+}
+
+pub async fn create_account_rest(
+    account_repo: Arc<AccountRepo>,
+    create_account_dto: CreateAccountDto,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    match account_repo.create_account(
+        &AccountId(create_account_dto.uid),
+        &create_account_dto.exchange,
+        &create_account_dto.api_key,
+        create_account_dto.sign_key.as_ref(),
+        create_account_dto.credentials.as_ref(),
+    ).await {
+        Ok(()) => {
+            Ok(warp::reply::with_status(
+                "Create account".to_string(),
+                http::StatusCode::OK,
+            ))
+        }
+        Err(err) => {
+            log::error!("{}", err);
+            Ok(warp::reply::with_status(
+                err.to_string(),
+                http::StatusCode::BAD_REQUEST,
+            ))
+        }
+    }
+}
+
+pub async fn sign_and_key_rest(
+    account_repo: Arc<AccountRepo>,
+    sign_and_get_dto: SignAndGetDto,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    match account_repo.sign_and_get_key(
+        &AccountId(sign_and_get_dto.uid),
+        &sign_and_get_dto.exchange,
+        &sign_and_get_dto.data_to_sign,
+    ).await {
+        Ok((mda, kek, _)) => {
+            Ok(warp::reply::with_status(
+                format!("Sign get {} {}", mda, kek.expose()),
+                http::StatusCode::OK,
+            ))
+        }
+        Err(err) => {
+            log::error!("{}", err);
+            Ok(warp::reply::with_status(
+                err.to_string(),
+                http::StatusCode::BAD_REQUEST,
+            ))
+        }
+    }
+}
+
+pub async fn remove_account_rest(
+    account_id: String,
+    account_repo: Arc<AccountRepo>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    match account_repo.remove_account(
+        &AccountId(account_id),
+        None,
+    ).await {
+        Ok(()) => {
+            Ok(warp::reply::with_status(
+                "Account removed".to_string(),
+                http::StatusCode::OK,
+            ))
+        }
+        Err(err) => {
+            log::error!("{}", err);
+            Ok(warp::reply::with_status(
+                err.to_string(),
+                http::StatusCode::BAD_REQUEST,
+            ))
+        }
+    }
+}
+
+pub async fn remove_key_rest(
+    account_id: String,
+    account_repo: Arc<AccountRepo>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    match account_repo.remove_key(
+        &AccountId(account_id),
+        None,
+        None,
+    ).await {
+        Ok(()) => {
+            Ok(warp::reply::with_status(
+                "Key removed".to_string(),
+                http::StatusCode::OK,
+            ))
+        }
+        Err(err) => {
+            log::error!("{}", err);
+            Ok(warp::reply::with_status(
+                err.to_string(),
+                http::StatusCode::BAD_REQUEST,
+            ))
+        }
+    }
+}
+
+pub async fn update_account_rest(
+    account_repo: Arc<AccountRepo>,
+    update_account_dto: UpdateAccountDto,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let uid = AccountId(update_account_dto.uid.clone());
+    let exchange = update_account_dto.exchange.clone();
+    match account_repo.update_account(
+        &uid,
+        &exchange,
+        &AccountPatch::from(update_account_dto),
+        None,
+    ).await {
+        Ok((response, _)) => {
+            Ok(warp::reply::with_status(
+                format!("Account with uid \"{}\" updated", response),
+                http::StatusCode::OK,
+            ))
+        }
+        Err(err) => {
+            log::error!("{}", err);
+            Ok(warp::reply::with_status(
+                err.to_string(),
+                http::StatusCode::BAD_REQUEST,
+            ))
+        }
+    }
+}
+
+pub async fn get_api_key_rest(
+    account_repo: Arc<AccountRepo>,
+    get_api_key_dto: GetApiKeyDto,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    match account_repo.get_api_key(
+        &AccountId(get_api_key_dto.uid),
+        &get_api_key_dto.exchange,
+    ).await {
+        Ok(api_key) => {
+            Ok(warp::reply::with_status(
+                format!("Api key {}", api_key.expose()),
+                http::StatusCode::OK,
+            ))
+        }
+        Err(err) => {
+            log::error!("{}", err);
+            Ok(warp::reply::with_status(
+                err.to_string(),
+                http::StatusCode::NOT_FOUND,
+            ))
+        }
+    }
+}
+
+pub async fn list_accounts_rest(
+    account_repo: Arc<AccountRepo>,
+    query: ListAccountsQuery,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    match account_repo.list_accounts(&query).await {
+        Ok((accounts, next_cursor)) => {
+            let page = AccountPageDto {
+                items: accounts.into_iter().map(AccountSummaryDto::from).collect(),
+                next_cursor,
+            };
+            Ok(warp::reply::with_status(
+                warp::reply::json(&page),
+                http::StatusCode::OK,
+            ))
+        }
+        Err(err) => {
+            Ok(json_error(err, http::StatusCode::BAD_REQUEST))
+        }
+    }
+}
+
+pub async fn get_account_rest(
+    account_id: String,
+    exchange: Option<ExchangeName>,
+    account_repo: Arc<AccountRepo>,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    match account_repo.get_account(
+        &AccountId(account_id),
+        exchange.as_ref(),
+    ).await {
+        Ok(account) => {
+            let version = account.version;
+            Ok(warp::reply::with_header(
+                warp::reply::json(&AccountDetailsDto::from(account)),
+                "ETag",
+                etag(version),
+            ).into_response())
+        }
+        Err(err) => {
+            Ok(json_error(err, http::StatusCode::INTERNAL_SERVER_ERROR).into_response())
+        }
+    }
+}
+
+pub async fn create_account_v1(
+    account_repo: Arc<AccountRepo>,
+    create_account_dto: CreateAccountDto,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    match account_repo.create_account(
+        &AccountId(create_account_dto.uid.clone()),
+        &create_account_dto.exchange,
+        &create_account_dto.api_key,
+        create_account_dto.sign_key.as_ref(),
+        create_account_dto.credentials.as_ref(),
+    ).await {
+        Ok(()) => {
+            let account = AccountRefDto {
+                uid: create_account_dto.uid,
+                exchange: create_account_dto.exchange,
+            };
+            Ok(warp::reply::with_status(
+                warp::reply::json(&account),
+                http::StatusCode::CREATED,
+            ).into_response())
+        }
+        Err(err) => Ok(json_error(err, http::StatusCode::BAD_REQUEST).into_response())
+    }
+}
+
+pub async fn update_account_v1(
+    account_id: String,
+    exchange: ExchangeName,
+    if_match: Option<String>,
+    account_repo: Arc<AccountRepo>,
+    account_changes_dto: AccountChangesDto,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    let expected_version = match expected_version(if_match) {
+        Ok(version) => version,
+        Err(err) => return Ok(json_error(err, http::StatusCode::BAD_REQUEST).into_response()),
+    };
+    match account_repo.update_account(
+        &AccountId(account_id),
+        &exchange,
+        &AccountPatch::from(account_changes_dto),
+        expected_version,
+    ).await {
+        Ok((uid, version)) => Ok(warp::reply::with_header(
+            warp::reply::json(&AccountRefDto { uid, exchange }),
+            "ETag",
+            etag(version),
+        ).into_response()),
+        Err(err) => Ok(json_error(err, http::StatusCode::BAD_REQUEST).into_response())
+    }
+}
+
+pub async fn remove_account_v1(
+    account_id: String,
+    if_match: Option<String>,
+    account_repo: Arc<AccountRepo>,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    let expected_version = match expected_version(if_match) {
+        Ok(version) => version,
+        Err(err) => return Ok(json_error(err, http::StatusCode::BAD_REQUEST).into_response()),
+    };
+    match account_repo.remove_account(&AccountId(account_id), expected_version).await {
+        Ok(()) => Ok(http::StatusCode::NO_CONTENT.into_response()),
+        Err(err) => Ok(json_error(err, http::StatusCode::INTERNAL_SERVER_ERROR).into_response())
+    }
+}
+
+pub async fn restore_account_v1(
+    account_id: String,
+    account_repo: Arc<AccountRepo>,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    match account_repo.restore_account(&AccountId(account_id)).await {
+        Ok((uid, exchange, version)) => Ok(warp::reply::with_header(
+            warp::reply::json(&AccountRefDto { uid, exchange }),
+            "ETag",
+            etag(version),
+        ).into_response()),
+        Err(err) => Ok(json_error(err, http::StatusCode::INTERNAL_SERVER_ERROR).into_response())
+    }
+}
+
+pub async fn create_signature_v1(
+    account_id: String,
+    exchange: ExchangeName,
+    account_repo: Arc<AccountRepo>,
+    data_to_sign: Vec<u8>,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    match account_repo.sign_and_get_key(
+        &AccountId(account_id),
+        &exchange,
+        &data_to_sign,
+    ).await {
+        Ok((uid, api_key, nonce)) => Ok(warp::reply::with_status(
+            warp::reply::json(&SignatureDto { uid, api_key, nonce }),
+            http::StatusCode::CREATED,
+        ).into_response()),
+        Err(err) => Ok(json_error(err, http::StatusCode::INTERNAL_SERVER_ERROR).into_response())
+    }
+}
+
+pub async fn get_api_key_v1(
+    account_id: String,
+    exchange: ExchangeName,
+    account_repo: Arc<AccountRepo>,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    match account_repo.get_api_key(&AccountId(account_id), &exchange).await {
+        Ok(api_key) => Ok(warp::reply::json(&ApiKeyDto { api_key }).into_response()),
+        Err(err) => Ok(json_error(err, http::StatusCode::INTERNAL_SERVER_ERROR).into_response())
+    }
+}
+
+pub async fn remove_api_key_v1(
+    account_id: String,
+    exchange: ExchangeName,
+    if_match: Option<String>,
+    account_repo: Arc<AccountRepo>,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    let expected_version = match expected_version(if_match) {
+        Ok(version) => version,
+        Err(err) => return Ok(json_error(err, http::StatusCode::BAD_REQUEST).into_response()),
+    };
+    match account_repo.remove_key(&AccountId(account_id), Some(&exchange), expected_version).await {
+        Ok(()) => Ok(http::StatusCode::NO_CONTENT.into_response()),
+        Err(err) => Ok(json_error(err, http::StatusCode::INTERNAL_SERVER_ERROR).into_response())
+    }
+}
+
+pub async fn generate_key_pair_v1(
+    account_id: String,
+    exchange: ExchangeName,
+    if_match: Option<String>,
+    account_repo: Arc<AccountRepo>,
+    key_pair_request_dto: KeyPairRequestDto,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    let expected_version = match expected_version(if_match) {
+        Ok(version) => version,
+        Err(err) => return Ok(json_error(err, http::StatusCode::BAD_REQUEST).into_response()),
+    };
+    let key_type = key_pair_request_dto.key_type;
+    match account_repo.generate_key_pair(&AccountId(account_id), &exchange, key_type, expected_version).await {
+        Ok((uid, version, public_key)) => Ok(warp::reply::with_header(
+            warp::reply::with_status(
+                warp::reply::json(&PublicKeyDto { uid, exchange, key_type, public_key }),
+                http::StatusCode::CREATED,
+            ),
+            "ETag",
+            etag(version),
+        ).into_response()),
+        Err(err) => Ok(json_error(err, http::StatusCode::BAD_REQUEST).into_response())
+    }
+}
+
+pub async fn sign_request_v1(
+    account_id: String,
+    exchange: ExchangeName,
+    account_repo: Arc<AccountRepo>,
+    sign_request_dto: SignRequestDto,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    match account_repo.sign_request(
+        &AccountId(account_id),
+        &exchange,
+        sign_request_dto.into(),
+    ).await {
+        Ok(signed) => Ok(warp::reply::json(&SignedRequestDto::from(signed)).into_response()),
+        Err(err) => Ok(json_error(err, http::StatusCode::BAD_REQUEST).into_response())
+    }
+}
+
+pub async fn publish_executor_event_v1(
+    account_id: String,
+    hub: Arc<EventHub>,
+    response: ExecutorResponse,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    match hub.publish_response(&account_id, &response) {
+        Ok(seq) => Ok(warp::reply::with_status(
+            warp::reply::json(&PublishedEventDto { seq }),
+            http::StatusCode::ACCEPTED,
+        ).into_response()),
+        Err(err) => Ok(json_error(err, http::StatusCode::INTERNAL_SERVER_ERROR).into_response())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn read(reply: WithStatus<Json>) -> (http::StatusCode, String) {
+        let response = reply.into_response();
+        let status = response.status();
+        let body = warp::hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn account_errors_get_their_own_status() {
+        let err = AccountError::NotFound("alice".to_string()).into();
+        let (status, body) = read(json_error(err, http::StatusCode::BAD_REQUEST)).await;
+        assert_eq!(status, http::StatusCode::NOT_FOUND);
+        assert!(body.contains("Account with uid \\\"alice\\\" not found"));
+
+        let err = AccountError::NoApiKey("alice".to_string()).into();
+        let (status, _) = read(json_error(err, http::StatusCode::BAD_REQUEST)).await;
+        assert_eq!(status, http::StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn database_failures_are_generic_500s() {
+        let err = anyhow::Error::from(sqlx::Error::PoolTimedOut);
+        let (status, body) = read(json_error(err, http::StatusCode::BAD_REQUEST)).await;
+        assert_eq!(status, http::StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(body, r#"{"error":"Internal server error"}"#);
+    }
+
+    #[tokio::test]
+    async fn unexpected_errors_keep_their_details_out_of_500s() {
+        let (status, body) = read(json_error(anyhow!("cipher failed"), http::StatusCode::INTERNAL_SERVER_ERROR)).await;
+        assert_eq!(status, http::StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(!body.contains("cipher"));
+
+        let (status, body) = read(json_error(anyhow!("invalid cursor"), http::StatusCode::BAD_REQUEST)).await;
+        assert_eq!(status, http::StatusCode::BAD_REQUEST);
+        assert!(body.contains("invalid cursor"));
+    }
+}
@@ -0,0 +1,547 @@
+use warp::{http, Filter, Reply};
+use warp::http::header::{CONTENT_TYPE, ETAG};
+use warp::hyper::Body;
+use warp::path::FullPath;
+use warp::reply::Response;
+use sqlx::{Pool, Postgres};
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use crate::crypto::CredentialCipher;
+use crate::dto::ErrorDto;
+use crate::rate_limit::RateLimiter;
+use std::future::Future;
+use std::sync::Arc;
+
+const REPLAYED_HEADER: &str = "Idempotent-Replayed";
+/// Length of the `key` column.
+const KEY_MAX_LEN: usize = 255;
+
+/// Responses of mutating requests, keyed by the caller and its `Idempotency-Key`.
+/// Bodies can carry api keys and signing material, so they are only kept encrypted with the
+/// credential cipher; without a master key only the status of a completed request is kept.
+pub struct IdempotencyStore {
+    pg_pool: Pool<Postgres>,
+    ttl: Duration,
+    /// How long a claim may stay in progress before another request with its key takes it over,
+    /// so a request that never completed, e.g. in a crash, doesn't block its key for the whole TTL.
+    lease: Duration,
+    cipher: CredentialCipher,
+}
+
+enum Claim {
+    /// The key is ours, under the claim made at this time.
+    Claimed(DateTime<Utc>),
+    Taken(StoredResponse),
+}
+
+struct StoredResponse {
+    fingerprint: String,
+    status_code: Option<i32>,
+    content_type: Option<String>,
+    etag: Option<String>,
+    body: Option<Vec<u8>>,
+}
+
+impl IdempotencyStore {
+    pub fn new(pg_pool: Pool<Postgres>, ttl_secs: i64, lease_secs: i64, cipher: CredentialCipher) -> IdempotencyStore {
+        IdempotencyStore { pg_pool, ttl: Duration::seconds(ttl_secs), lease: Duration::seconds(lease_secs), cipher }
+    }
+
+    fn body_aad(caller: &str, key: &str) -> Vec<u8> {
+        format!("idempotency:{}:{}", caller, key).into_bytes()
+    }
+
+    /// Claims `key` for a new request, taking over records past their TTL and claims past their lease.
+    async fn claim(&self, caller: &str, key: &str, fingerprint: &str) -> Result<Claim, anyhow::Error> {
+        loop {
+            let now = Utc::now();
+            let claimed = sqlx::query!(
+            r#"INSERT INTO test.public.idempotency_keys (caller, key, fingerprint, created_at)
+             VALUES ($1, $2, $3, $4)
+             ON CONFLICT (caller, key) DO UPDATE
+             SET fingerprint = EXCLUDED.fingerprint, created_at = EXCLUDED.created_at,
+                 status_code = NULL, content_type = NULL, etag = NULL, body = NULL
+             WHERE idempotency_keys.created_at < $5
+                OR (idempotency_keys.status_code IS NULL AND idempotency_keys.created_at < $6)
+             RETURNING created_at;"#,
+            caller,
+            key,
+            fingerprint,
+            now,
+            now - self.ttl,
+            now - self.lease,
+        )
+                .fetch_optional(&self.pg_pool)
+                .await?;
+            if let Some(claimed) = claimed {
+                return Ok(Claim::Claimed(claimed.created_at));
+            }
+
+            let stored = sqlx::query_as!(
+            StoredResponse,
+            r#"SELECT fingerprint, status_code, content_type, etag, body FROM test.public.idempotency_keys
+             WHERE caller = $1 AND key = $2;"#,
+            caller,
+            key,
+        )
+                .fetch_optional(&self.pg_pool)
+                .await?;
+            // Released since the insert; claim it again.
+            if let Some(stored) = stored {
+                return Ok(Claim::Taken(stored));
+            }
+        }
+    }
+
+    async fn complete(
+        &self,
+        caller: &str,
+        key: &str,
+        claimed_at: DateTime<Utc>,
+        response: &http::response::Parts,
+        body: &[u8],
+    ) -> Result<(), anyhow::Error> {
+        let header = |name: http::header::HeaderName| response.headers.get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.to_string());
+        let status_code = response.status.as_u16() as i32;
+        let sealed = if self.cipher.is_configured() {
+            Some(self.cipher.encrypt(body, &Self::body_aad(caller, key)).await?)
+        } else {
+            None
+        };
+        sqlx::query!(
+        r#"UPDATE test.public.idempotency_keys SET status_code = $4, content_type = $5, etag = $6, body = $7
+         WHERE caller = $1 AND key = $2 AND created_at = $3;"#,
+        caller,
+        key,
+        claimed_at,
+        status_code,
+        header(CONTENT_TYPE),
+        header(ETAG),
+        sealed,
+    )
+            .execute(&self.pg_pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn release(&self, caller: &str, key: &str, claimed_at: DateTime<Utc>) -> Result<(), anyhow::Error> {
+        sqlx::query!(
+        r#"DELETE FROM test.public.idempotency_keys WHERE caller = $1 AND key = $2 AND created_at = $3;"#,
+        caller,
+        key,
+        claimed_at,
+    )
+            .execute(&self.pg_pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Deletes the records past their TTL.
+    pub async fn purge_expired(&self) -> Result<u64, anyhow::Error> {
+        let expired_before = Utc::now() - self.ttl;
+        let purged = sqlx::query!(
+        r#"DELETE FROM test.public.idempotency_keys WHERE created_at < $1;"#,
+        expired_before,
+    )
+            .execute(&self.pg_pool)
+            .await?
+            .rows_affected();
+        Ok(purged)
+    }
+
+    /// Runs `purge_expired` every `interval` for as long as the process lives.
+    pub fn spawn_purge_task(self: Arc<Self>, interval: std::time::Duration) {
+        tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(interval);
+            loop {
+                ticks.tick().await;
+                if let Err(err) = self.purge_expired().await {
+                    log::error!("purging idempotency keys failed: {}", err);
+                }
+            }
+        });
+    }
+}
+
+/// Idempotency context of a single request.
+#[derive(Clone)]
+pub struct Idempotency {
+    store: Arc<IdempotencyStore>,
+    /// Keys of different callers never collide, so one can't replay another's response.
+    caller: String,
+    key: Option<String>,
+    method: http::Method,
+    path: String,
+}
+
+/// Scopes keys to the caller as `limiter` identifies it.
+pub fn idempotency(
+    store: Arc<IdempotencyStore>,
+    limiter: Arc<RateLimiter>,
+) -> impl Filter<Extract=(Idempotency, ), Error=warp::Rejection> + Clone {
+    warp::any()
+        .map(move || store.clone())
+        .and(warp::any().map(move || limiter.clone()))
+        .and(warp::header::optional::<String>("authorization"))
+        .and(warp::addr::remote())
+        .and(warp::header::optional::<String>("idempotency-key"))
+        .and(warp::method())
+        .and(warp::path::full())
+        .map(|store, limiter: Arc<RateLimiter>, authorization: Option<String>, remote, key, method, path: FullPath| {
+            Idempotency {
+                store,
+                caller: limiter.caller_id(authorization.as_deref(), remote),
+                key,
+                method,
+                path: path.as_str().to_string(),
+            }
+        })
+}
+
+/// Bytes of a request body used to detect a key being reused for a different request.
+pub fn body_of<T: Serialize>(body: &T) -> Vec<u8> {
+    serde_json::to_vec(body).unwrap_or_default()
+}
+
+fn error_response(error: String, status: http::StatusCode) -> Response {
+    warp::reply::with_status(warp::reply::json(&ErrorDto { error }), status).into_response()
+}
+
+/// The details of failures are logged, not returned.
+fn internal_error() -> Response {
+    error_response("Internal server error".to_string(), http::StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+impl Idempotency {
+    fn fingerprint(&self, body: &[u8]) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(self.method.as_str().as_bytes());
+        hasher.update(b" ");
+        hasher.update(self.path.as_bytes());
+        hasher.update(b"\n");
+        hasher.update(body);
+        hex::encode(hasher.finalize())
+    }
+
+    /// Runs `handler` once per key: retries with the same key and body get the stored response back.
+    pub async fn run<R, F>(self, body: Vec<u8>, handler: F) -> Result<Response, warp::Rejection>
+        where
+            F: Future<Output=Result<R, warp::Rejection>>,
+            R: Reply,
+    {
+        let key = match &self.key {
+            Some(key) => key.clone(),
+            None => return handler.await.map(Reply::into_response),
+        };
+        if key.chars().count() > KEY_MAX_LEN {
+            return Ok(error_response(
+                format!("Idempotency-Key must be at most {} characters long", KEY_MAX_LEN),
+                http::StatusCode::BAD_REQUEST,
+            ));
+        }
+        let fingerprint = self.fingerprint(&body);
+
+        let claimed_at = match self.store.claim(&self.caller, &key, &fingerprint).await {
+            Ok(Claim::Claimed(claimed_at)) => claimed_at,
+            Ok(Claim::Taken(stored)) => {
+                if stored.fingerprint != fingerprint {
+                    return Ok(error_response(
+                        format!("Idempotency key \"{}\" was already used for a different request", key),
+                        http::StatusCode::UNPROCESSABLE_ENTITY,
+                    ));
+                }
+                return Ok(match (stored.status_code, stored.body) {
+                    (Some(status_code), Some(sealed)) => {
+                        let aad = IdempotencyStore::body_aad(&self.caller, &key);
+                        match self.store.cipher.decrypt(&sealed, &aad).await {
+                            Ok(body) => replay(status_code, stored.content_type, stored.etag, body),
+                            Err(err) => {
+                                log::error!("{}", err);
+                                internal_error()
+                            }
+                        }
+                    }
+                    (Some(status_code), None) => error_response(
+                        format!(
+                            "Request with idempotency key \"{}\" already completed with status {}; \
+                             its response is only kept when a master key is configured",
+                            key, status_code,
+                        ),
+                        http::StatusCode::CONFLICT,
+                    ),
+                    (None, _) => error_response(
+                        format!("Request with idempotency key \"{}\" is still in progress", key),
+                        http::StatusCode::CONFLICT,
+                    ),
+                });
+            }
+            Err(err) => {
+                log::error!("{}", err);
+                return Ok(internal_error());
+            }
+        };
+
+        let response = match handler.await {
+            Ok(reply) => reply.into_response(),
+            Err(rejection) => {
+                if let Err(err) = self.store.release(&self.caller, &key, claimed_at).await {
+                    log::error!("{}", err);
+                }
+                return Err(rejection);
+            }
+        };
+
+        let (parts, body) = response.into_parts();
+        let body = match warp::hyper::body::to_bytes(body).await {
+            Ok(body) => body,
+            Err(err) => {
+                log::error!("{}", err);
+                return Ok(internal_error());
+            }
+        };
+        let stored = if parts.status.is_server_error() {
+            self.store.release(&self.caller, &key, claimed_at).await
+        } else {
+            self.store.complete(&self.caller, &key, claimed_at, &parts, &body).await
+        };
+        if let Err(err) = stored {
+            log::error!("{}", err);
+        }
+        Ok(Response::from_parts(parts, Body::from(body)))
+    }
+}
+
+fn replay(status_code: i32, content_type: Option<String>, etag: Option<String>, body: Vec<u8>) -> Response {
+    let mut response = http::Response::builder()
+        .status(status_code as u16)
+        .header(REPLAYED_HEADER, "true");
+    if let Some(content_type) = content_type {
+        response = response.header(CONTENT_TYPE, content_type);
+    }
+    if let Some(etag) = etag {
+        response = response.header(ETAG, etag);
+    }
+    match response.body(Body::from(body)) {
+        Ok(response) => response,
+        Err(err) => {
+            log::error!("{}", err);
+            internal_error()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::crypto::KEY_LEN;
+    use crate::db::{db_connect, db_migrate};
+    use crate::key_provider::LocalKeyProvider;
+    use std::env;
+    use std::net::SocketAddr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::sync::Notify;
+    use warp::http::StatusCode;
+    use warp::hyper::body::Bytes;
+    use warp::Rejection;
+
+    /// A store with the given TTL and lease, or `None` without a database.
+    async fn store(ttl_secs: i64, lease_secs: i64) -> Option<Arc<IdempotencyStore>> {
+        let database_url = match env::var("DATABASE_URL") {
+            Ok(database_url) => database_url,
+            Err(_) => {
+                eprintln!("DATABASE_URL is not set, skipping idempotency test");
+                return None;
+            }
+        };
+        let pg_pool = db_connect(&database_url).await.unwrap();
+        db_migrate(&pg_pool).await.unwrap();
+        let provider = LocalKeyProvider::new(&base64::encode([3u8; KEY_LEN])).unwrap();
+        let cipher = CredentialCipher::new(Some(Arc::new(provider)));
+        Some(Arc::new(IdempotencyStore::new(pg_pool, ttl_secs, lease_secs, cipher)))
+    }
+
+    fn unique_key(name: &str) -> String {
+        format!("{}-{}", name, chrono::Utc::now().timestamp_micros())
+    }
+
+    /// `POST /things`, answering 201 with the number of times the handler ran.
+    /// With `gate`, the handler waits for it before answering.
+    fn route(
+        store: Arc<IdempotencyStore>,
+        calls: Arc<AtomicUsize>,
+        gate: Option<Arc<Notify>>,
+    ) -> impl Filter<Extract=(Response, ), Error=Rejection> + Clone {
+        let limiter = Arc::new(RateLimiter::new(&Config::from_env()));
+        warp::post()
+            .and(warp::path("things"))
+            .and(idempotency(store, limiter))
+            .and(warp::body::bytes())
+            .and_then(move |idempotency: Idempotency, body: Bytes| {
+                let (calls, gate) = (calls.clone(), gate.clone());
+                idempotency.run(body.to_vec(), async move {
+                    let call = calls.fetch_add(1, Ordering::SeqCst) + 1;
+                    if let Some(gate) = gate {
+                        gate.notified().await;
+                    }
+                    Ok::<_, Rejection>(warp::reply::with_status(format!("call {}", call), StatusCode::CREATED))
+                })
+            })
+    }
+
+    fn request(key: &str, body: &str) -> warp::test::RequestBuilder {
+        request_from(key, body, ([10, 0, 0, 1], 4000).into())
+    }
+
+    fn request_from(key: &str, body: &str, remote: SocketAddr) -> warp::test::RequestBuilder {
+        warp::test::request()
+            .method("POST")
+            .path("/things")
+            .remote_addr(remote)
+            .header("idempotency-key", key)
+            .body(body)
+    }
+
+    #[tokio::test]
+    async fn replays_the_first_response() {
+        let store = match store(3600, 60).await {
+            Some(store) => store,
+            None => return,
+        };
+        let calls = Arc::new(AtomicUsize::new(0));
+        let route = route(store, calls.clone(), None);
+        let key = unique_key("replay");
+
+        let first = request(&key, "{}").reply(&route).await;
+        assert_eq!(first.status(), StatusCode::CREATED);
+        assert!(first.headers().get("idempotent-replayed").is_none());
+
+        let retry = request(&key, "{}").reply(&route).await;
+        assert_eq!(retry.status(), StatusCode::CREATED);
+        assert_eq!(retry.headers()["idempotent-replayed"], "true");
+        assert_eq!(retry.body(), "call 1");
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn rejects_a_key_reused_for_a_different_body() {
+        let store = match store(3600, 60).await {
+            Some(store) => store,
+            None => return,
+        };
+        let calls = Arc::new(AtomicUsize::new(0));
+        let route = route(store, calls.clone(), None);
+        let key = unique_key("mismatch");
+
+        assert_eq!(request(&key, r#"{"a":1}"#).reply(&route).await.status(), StatusCode::CREATED);
+        let reused = request(&key, r#"{"a":2}"#).reply(&route).await;
+        assert_eq!(reused.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn answers_409_while_the_first_request_is_in_progress() {
+        let store = match store(3600, 60).await {
+            Some(store) => store,
+            None => return,
+        };
+        let calls = Arc::new(AtomicUsize::new(0));
+        let gate = Arc::new(Notify::new());
+        let route = route(store, calls.clone(), Some(gate.clone()));
+        let key = unique_key("in-progress");
+
+        let first = tokio::spawn({
+            let (key, route) = (key.clone(), route.clone());
+            async move { request(&key, "{}").reply(&route).await }
+        });
+        while calls.load(Ordering::SeqCst) == 0 {
+            tokio::task::yield_now().await;
+        }
+        let concurrent = request(&key, "{}").reply(&route).await;
+        assert_eq!(concurrent.status(), StatusCode::CONFLICT);
+
+        gate.notify_one();
+        assert_eq!(first.await.unwrap().status(), StatusCode::CREATED);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn takes_over_claims_past_their_lease() {
+        let store = match store(3600, 0).await {
+            Some(store) => store,
+            None => return,
+        };
+        let calls = Arc::new(AtomicUsize::new(0));
+        let gate = Arc::new(Notify::new());
+        let route = route(store, calls.clone(), Some(gate.clone()));
+        let key = unique_key("lease");
+
+        // The first request stands in for one that crashed: it never completes.
+        let abandoned = tokio::spawn({
+            let (key, route) = (key.clone(), route.clone());
+            async move { request(&key, "{}").reply(&route).await }
+        });
+        while calls.load(Ordering::SeqCst) == 0 {
+            tokio::task::yield_now().await;
+        }
+        let retry = tokio::spawn(async move { request(&key, "{}").reply(&route).await });
+        while calls.load(Ordering::SeqCst) == 1 {
+            tokio::task::yield_now().await;
+        }
+        // The retry may not be waiting on the gate yet; the stored permit covers that.
+        gate.notify_waiters();
+        gate.notify_one();
+        assert_eq!(retry.await.unwrap().status(), StatusCode::CREATED);
+        abandoned.abort();
+    }
+
+    #[tokio::test]
+    async fn scopes_keys_to_the_caller() {
+        let store = match store(3600, 60).await {
+            Some(store) => store,
+            None => return,
+        };
+        let calls = Arc::new(AtomicUsize::new(0));
+        let route = route(store, calls.clone(), None);
+        let key = unique_key("caller");
+
+        let first = request_from(&key, "{}", ([10, 0, 0, 1], 4000).into()).reply(&route).await;
+        let other = request_from(&key, "{}", ([10, 0, 0, 2], 4000).into()).reply(&route).await;
+        assert_eq!(first.body(), "call 1");
+        assert_eq!(other.body(), "call 2");
+        assert!(other.headers().get("idempotent-replayed").is_none());
+    }
+
+    #[tokio::test]
+    async fn rejects_keys_longer_than_the_column() {
+        let store = match store(3600, 60).await {
+            Some(store) => store,
+            None => return,
+        };
+        let calls = Arc::new(AtomicUsize::new(0));
+        let route = route(store, calls.clone(), None);
+
+        let response = request(&"k".repeat(256), "{}").reply(&route).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn runs_requests_again_past_the_ttl() {
+        let store = match store(0, 60).await {
+            Some(store) => store,
+            None => return,
+        };
+        let calls = Arc::new(AtomicUsize::new(0));
+        let route = route(store, calls.clone(), None);
+        let key = unique_key("expired");
+
+        assert_eq!(request(&key, "{}").reply(&route).await.body(), "call 1");
+        let later = request(&key, "{}").reply(&route).await;
+        assert_eq!(later.body(), "call 2");
+        assert!(later.headers().get("idempotent-replayed").is_none());
+    }
+}
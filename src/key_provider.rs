@@ -0,0 +1,226 @@
+use anyhow::{anyhow, bail};
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::json;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use crate::config::KeyProviderConfig;
+use crate::crypto::{self, KEY_LEN};
+use crate::secret::Secret;
+use zeroize::Zeroizing;
+
+/// Holder of the master key, wrapping the data keys `CredentialCipher` encrypts values with.
+#[async_trait]
+pub trait KeyProvider: Send + Sync {
+    async fn wrap(&self, data_key: &[u8]) -> Result<Vec<u8>, anyhow::Error>;
+    async fn unwrap(&self, wrapped: &[u8]) -> Result<Vec<u8>, anyhow::Error>;
+}
+
+/// Builds the configured provider; `None` when no master key is configured at all.
+pub fn from_config(config: &KeyProviderConfig) -> Result<Option<Arc<dyn KeyProvider>>, anyhow::Error> {
+    match config {
+        KeyProviderConfig::Local { master_key: None, master_key_file: None } => Ok(None),
+        KeyProviderConfig::Local { master_key: Some(master_key), .. } => {
+            Ok(Some(Arc::new(LocalKeyProvider::new(master_key.expose())?)))
+        }
+        KeyProviderConfig::Local { master_key_file: Some(path), .. } => {
+            Ok(Some(Arc::new(LocalKeyProvider::from_file(path)?)))
+        }
+        KeyProviderConfig::Vault { addr, token, mount, key_name } => {
+            Ok(Some(Arc::new(VaultTransitProvider::new(addr, token, mount, key_name))))
+        }
+    }
+}
+
+/// Master key held in process memory, read from config or a key file as base64.
+pub struct LocalKeyProvider {
+    master_key: Zeroizing<Vec<u8>>,
+}
+
+impl LocalKeyProvider {
+    pub fn new(master_key: &str) -> Result<LocalKeyProvider, anyhow::Error> {
+        let master_key = Zeroizing::new(
+            base64::decode(master_key.trim()).map_err(|_| anyhow!("master key must be base64"))?,
+        );
+        if master_key.len() != KEY_LEN {
+            bail!("master key must be {} bytes long", KEY_LEN);
+        }
+        Ok(LocalKeyProvider { master_key })
+    }
+
+    pub fn from_file(path: &Path) -> Result<LocalKeyProvider, anyhow::Error> {
+        let master_key = Zeroizing::new(fs::read_to_string(path)
+            .map_err(|err| anyhow!("can't read master key file {}: {}", path.display(), err))?);
+        LocalKeyProvider::new(&master_key)
+    }
+}
+
+#[async_trait]
+impl KeyProvider for LocalKeyProvider {
+    async fn wrap(&self, data_key: &[u8]) -> Result<Vec<u8>, anyhow::Error> {
+        crypto::aes_seal(&self.master_key, data_key, b"data-key")
+    }
+
+    async fn unwrap(&self, wrapped: &[u8]) -> Result<Vec<u8>, anyhow::Error> {
+        crypto::aes_open(&self.master_key, wrapped, b"data-key")
+    }
+}
+
+/// HashiCorp Vault Transit: data keys are encrypted by a named key that never leaves Vault.
+pub struct VaultTransitProvider {
+    client: reqwest::Client,
+    token: Secret,
+    encrypt_url: String,
+    decrypt_url: String,
+}
+
+#[derive(Deserialize)]
+struct VaultResponse<T> {
+    data: T,
+}
+
+#[derive(Deserialize)]
+struct VaultCiphertext {
+    ciphertext: String,
+}
+
+#[derive(Deserialize)]
+struct VaultPlaintext {
+    plaintext: String,
+}
+
+impl VaultTransitProvider {
+    pub fn new(addr: &str, token: &Secret, mount: &str, key_name: &str) -> VaultTransitProvider {
+        let base = format!("{}/v1/{}", addr.trim_end_matches('/'), mount.trim_matches('/'));
+        VaultTransitProvider {
+            client: reqwest::Client::new(),
+            token: token.clone(),
+            encrypt_url: format!("{}/encrypt/{}", base, key_name),
+            decrypt_url: format!("{}/decrypt/{}", base, key_name),
+        }
+    }
+
+    async fn call<T: serde::de::DeserializeOwned>(
+        &self,
+        url: &str,
+        body: serde_json::Value,
+    ) -> Result<T, anyhow::Error> {
+        let response = self.client
+            .post(url)
+            .header("X-Vault-Token", self.token.expose())
+            .json(&body)
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            bail!("vault transit request failed with status {}", response.status());
+        }
+        Ok(response.json::<VaultResponse<T>>().await?.data)
+    }
+}
+
+#[async_trait]
+impl KeyProvider for VaultTransitProvider {
+    async fn wrap(&self, data_key: &[u8]) -> Result<Vec<u8>, anyhow::Error> {
+        let result: VaultCiphertext = self
+            .call(&self.encrypt_url, json!({ "plaintext": base64::encode(data_key) }))
+            .await?;
+        Ok(result.ciphertext.into_bytes())
+    }
+
+    async fn unwrap(&self, wrapped: &[u8]) -> Result<Vec<u8>, anyhow::Error> {
+        let ciphertext = std::str::from_utf8(wrapped).map_err(|_| anyhow!("invalid vault ciphertext"))?;
+        let result: VaultPlaintext = self
+            .call(&self.decrypt_url, json!({ "ciphertext": ciphertext }))
+            .await?;
+        let plaintext = Secret::from(result.plaintext);
+        base64::decode(plaintext.expose()).map_err(|_| anyhow!("vault returned invalid base64"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::CredentialCipher;
+    use std::collections::HashMap;
+    use std::net::SocketAddr;
+    use warp::Filter;
+
+    const TOKEN: &str = "s.test-token";
+
+    /// Minimal stand-in for Vault's transit engine: "encrypts" by tagging the base64 plaintext.
+    fn start_transit_stub() -> SocketAddr {
+        let encrypt = warp::path!("v1" / "transit" / "encrypt" / String)
+            .and(warp::body::json())
+            .map(|key: String, body: HashMap<String, String>| {
+                warp::reply::json(&json!({ "data": { "ciphertext": format!("vault:v1:{}:{}", key, body["plaintext"]) } }))
+            });
+        let decrypt = warp::path!("v1" / "transit" / "decrypt" / String)
+            .and(warp::body::json())
+            .map(|key: String, body: HashMap<String, String>| {
+                let prefix = format!("vault:v1:{}:", key);
+                match body["ciphertext"].strip_prefix(&prefix) {
+                    Some(plaintext) => warp::reply::with_status(
+                        warp::reply::json(&json!({ "data": { "plaintext": plaintext } })),
+                        warp::http::StatusCode::OK,
+                    ),
+                    None => warp::reply::with_status(
+                        warp::reply::json(&json!({ "errors": ["invalid ciphertext"] })),
+                        warp::http::StatusCode::BAD_REQUEST,
+                    ),
+                }
+            });
+        let routes = warp::post()
+            .and(warp::header::exact("x-vault-token", TOKEN))
+            .and(encrypt.or(decrypt));
+        let (addr, server) = warp::serve(routes).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        addr
+    }
+
+    fn vault(addr: SocketAddr, token: &str, key_name: &str) -> VaultTransitProvider {
+        VaultTransitProvider::new(
+            &format!("http://{}/", addr),
+            &Secret::from(token.to_string()),
+            "/transit/",
+            key_name,
+        )
+    }
+
+    #[tokio::test]
+    async fn local_provider_wraps_and_unwraps() {
+        let provider = LocalKeyProvider::new(&base64::encode([1u8; KEY_LEN])).unwrap();
+        let wrapped = provider.wrap(&[2u8; KEY_LEN]).await.unwrap();
+        assert_eq!(provider.unwrap(&wrapped).await.unwrap(), vec![2u8; KEY_LEN]);
+    }
+
+    #[test]
+    fn local_provider_rejects_bad_master_keys() {
+        assert!(LocalKeyProvider::new("not base64!").is_err());
+        assert!(LocalKeyProvider::new(&base64::encode([1u8; 16])).is_err());
+    }
+
+    #[tokio::test]
+    async fn vault_transit_wraps_and_unwraps() {
+        let provider = vault(start_transit_stub(), TOKEN, "try-api");
+        let wrapped = provider.wrap(&[3u8; KEY_LEN]).await.unwrap();
+        assert!(String::from_utf8(wrapped.clone()).unwrap().starts_with("vault:v1:try-api:"));
+        assert_eq!(provider.unwrap(&wrapped).await.unwrap(), vec![3u8; KEY_LEN]);
+    }
+
+    #[tokio::test]
+    async fn vault_transit_backs_credential_cipher() {
+        let cipher = CredentialCipher::new(Some(Arc::new(vault(start_transit_stub(), TOKEN, "try-api"))));
+        let sealed = cipher.encrypt(b"secret", b"aad").await.unwrap();
+        assert_eq!(cipher.decrypt(&sealed, b"aad").await.unwrap(), b"secret");
+    }
+
+    #[tokio::test]
+    async fn vault_transit_fails_on_error_status() {
+        let addr = start_transit_stub();
+        assert!(vault(addr, "s.wrong-token", "try-api").wrap(&[3u8; KEY_LEN]).await.is_err());
+
+        let wrapped = vault(addr, TOKEN, "try-api").wrap(&[3u8; KEY_LEN]).await.unwrap();
+        assert!(vault(addr, TOKEN, "other").unwrap(&wrapped).await.is_err());
+    }
+}
@@ -0,0 +1,51 @@
+//! Models and DTOs of the API are always built, so clients can share them; the service itself
+//! needs the default `server` feature.
+
+// `OpgModel` derives implement the trait inside a constant, which newer compilers warn about.
+#![allow(non_local_definitions)]
+
+#[cfg(feature = "server")]
+pub mod account;
+#[cfg(feature = "server")]
+pub mod api;
+#[cfg(feature = "server")]
+pub mod config;
+#[cfg(feature = "server")]
+pub mod crypto;
+pub mod models;
+pub mod dto;
+#[cfg(feature = "server")]
+pub mod events;
+#[cfg(feature = "server")]
+pub mod grpc;
+#[cfg(feature = "server")]
+pub mod db;
+#[cfg(feature = "server")]
+pub mod docs;
+#[cfg(feature = "server")]
+pub mod exchange_auth;
+#[cfg(feature = "server")]
+pub mod handlers;
+#[cfg(feature = "server")]
+pub mod idempotency;
+#[cfg(feature = "server")]
+pub mod key_provider;
+#[cfg(feature = "server")]
+pub mod keys;
+#[cfg(feature = "server")]
+pub mod nonce;
+#[cfg(feature = "server")]
+pub mod payload;
+#[cfg(feature = "server")]
+pub mod rate_limit;
+#[cfg(feature = "server")]
+pub mod rejections;
+#[cfg(feature = "server")]
+pub mod routes;
+pub mod secret;
+#[cfg(feature = "server")]
+pub mod stream;
+#[cfg(feature = "server")]
+pub mod transfer;
+#[cfg(feature = "server")]
+pub mod validation;
@@ -1,11 +1,13 @@
 use std::convert::TryFrom;
 use std::fmt;
+use std::str::FromStr;
 
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use uuid::Uuid;
 use opg::*;
+use crate::secret::Secret;
 
 #[derive(Clone, Debug, Serialize, Deserialize, Hash, Eq, PartialEq, OpgModel)]
 #[opg("accountid string", format = "id", example = "abcd0001")]
@@ -53,12 +55,177 @@ impl TryFrom<String> for ExchangeName {
     }
 }
 
+impl FromStr for ExchangeName {
+    type Err = ExchangeConvertError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        ExchangeName::ALL
+            .iter()
+            .find(|exchange| exchange.to_string().eq_ignore_ascii_case(value))
+            .cloned()
+            .ok_or(ExchangeConvertError)
+    }
+}
+
+impl ExchangeName {
+    pub const ALL: [ExchangeName; 8] = [
+        ExchangeName::Binance,
+        ExchangeName::HitBtc,
+        ExchangeName::Kraken,
+        ExchangeName::Okex,
+        ExchangeName::Kucoin,
+        ExchangeName::Bitfinex,
+        ExchangeName::Huobi,
+        ExchangeName::Quoine,
+    ];
+}
+
 impl fmt::Display for ExchangeName {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{:?}", self)
     }
 }
 
+/// How an account signs: with the shared-secret `sign_key` or an asymmetric private key.
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, Hash, Eq, PartialEq, OpgModel)]
+#[serde(rename_all = "lowercase")]
+#[opg("KeyType")]
+pub enum KeyType {
+    Hmac,
+    Ed25519,
+    Rsa,
+}
+
+impl FromStr for KeyType {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "hmac" => Ok(KeyType::Hmac),
+            "ed25519" => Ok(KeyType::Ed25519),
+            "rsa" => Ok(KeyType::Rsa),
+            _ => Err(anyhow::anyhow!("unknown key type \"{}\"", value)),
+        }
+    }
+}
+
+impl fmt::Display for KeyType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            KeyType::Hmac => write!(f, "hmac"),
+            KeyType::Ed25519 => write!(f, "ed25519"),
+            KeyType::Rsa => write!(f, "rsa"),
+        }
+    }
+}
+
+fn kucoin_key_version() -> u8 {
+    2
+}
+
+/// Credentials beyond the api and sign key, shaped by the exchange they belong to.
+#[derive(Clone, Debug, Serialize, Deserialize, Hash, Eq, PartialEq, OpgModel)]
+#[serde(tag = "exchange", rename_all = "camelCase")]
+#[opg("ExchangeCredentials")]
+pub enum ExchangeCredentials {
+    Binance { sub_account_id: Option<String> },
+    HitBtc {},
+    Kraken {},
+    Okex { passphrase: Secret },
+    Kucoin {
+        passphrase: Secret,
+        #[serde(default = "kucoin_key_version")]
+        key_version: u8,
+    },
+    Bitfinex { sub_account_id: Option<String> },
+    Huobi { account_id: Option<String> },
+    Quoine {},
+}
+
+impl ExchangeCredentials {
+    pub fn exchange(&self) -> ExchangeName {
+        match self {
+            ExchangeCredentials::Binance { .. } => ExchangeName::Binance,
+            ExchangeCredentials::HitBtc {} => ExchangeName::HitBtc,
+            ExchangeCredentials::Kraken {} => ExchangeName::Kraken,
+            ExchangeCredentials::Okex { .. } => ExchangeName::Okex,
+            ExchangeCredentials::Kucoin { .. } => ExchangeName::Kucoin,
+            ExchangeCredentials::Bitfinex { .. } => ExchangeName::Bitfinex,
+            ExchangeCredentials::Huobi { .. } => ExchangeName::Huobi,
+            ExchangeCredentials::Quoine {} => ExchangeName::Quoine,
+        }
+    }
+
+    /// Exchanges whose requests can't be authenticated without these credentials.
+    pub fn required_for(exchange: &ExchangeName) -> bool {
+        matches!(exchange, ExchangeName::Okex | ExchangeName::Kucoin)
+    }
+
+    pub fn passphrase(&self) -> Option<&str> {
+        match self {
+            ExchangeCredentials::Okex { passphrase } => Some(passphrase.expose()),
+            ExchangeCredentials::Kucoin { passphrase, .. } => Some(passphrase.expose()),
+            _ => None,
+        }
+    }
+}
+
+/// A field of a partial update: keep the stored value, replace it, or set it to NULL.
+#[derive(Clone, Debug, Default, Hash, Eq, PartialEq)]
+pub enum Patch<T> {
+    #[default]
+    Leave,
+    Set(T),
+    Clear,
+}
+
+impl<T> Patch<T> {
+    pub fn is_leave(&self) -> bool {
+        matches!(self, Patch::Leave)
+    }
+}
+
+impl<T> From<Option<Option<T>>> for Patch<T> {
+    /// Maps an absent field to `Leave` and an explicit `null` to `Clear`.
+    fn from(value: Option<Option<T>>) -> Self {
+        match value {
+            None => Patch::Leave,
+            Some(None) => Patch::Clear,
+            Some(Some(value)) => Patch::Set(value),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Hash, Eq, PartialEq)]
+pub struct AccountPatch {
+    pub api_key: Patch<Secret>,
+    pub sign_key: Patch<Secret>,
+    pub credentials: Patch<ExchangeCredentials>,
+}
+
+impl AccountPatch {
+    pub fn is_empty(&self) -> bool {
+        self.api_key.is_leave() && self.sign_key.is_leave() && self.credentials.is_leave()
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, Serialize, Deserialize, Hash, Eq, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum AccountSort {
+    #[default]
+    Uid,
+    CreatedAt,
+    UpdatedAt,
+}
+
+#[derive(Copy, Clone, Debug, Default, Serialize, Deserialize, Hash, Eq, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
 #[derive(Copy, Clone, Debug, Serialize, Deserialize, Hash, Eq, PartialEq)]
 pub enum OrderType {
     Market,
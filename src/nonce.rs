@@ -0,0 +1,229 @@
+use async_trait::async_trait;
+use chrono::Utc;
+use sqlx::{Pool, Postgres};
+use serde::{Deserialize, Serialize};
+use crate::models::{AccountId, ExchangeName};
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Mutex;
+use anyhow::anyhow;
+
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, Hash, Eq, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum NonceStyle {
+    MillisTimestamp,
+    MicrosTimestamp,
+    Counter,
+}
+
+impl NonceStyle {
+    pub fn default_for(exchange: &ExchangeName) -> NonceStyle {
+        match exchange {
+            ExchangeName::Bitfinex => NonceStyle::MicrosTimestamp,
+            _ => NonceStyle::MillisTimestamp,
+        }
+    }
+
+    /// Smallest value a fresh nonce of this style may take right now.
+    fn floor(self) -> i64 {
+        match self {
+            NonceStyle::MillisTimestamp => Utc::now().timestamp_millis(),
+            NonceStyle::MicrosTimestamp => Utc::now().timestamp_micros(),
+            NonceStyle::Counter => 1,
+        }
+    }
+}
+
+impl FromStr for NonceStyle {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "millis" => Ok(NonceStyle::MillisTimestamp),
+            "micros" => Ok(NonceStyle::MicrosTimestamp),
+            "counter" => Ok(NonceStyle::Counter),
+            _ => Err(anyhow!("unknown nonce style \"{}\", expected millis, micros or counter", value)),
+        }
+    }
+}
+
+/// Hands out strictly increasing nonces per `(uid, exchange)`, shared by every caller of the service.
+#[async_trait]
+pub trait NonceStore: Send + Sync {
+    async fn next(&self, uid: &AccountId, exchange: &ExchangeName, style: NonceStyle) -> Result<u64, anyhow::Error>;
+}
+
+pub struct PgNonceStore {
+    pg_pool: Pool<Postgres>,
+}
+
+impl PgNonceStore {
+    pub fn new(pg_pool: Pool<Postgres>) -> PgNonceStore {
+        PgNonceStore { pg_pool }
+    }
+}
+
+#[async_trait]
+impl NonceStore for PgNonceStore {
+    async fn next(&self, uid: &AccountId, exchange: &ExchangeName, style: NonceStyle) -> Result<u64, anyhow::Error> {
+        let result = sqlx::query!(
+        r#"INSERT INTO test.public.account_nonces (uid, exchange, value)
+         VALUES ($1, $2, $3)
+         ON CONFLICT (uid, exchange) DO UPDATE
+         SET value = GREATEST(account_nonces.value + 1, EXCLUDED.value)
+         RETURNING value;"#,
+        uid.0,
+        exchange.to_string(),
+        style.floor(),
+    )
+            .fetch_one(&self.pg_pool)
+            .await?;
+        Ok(result.value as u64)
+    }
+}
+
+/// Process-local counters, for deployments where a single instance owns the keys.
+#[derive(Default)]
+pub struct MemoryNonceStore {
+    last: Mutex<HashMap<(String, ExchangeName), i64>>,
+}
+
+#[async_trait]
+impl NonceStore for MemoryNonceStore {
+    async fn next(&self, uid: &AccountId, exchange: &ExchangeName, style: NonceStyle) -> Result<u64, anyhow::Error> {
+        let mut last = self.last.lock().map_err(|_| anyhow!("nonce store lock poisoned"))?;
+        let value = last.entry((uid.0.clone(), exchange.clone())).or_insert(0);
+        *value = (*value + 1).max(style.floor());
+        Ok(*value as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::sync::Arc;
+    use crate::db::{db_connect, db_migrate};
+
+    fn alice() -> AccountId {
+        AccountId("alice".to_string())
+    }
+
+    /// The database at `DATABASE_URL`, or `None` when it isn't set.
+    async fn pool() -> Option<Pool<Postgres>> {
+        let database_url = match env::var("DATABASE_URL") {
+            Ok(database_url) => database_url,
+            Err(_) => {
+                eprintln!("DATABASE_URL is not set, skipping nonce test");
+                return None;
+            }
+        };
+        let pg_pool = db_connect(&database_url).await.unwrap();
+        db_migrate(&pg_pool).await.unwrap();
+        Some(pg_pool)
+    }
+
+    fn unique_uid(name: &str) -> AccountId {
+        AccountId(format!("nonce-{}-{}", name, Utc::now().timestamp_micros()))
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn hands_out_unique_increasing_nonces_to_concurrent_callers() {
+        let store = Arc::new(MemoryNonceStore::default());
+        let callers: Vec<_> = (0..8)
+            .map(|_| {
+                let store = store.clone();
+                tokio::spawn(async move {
+                    let mut nonces = Vec::new();
+                    for _ in 0..50 {
+                        nonces.push(store.next(&alice(), &ExchangeName::Kraken, NonceStyle::Counter).await.unwrap());
+                    }
+                    nonces
+                })
+            })
+            .collect();
+        let mut all = Vec::new();
+        for caller in callers {
+            let nonces = caller.await.unwrap();
+            assert!(nonces.windows(2).all(|pair| pair[0] < pair[1]), "{:?}", nonces);
+            all.extend(nonces);
+        }
+        all.sort_unstable();
+        assert_eq!(all, (1..=400).collect::<Vec<u64>>());
+    }
+
+    #[tokio::test]
+    async fn keeps_increasing_when_the_clock_goes_backwards() {
+        let store = MemoryNonceStore::default();
+        let ahead = Utc::now().timestamp_millis() + 60 * 60 * 1000;
+        store.last.lock().unwrap().insert(("alice".to_string(), ExchangeName::Kraken), ahead);
+
+        let next = store.next(&alice(), &ExchangeName::Kraken, NonceStyle::MillisTimestamp).await.unwrap();
+        assert_eq!(next, ahead as u64 + 1);
+    }
+
+    #[tokio::test]
+    async fn starts_timestamp_nonces_at_the_clock() {
+        let store = MemoryNonceStore::default();
+        let before = Utc::now().timestamp_micros() as u64;
+        let next = store.next(&alice(), &ExchangeName::Bitfinex, NonceStyle::MicrosTimestamp).await.unwrap();
+        assert!(next >= before);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn postgres_hands_out_unique_increasing_nonces_to_concurrent_callers() {
+        let pg_pool = match pool().await {
+            Some(pg_pool) => pg_pool,
+            None => return,
+        };
+        let store = Arc::new(PgNonceStore::new(pg_pool));
+        let uid = unique_uid("concurrent");
+        let callers: Vec<_> = (0..8)
+            .map(|_| {
+                let (store, uid) = (store.clone(), uid.clone());
+                tokio::spawn(async move {
+                    let mut nonces = Vec::new();
+                    for _ in 0..25 {
+                        nonces.push(store.next(&uid, &ExchangeName::Kraken, NonceStyle::Counter).await.unwrap());
+                    }
+                    nonces
+                })
+            })
+            .collect();
+        let mut all = Vec::new();
+        for caller in callers {
+            let nonces = caller.await.unwrap();
+            assert!(nonces.windows(2).all(|pair| pair[0] < pair[1]), "{:?}", nonces);
+            all.extend(nonces);
+        }
+        all.sort_unstable();
+        assert_eq!(all, (1..=200).collect::<Vec<u64>>());
+    }
+
+    #[tokio::test]
+    async fn postgres_keeps_increasing_when_the_clock_goes_backwards() {
+        let pg_pool = match pool().await {
+            Some(pg_pool) => pg_pool,
+            None => return,
+        };
+        let store = PgNonceStore::new(pg_pool.clone());
+        let uid = unique_uid("clock");
+        let first = store.next(&uid, &ExchangeName::Kraken, NonceStyle::MillisTimestamp).await.unwrap();
+        assert!(first >= Utc::now().timestamp_millis() as u64 - 60_000);
+
+        // A stored nonce an hour ahead is what a clock set back by an hour leaves behind.
+        let ahead = first as i64 + 60 * 60 * 1000;
+        sqlx::query("UPDATE account_nonces SET value = $1 WHERE uid = $2 AND exchange = $3")
+            .bind(ahead)
+            .bind(&uid.0)
+            .bind(ExchangeName::Kraken.to_string())
+            .execute(&pg_pool)
+            .await
+            .unwrap();
+
+        let next = store.next(&uid, &ExchangeName::Kraken, NonceStyle::MillisTimestamp).await.unwrap();
+        assert_eq!(next, ahead as u64 + 1);
+        let after = store.next(&uid, &ExchangeName::Kraken, NonceStyle::MillisTimestamp).await.unwrap();
+        assert_eq!(after, ahead as u64 + 2);
+    }
+}
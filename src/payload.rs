@@ -0,0 +1,96 @@
+use warp::Filter;
+use warp::hyper::body::Bytes;
+use crate::dto::{FieldErrorDto, SignatureRequestDto};
+use crate::validation::ValidationRejection;
+
+const PAYLOAD_MAX_LEN: u64 = 1024 * 1024;
+
+fn invalid_payload(message: String) -> warp::Rejection {
+    warp::reject::custom(ValidationRejection(vec![FieldErrorDto {
+        field: "data_to_sign".to_string(),
+        message,
+    }]))
+}
+
+/// Bytes to sign, read according to the request `Content-Type`:
+/// `application/octet-stream` and `text/plain` bodies are taken as is,
+/// JSON bodies are a `SignatureRequestDto` decoded with its `encoding`.
+pub fn signing_payload() -> impl Filter<Extract=(Vec<u8>, ), Error=warp::Rejection> + Clone {
+    warp::header::optional::<String>("content-type")
+        .and(warp::body::content_length_limit(PAYLOAD_MAX_LEN))
+        .and(warp::body::bytes())
+        .and_then(|content_type: Option<String>, body: Bytes| async move {
+            let mime = content_type.as_deref()
+                .and_then(|content_type| content_type.split(';').next())
+                .map(|mime| mime.trim().to_ascii_lowercase());
+            match mime.as_deref() {
+                Some("application/octet-stream") => Ok(body.to_vec()),
+                Some("text/plain") => match std::str::from_utf8(&body) {
+                    Ok(_) => Ok(body.to_vec()),
+                    Err(_) => Err(invalid_payload("is not valid UTF-8".to_string())),
+                },
+                _ => match serde_json::from_slice::<SignatureRequestDto>(&body) {
+                    Ok(request) => request.decode().map_err(invalid_payload),
+                    Err(err) => Err(invalid_payload(err.to_string())),
+                },
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn read(content_type: Option<&str>, body: &[u8]) -> Result<Vec<u8>, String> {
+        let mut request = warp::test::request().method("POST").body(body);
+        if let Some(content_type) = content_type {
+            request = request.header("content-type", content_type);
+        }
+        request.filter(&signing_payload()).await.map_err(|rejection| {
+            let errors = &rejection.find::<ValidationRejection>().expect("a validation rejection").0;
+            assert_eq!(errors[0].field, "data_to_sign");
+            errors[0].message.clone()
+        })
+    }
+
+    #[tokio::test]
+    async fn takes_octet_stream_bodies_as_they_are() {
+        let body = [0u8, 159, 146, 150, 255];
+        assert_eq!(read(Some("application/octet-stream"), &body).await.unwrap(), body);
+        assert_eq!(read(Some("Application/Octet-Stream; charset=binary"), &body).await.unwrap(), body);
+    }
+
+    #[tokio::test]
+    async fn takes_text_bodies_that_are_utf8() {
+        let body = "ünïcode payload".as_bytes();
+        assert_eq!(read(Some("text/plain; charset=utf-8"), body).await.unwrap(), body);
+        assert_eq!(read(Some("text/plain"), &[0xff, 0xfe]).await.unwrap_err(), "is not valid UTF-8");
+    }
+
+    #[tokio::test]
+    async fn decodes_json_bodies_with_their_encoding() {
+        let json = Some("application/json");
+        assert_eq!(read(json, br#"{"data_to_sign":"payload"}"#).await.unwrap(), b"payload");
+        assert_eq!(read(json, br#"{"data_to_sign":"payload","encoding":"utf8"}"#).await.unwrap(), b"payload");
+        let binary = [0u8, 159, 146, 150, 255];
+        assert_eq!(read(json, br#"{"data_to_sign":"AJ+Slv8=","encoding":"base64"}"#).await.unwrap(), binary);
+        assert_eq!(read(json, br#"{"data_to_sign":"009f9296ff","encoding":"hex"}"#).await.unwrap(), binary);
+        // Without a content type the body is read as JSON too.
+        assert_eq!(read(None, br#"{"data_to_sign":"payload"}"#).await.unwrap(), b"payload");
+    }
+
+    #[tokio::test]
+    async fn rejects_payloads_that_do_not_decode() {
+        let json = Some("application/json");
+        let err = read(json, br#"{"data_to_sign":"not base64!","encoding":"base64"}"#).await.unwrap_err();
+        assert_eq!(err, "is not valid base64");
+        let err = read(json, br#"{"data_to_sign":"0g","encoding":"hex"}"#).await.unwrap_err();
+        assert_eq!(err, "is not valid hex");
+        let err = read(json, br#"{"data_to_sign":"abc","encoding":"hex"}"#).await.unwrap_err();
+        assert_eq!(err, "is not valid hex");
+        let err = read(json, br#"{"data_to_sign":"payload","encoding":"rot13"}"#).await.unwrap_err();
+        assert!(err.contains("unknown variant"), "{}", err);
+        let err = read(json, b"[1, 2, 3]").await.unwrap_err();
+        assert!(err.starts_with("invalid type"), "{}", err);
+    }
+}
@@ -0,0 +1,413 @@
+use anyhow::anyhow;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use warp::http::{HeaderValue, Method};
+use warp::path::FullPath;
+use warp::reply::Response;
+use warp::{Filter, Rejection};
+use crate::api;
+use crate::config::Config;
+use crate::dto::{CreateAccountDto, GetApiKeyDto, SignAndGetDto, UpdateAccountDto};
+use crate::models::ExchangeName;
+
+/// Most buckets kept at once; past it the least recently used bucket makes room for a new one.
+const MAX_BUCKETS: usize = 10_000;
+/// Share of an exchange budget left at which signing responses start carrying a warning.
+const BUDGET_WARNING_RATIO: f64 = 0.1;
+
+/// A token bucket setting, written as `<burst>/<period_secs>` or `off`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum RateLimit {
+    Off,
+    /// Up to `burst` requests at once, refilled at `burst` per `period_secs`.
+    Bucket { burst: u32, period_secs: u32 },
+}
+
+impl FromStr for RateLimit {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if value == "off" {
+            return Ok(RateLimit::Off);
+        }
+        let invalid = || anyhow!("rate limit must be \"<burst>/<period_secs>\" or \"off\", got \"{}\"", value);
+        let mut parts = value.splitn(2, '/');
+        let burst = parts.next().and_then(|burst| burst.parse().ok()).ok_or_else(invalid)?;
+        let period_secs = parts.next().and_then(|period| period.parse().ok()).ok_or_else(invalid)?;
+        if burst == 0 || period_secs == 0 {
+            return Err(invalid());
+        }
+        Ok(RateLimit::Bucket { burst, period_secs })
+    }
+}
+
+/// Documented request weight budget of each exchange's API key, per window.
+pub fn exchange_budget(exchange: &ExchangeName) -> RateLimit {
+    let (burst, period_secs) = match exchange {
+        ExchangeName::Binance => (1200, 60),
+        ExchangeName::HitBtc => (100, 1),
+        ExchangeName::Kraken => (15, 45),
+        ExchangeName::Okex => (20, 2),
+        ExchangeName::Kucoin => (1800, 60),
+        ExchangeName::Bitfinex => (90, 60),
+        ExchangeName::Huobi => (100, 10),
+        ExchangeName::Quoine => (300, 300),
+    };
+    RateLimit::Bucket { burst, period_secs }
+}
+
+#[derive(Debug)]
+pub struct RateLimited {
+    pub scope: &'static str,
+    pub retry_after: Duration,
+}
+
+impl RateLimited {
+    /// Rounded up, so a client waiting exactly this long finds a token.
+    pub fn retry_after_secs(&self) -> u64 {
+        self.retry_after.as_secs() + u64::from(self.retry_after.subsec_nanos() > 0)
+    }
+}
+
+impl warp::reject::Reject for RateLimited {}
+
+struct TokenBucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    fn refill(&mut self, burst: f64, per_sec: f64, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * per_sec).min(burst);
+        self.updated = now;
+    }
+}
+
+#[derive(Clone, Debug, Hash, Eq, PartialEq)]
+enum BucketKey {
+    Caller(String),
+    Uid(String),
+    Route(String),
+    Exchange(String, ExchangeName),
+}
+
+/// What a request is limited by, taken from its headers and path.
+struct RequestScope {
+    caller: String,
+    route: String,
+    uid: Option<String>,
+    /// Set for requests signing for an exchange, which spend its weight budget.
+    exchange: Option<ExchangeName>,
+}
+
+fn token_digest(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+impl RequestScope {
+    /// `None` for requests no endpoint of `api::ENDPOINTS` serves, which are not limited.
+    fn of(method: &Method, path: &str, caller: String) -> Option<RequestScope> {
+        let (endpoint, values) = api::find(method, path)?;
+        let param = |name| endpoint.params().position(|param| param == name).map(|index| values[index].clone());
+        let signing = endpoint.path.ends_with("/signatures") || endpoint.path.ends_with("/requests");
+        Some(RequestScope {
+            caller,
+            route: format!("{} {}", method, endpoint.path),
+            uid: param("uid"),
+            exchange: param("exchange").filter(|_| signing).and_then(|exchange| exchange.parse().ok()),
+        })
+    }
+}
+
+/// Token buckets per caller, uid and route, plus optional per-account exchange weight budgets.
+pub struct RateLimiter {
+    caller: RateLimit,
+    uid: RateLimit,
+    route: RateLimit,
+    exchange_budgets: bool,
+    /// SHA-256 of the `api_tokens`, so callers can be recognized without keeping the tokens.
+    api_tokens: HashSet<String>,
+    buckets: Mutex<HashMap<BucketKey, TokenBucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: &Config) -> RateLimiter {
+        RateLimiter {
+            caller: config.rate_limit_caller,
+            uid: config.rate_limit_uid,
+            route: config.rate_limit_route,
+            exchange_budgets: config.exchange_budgets,
+            api_tokens: config.api_tokens.iter().map(|token| token_digest(token.expose())).collect(),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Identifies the caller of a request, for its rate limit bucket and idempotency keys.
+    /// Only tokens of `API_TOKENS` identify a caller: any other could be made up per request,
+    /// so those requests are told apart by client address.
+    pub fn caller_id(&self, authorization: Option<&str>, remote: Option<SocketAddr>) -> String {
+        let digest = authorization
+            .map(|authorization| token_digest(authorization.strip_prefix("Bearer ").unwrap_or(authorization)))
+            .filter(|digest| self.api_tokens.contains(digest));
+        match (digest, remote) {
+            (Some(digest), _) => format!("token:{}", &digest[..16]),
+            (None, Some(remote)) => format!("ip:{}", remote.ip()),
+            (None, None) => "anonymous".to_string(),
+        }
+    }
+
+    /// Takes `cost` tokens from the bucket at `key`, or tells how long until they are available.
+    /// Returns the tokens left.
+    fn take(
+        buckets: &mut HashMap<BucketKey, TokenBucket>,
+        key: BucketKey,
+        limit: RateLimit,
+        cost: f64,
+        now: Instant,
+    ) -> Result<f64, Duration> {
+        let (burst, period_secs) = match limit {
+            RateLimit::Off => return Ok(f64::INFINITY),
+            RateLimit::Bucket { burst, period_secs } => (burst as f64, period_secs as f64),
+        };
+        let per_sec = burst / period_secs;
+        if !buckets.contains_key(&key) {
+            Self::make_room(buckets, now);
+        }
+        let bucket = buckets.entry(key).or_insert(TokenBucket { tokens: burst, updated: now });
+        bucket.refill(burst, per_sec, now);
+        if bucket.tokens >= cost {
+            bucket.tokens -= cost;
+            Ok(bucket.tokens)
+        } else {
+            Err(Duration::from_secs_f64((cost - bucket.tokens) / per_sec))
+        }
+    }
+
+    /// Keeps the table under `MAX_BUCKETS` before a bucket is added.
+    fn make_room(buckets: &mut HashMap<BucketKey, TokenBucket>, now: Instant) {
+        if buckets.len() < MAX_BUCKETS {
+            return;
+        }
+        // Buckets idle for longer than any refill period are full again, so dropping them is free.
+        let idle = Duration::from_secs(3600);
+        buckets.retain(|_, bucket| now.saturating_duration_since(bucket.updated) < idle);
+        if buckets.len() < MAX_BUCKETS {
+            return;
+        }
+        let oldest = buckets.iter()
+            .min_by_key(|(_, bucket)| bucket.updated)
+            .map(|(key, _)| key.clone());
+        if let Some(oldest) = oldest {
+            buckets.remove(&oldest);
+        }
+    }
+
+    fn check(&self, scope: &RequestScope, weight: u32) -> Result<Option<String>, RateLimited> {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+
+        let limited = |scope, retry_after| RateLimited { scope, retry_after };
+        Self::take(&mut buckets, BucketKey::Caller(scope.caller.clone()), self.caller, 1.0, now)
+            .map_err(|retry_after| limited("caller", retry_after))?;
+        Self::take(&mut buckets, BucketKey::Route(scope.route.clone()), self.route, 1.0, now)
+            .map_err(|retry_after| limited("route", retry_after))?;
+        if let Some(uid) = &scope.uid {
+            Self::take(&mut buckets, BucketKey::Uid(uid.clone()), self.uid, 1.0, now)
+                .map_err(|retry_after| limited("uid", retry_after))?;
+        }
+
+        // Exchange budgets only warn: the request is still signed, the caller decides whether to send it.
+        match (&scope.uid, &scope.exchange) {
+            (Some(uid), Some(exchange)) if self.exchange_budgets => {
+                let budget = exchange_budget(exchange);
+                let key = BucketKey::Exchange(uid.clone(), exchange.clone());
+                let warning = match Self::take(&mut buckets, key, budget, weight as f64, now) {
+                    Ok(left) => match budget {
+                        RateLimit::Bucket { burst, .. } if left < burst as f64 * BUDGET_WARNING_RATIO => Some(
+                            format!("{} weight budget nearly spent, {} left", exchange, left.floor()),
+                        ),
+                        _ => None,
+                    },
+                    Err(retry_after) => Some(format!(
+                        "{} weight budget exceeded, the exchange may reject this key for {}s",
+                        exchange,
+                        retry_after.as_secs() + 1,
+                    )),
+                };
+                if let Some(warning) = &warning {
+                    log::warn!("account with uid \"{}\": {}", uid, warning);
+                }
+                Ok(warning)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Applies the same limits to a call that didn't come through warp, e.g. over gRPC.
+    /// `route` names the operation, shared by all callers like a REST route.
+    pub fn check_call(
+        &self,
+        route: &str,
+        authorization: Option<&str>,
+        remote: Option<SocketAddr>,
+        uid: Option<&str>,
+        exchange: Option<&ExchangeName>,
+    ) -> Result<Option<String>, RateLimited> {
+        let scope = RequestScope {
+            caller: self.caller_id(authorization, remote),
+            route: route.to_string(),
+            uid: uid.map(str::to_string),
+            exchange: exchange.cloned(),
+        };
+        self.check(&scope, 1)
+    }
+
+    pub fn check_uid(&self, uid: &str) -> Result<(), RateLimited> {
+        let mut buckets = self.buckets.lock().unwrap();
+        Self::take(&mut buckets, BucketKey::Uid(uid.to_string()), self.uid, 1.0, Instant::now())
+            .map(|_| ())
+            .map_err(|retry_after| RateLimited { scope: "uid", retry_after })
+    }
+}
+
+/// Applies the caller, route and path uid limits, extracting a warning for exchange budgets.
+/// Signing requests may declare their exchange request weight with `X-Exchange-Weight`.
+/// Requests for paths no endpoint serves are passed on unlimited, to be rejected as not found.
+pub fn rate_limit(
+    limiter: Arc<RateLimiter>,
+) -> impl Filter<Extract=(Option<String>, ), Error=Rejection> + Clone {
+    warp::method()
+        .and(warp::path::full())
+        .and(warp::header::optional::<String>("authorization"))
+        .and(warp::addr::remote())
+        .and(warp::header::optional::<u32>("x-exchange-weight"))
+        .and_then(move |method: Method, path: FullPath, authorization: Option<String>, remote, weight: Option<u32>| {
+            let limiter = limiter.clone();
+            async move {
+                let caller = limiter.caller_id(authorization.as_deref(), remote);
+                match RequestScope::of(&method, path.as_str(), caller) {
+                    Some(scope) => limiter.check(&scope, weight.unwrap_or(1)).map_err(warp::reject::custom),
+                    None => Ok(None),
+                }
+            }
+        })
+}
+
+/// Adds the exchange budget warning, if any, as a `Warning` header.
+pub fn with_warning(warning: Option<String>, mut response: Response) -> Response {
+    if let Some(warning) = warning {
+        if let Ok(value) = HeaderValue::from_str(&format!("199 try_api \"{}\"", warning)) {
+            response.headers_mut().insert("Warning", value);
+        }
+    }
+    response
+}
+
+/// Request bodies naming the account they act on, for routes without the uid in the path.
+pub trait AccountScoped {
+    fn uid(&self) -> &str;
+}
+
+impl AccountScoped for CreateAccountDto {
+    fn uid(&self) -> &str {
+        &self.uid
+    }
+}
+
+impl AccountScoped for SignAndGetDto {
+    fn uid(&self) -> &str {
+        &self.uid
+    }
+}
+
+impl AccountScoped for UpdateAccountDto {
+    fn uid(&self) -> &str {
+        &self.uid
+    }
+}
+
+impl AccountScoped for GetApiKeyDto {
+    fn uid(&self) -> &str {
+        &self.uid
+    }
+}
+
+/// Applies the uid limit to a request body, for routes `rate_limit` can't see the uid of.
+pub async fn uid_limited<T: AccountScoped>(body: T, limiter: Arc<RateLimiter>) -> Result<T, Rejection> {
+    match limiter.check_uid(body.uid()) {
+        Ok(()) => Ok(body),
+        Err(limited) => Err(warp::reject::custom(limited)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(caller: RateLimit) -> RateLimiter {
+        RateLimiter {
+            caller,
+            uid: RateLimit::Off,
+            route: RateLimit::Off,
+            exchange_budgets: false,
+            api_tokens: vec![token_digest("known")].into_iter().collect(),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn remote() -> Option<SocketAddr> {
+        Some(([10, 0, 0, 1], 4000).into())
+    }
+
+    #[test]
+    fn identifies_callers_by_known_tokens_only() {
+        let limiter = limiter(RateLimit::Off);
+        assert!(limiter.caller_id(Some("Bearer known"), remote()).starts_with("token:"));
+        assert_eq!(limiter.caller_id(Some("Bearer made-up"), remote()), "ip:10.0.0.1");
+        assert_eq!(limiter.caller_id(None, remote()), "ip:10.0.0.1");
+        assert_eq!(limiter.caller_id(Some("Bearer made-up"), None), "anonymous");
+    }
+
+    #[test]
+    fn made_up_tokens_share_the_address_bucket() {
+        let limiter = limiter(RateLimit::Bucket { burst: 2, period_secs: 3600 });
+        let call = |token: &str| {
+            let authorization = format!("Bearer {}", token);
+            limiter.check_call("getAccount", Some(&authorization), remote(), None, None)
+        };
+        assert!(call("first").is_ok());
+        assert!(call("second").is_ok());
+        assert!(call("third").is_err());
+        assert!(call("known").is_ok());
+    }
+
+    #[test]
+    fn scopes_only_served_routes() {
+        let scope = RequestScope::of(&Method::POST, "/v1/accounts/alice/kraken/signatures", "ip:a".to_string())
+            .unwrap();
+        assert_eq!(scope.route, "POST /v1/accounts/{uid}/{exchange}/signatures");
+        assert_eq!(scope.uid.as_deref(), Some("alice"));
+        assert_eq!(scope.exchange, Some(ExchangeName::Kraken));
+
+        let scope = RequestScope::of(&Method::GET, "/v1/accounts/alice/kraken", "ip:a".to_string()).unwrap();
+        assert_eq!(scope.exchange, None);
+
+        assert!(RequestScope::of(&Method::GET, "/v1/accounts/alice/kraken/nothing", "ip:a".to_string()).is_none());
+        assert!(RequestScope::of(&Method::GET, "/random-1234", "ip:a".to_string()).is_none());
+    }
+
+    #[test]
+    fn never_keeps_more_than_max_buckets() {
+        let limiter = limiter(RateLimit::Bucket { burst: 1, period_secs: 3600 });
+        for index in 0..MAX_BUCKETS + 10 {
+            let remote = SocketAddr::from(([10, (index >> 16) as u8, (index >> 8) as u8, index as u8], 4000));
+            assert!(limiter.check_call("getAccount", None, Some(remote), None, None).is_ok());
+        }
+        assert_eq!(limiter.buckets.lock().unwrap().len(), MAX_BUCKETS);
+    }
+}
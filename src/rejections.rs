@@ -0,0 +1,30 @@
+use warp::{http, Reply};
+use warp::reply::Response;
+use crate::dto::{ErrorDto, ValidationErrorDto};
+use crate::rate_limit::RateLimited;
+use crate::stream::Unauthorized;
+use crate::validation::ValidationRejection;
+
+/// Turns rejections raised by our own filters into JSON error responses.
+pub async fn handle_rejection(rejection: warp::Rejection) -> Result<Response, warp::Rejection> {
+    if let Some(ValidationRejection(errors)) = rejection.find() {
+        let body = ValidationErrorDto {
+            error: "Request validation failed".to_string(),
+            errors: errors.clone(),
+        };
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&body),
+            http::StatusCode::UNPROCESSABLE_ENTITY,
+        ).into_response());
+    }
+    if let Some(limited) = rejection.find::<RateLimited>() {
+        let body = ErrorDto { error: format!("Rate limit per {} exceeded", limited.scope) };
+        let reply = warp::reply::with_status(warp::reply::json(&body), http::StatusCode::TOO_MANY_REQUESTS);
+        return Ok(warp::reply::with_header(reply, "Retry-After", limited.retry_after_secs().to_string()).into_response());
+    }
+    if rejection.find::<Unauthorized>().is_some() {
+        let body = ErrorDto { error: "A valid bearer token is required".to_string() };
+        return Ok(warp::reply::with_status(warp::reply::json(&body), http::StatusCode::UNAUTHORIZED).into_response());
+    }
+    Err(rejection)
+}
@@ -0,0 +1,557 @@
+use warp::{Filter, Reply};
+use warp::filters::BoxedFilter;
+use warp::reply::Response;
+use crate::account::AccountRepo;
+use crate::api::{self, Endpoint};
+use crate::config::Config;
+use crate::docs;
+use crate::dto::{
+    CreateAccountDto, SignAndGetDto, UpdateAccountDto, GetApiKeyDto, ListAccountsQuery, AccountChangesDto,
+    SignRequestDto, KeyPairRequestDto,
+};
+use crate::handlers;
+use crate::idempotency::{self, Idempotency, IdempotencyStore};
+use crate::payload;
+use crate::rate_limit::{self, AccountScoped, RateLimiter};
+use crate::rejections;
+use crate::stream::{self, EventHub};
+use crate::validation::{self, Validate};
+use crate::models::{ExchangeName, ExecutorResponse};
+use std::convert::Infallible;
+use std::sync::Arc;
+
+fn json_body<T>() -> impl Filter<Extract=(T, ), Error=warp::Rejection> + Clone
+    where
+            for<'a> T: serde::Deserialize<'a> + Send,
+{
+    warp::body::content_length_limit(1024 * 1024).and(warp::filters::body::json::<T>())
+}
+
+fn validated_json_body<T>() -> impl Filter<Extract=(T, ), Error=warp::Rejection> + Clone
+    where
+            for<'a> T: serde::Deserialize<'a> + Validate + Send,
+{
+    json_body::<T>().and_then(validation::validated)
+}
+
+fn with_state(
+    account_repo: Arc<AccountRepo>,
+) -> impl Filter<Extract=(Arc<AccountRepo>, ), Error=Infallible> + Clone {
+    warp::any().map(move || account_repo.clone())
+}
+
+/// Body of a legacy route, counted against the limit of the uid it names.
+fn uid_limited_body<T>(
+    limiter: Arc<RateLimiter>,
+) -> impl Filter<Extract=(T, ), Error=warp::Rejection> + Clone
+    where
+            for<'a> T: serde::Deserialize<'a> + Validate + AccountScoped + Send,
+{
+    validated_json_body::<T>()
+        .and(warp::any().map(move || limiter.clone()))
+        .and_then(rate_limit::uid_limited)
+}
+
+/// The event hub; routes needing it are not found while the stream is disabled.
+fn with_hub(hub: Option<Arc<EventHub>>) -> impl Filter<Extract=(Arc<EventHub>, ), Error=warp::Rejection> + Clone {
+    warp::any().and_then(move || {
+        let hub = hub.clone();
+        async move { hub.ok_or_else(warp::reject::not_found) }
+    })
+}
+
+fn if_match() -> impl Filter<Extract=(Option<String>, ), Error=warp::Rejection> + Clone {
+    warp::header::optional::<String>("if-match")
+}
+
+/// Matches `endpoint`, which has no path parameters.
+fn endpoint(endpoint: &'static Endpoint) -> impl Filter<Extract=(), Error=warp::Rejection> + Clone {
+    assert_eq!(endpoint.params().count(), 0, "{} takes path parameters", endpoint.operation_id);
+    api::route(endpoint).map(|_| ()).untuple_one()
+}
+
+/// Matches `endpoint`, extracting its `{uid}`.
+fn uid_endpoint(endpoint: &'static Endpoint) -> impl Filter<Extract=(String, ), Error=warp::Rejection> + Clone {
+    assert!(endpoint.params().eq(["uid"].iter().copied()), "{} doesn't take a uid", endpoint.operation_id);
+    api::route(endpoint).map(|mut params: Vec<String>| params.remove(0))
+}
+
+/// Matches `endpoint`, extracting its `{uid}` and `{exchange}`; unknown exchanges are not found.
+fn exchange_endpoint(
+    endpoint: &'static Endpoint,
+) -> impl Filter<Extract=(String, ExchangeName), Error=warp::Rejection> + Clone {
+    assert!(
+        endpoint.params().eq(["uid", "exchange"].iter().copied()),
+        "{} doesn't take a uid and exchange",
+        endpoint.operation_id,
+    );
+    api::route(endpoint)
+        .and_then(|params: Vec<String>| async move {
+            match params[1].parse::<ExchangeName>() {
+                Ok(exchange) => Ok((params[0].clone(), exchange)),
+                Err(_) => Err(warp::reject::not_found()),
+            }
+        })
+        .untuple_one()
+}
+
+/// `uid_endpoint` with the uid validated; for `/v1` routes without a body to validate it in.
+fn valid_uid_endpoint(endpoint: &'static Endpoint) -> impl Filter<Extract=(String, ), Error=warp::Rejection> + Clone {
+    uid_endpoint(endpoint).and_then(validation::validated_uid)
+}
+
+/// `exchange_endpoint` with the uid validated; for `/v1` routes without a body to validate it in.
+fn valid_exchange_endpoint(
+    endpoint: &'static Endpoint,
+) -> impl Filter<Extract=(String, ExchangeName), Error=warp::Rejection> + Clone {
+    exchange_endpoint(endpoint)
+        .and_then(|account_id, exchange| async move {
+            validation::validated_uid(account_id).await.map(|account_id| (account_id, exchange))
+        })
+        .untuple_one()
+}
+
+/// Marks a reply as coming from a pre-`/v1` route.
+fn deprecated(reply: impl Reply) -> impl Reply {
+    let reply = warp::reply::with_header(reply, "Deprecation", "true");
+    warp::reply::with_header(reply, "Link", "</v1/accounts>; rel=\"successor-version\"")
+}
+
+pub fn routes(
+    config: &Config,
+    account_repo: Arc<AccountRepo>,
+    idempotency_store: Arc<IdempotencyStore>,
+    limiter: Arc<RateLimiter>,
+    hub: Option<Arc<EventHub>>,
+) -> BoxedFilter<(Response, )> {
+    let document = docs::document(config.public_url.as_deref().unwrap_or("/"));
+    let (document_json, document_yaml) = (docs::json(&document), docs::yaml(&document));
+    let openapi_json = endpoint(&api::OPENAPI_JSON)
+        .map(move || {
+            let reply = warp::reply::with_header(document_json.clone(), "Content-Type", "application/json");
+            Reply::into_response(reply)
+        });
+    let yaml_reply = move || {
+        let reply = warp::reply::with_header(document_yaml.clone(), "Content-Type", "application/yaml");
+        Reply::into_response(reply)
+    };
+    let openapi_yaml = endpoint(&api::OPENAPI_YAML).map(yaml_reply.clone());
+    let swagger = endpoint(&api::SWAGGER_YAML).map(yaml_reply);
+    let documentation = openapi_json.or(openapi_yaml).unify().or(swagger).unify();
+    let documentation = if config.docs_ui {
+        documentation.or(docs::ui_route()).unify().boxed()
+    } else {
+        documentation.boxed()
+    };
+    // The stream authenticates its own clients and holds connections open, so it is not rate limited.
+    let unlimited = match (&config.stream, hub.clone()) {
+        (Some(stream_config), Some(hub)) => documentation.or(stream::ws_route(stream_config, hub)).unify().boxed(),
+        _ => documentation.boxed(),
+    };
+
+    let v1 = v1_routes(account_repo.clone(), idempotency_store.clone(), limiter.clone(), hub);
+
+    if config.legacy_routes {
+        let legacy = legacy_routes(account_repo, idempotency_store, limiter.clone())
+            .map(|reply| Reply::into_response(deprecated(reply)));
+        let api = rate_limit::rate_limit(limiter).and(v1.or(legacy).unify()).map(rate_limit::with_warning);
+        unlimited.or(api).unify()
+            .recover(rejections::handle_rejection).unify()
+            .boxed()
+    } else {
+        let api = rate_limit::rate_limit(limiter).and(v1).map(rate_limit::with_warning);
+        unlimited.or(api).unify()
+            .recover(rejections::handle_rejection).unify()
+            .boxed()
+    }
+}
+
+fn v1_routes(
+    account_repo: Arc<AccountRepo>,
+    idempotency_store: Arc<IdempotencyStore>,
+    limiter: Arc<RateLimiter>,
+    hub: Option<Arc<EventHub>>,
+) -> impl Filter<Extract=(Response, ), Error=warp::Rejection> + Clone {
+    let state = with_state(account_repo);
+    let idempotency = idempotency::idempotency(idempotency_store, limiter.clone());
+
+    let list_accounts = endpoint(&api::V1_LIST_ACCOUNTS)
+        .and(state.clone())
+        .and(warp::query::<ListAccountsQuery>())
+        .and_then(handlers::list_accounts_rest)
+        .map(Reply::into_response);
+
+    let create_account = endpoint(&api::V1_CREATE_ACCOUNT)
+        .and(idempotency.clone())
+        .and(state.clone())
+        .and(uid_limited_body::<CreateAccountDto>(limiter.clone()))
+        .and_then(|idempotency: Idempotency, account_repo, dto: CreateAccountDto| {
+            idempotency.run(idempotency::body_of(&dto), handlers::create_account_v1(account_repo, dto))
+        });
+
+    let get_account = valid_uid_endpoint(&api::V1_GET_ACCOUNT)
+        .and(state.clone())
+        .and_then(|account_id, account_repo| handlers::get_account_rest(account_id, None, account_repo));
+
+    let remove_account = valid_uid_endpoint(&api::V1_REMOVE_ACCOUNT)
+        .and(if_match())
+        .and(idempotency.clone())
+        .and(state.clone())
+        .and_then(|account_id, if_match, idempotency: Idempotency, account_repo| {
+            idempotency.run(Vec::new(), handlers::remove_account_v1(account_id, if_match, account_repo))
+        });
+
+    let restore_account = valid_uid_endpoint(&api::V1_RESTORE_ACCOUNT)
+        .and(idempotency.clone())
+        .and(state.clone())
+        .and_then(|account_id, idempotency: Idempotency, account_repo| {
+            idempotency.run(Vec::new(), handlers::restore_account_v1(account_id, account_repo))
+        });
+
+    let get_exchange_account = valid_exchange_endpoint(&api::V1_GET_EXCHANGE_ACCOUNT)
+        .and(state.clone())
+        .and_then(|account_id, exchange, account_repo| {
+            handlers::get_account_rest(account_id, Some(exchange), account_repo)
+        });
+
+    let update_account = exchange_endpoint(&api::V1_UPDATE_ACCOUNT)
+        .and(json_body::<AccountChangesDto>())
+        .and_then(validation::validated_for_exchange::<AccountChangesDto>)
+        .untuple_one()
+        .and(if_match())
+        .and(idempotency.clone())
+        .and(state.clone())
+        .and_then(|account_id, exchange, dto: AccountChangesDto, if_match, idempotency: Idempotency, account_repo| {
+            idempotency.run(
+                idempotency::body_of(&dto),
+                handlers::update_account_v1(account_id, exchange, if_match, account_repo, dto),
+            )
+        });
+
+    let create_signature = valid_exchange_endpoint(&api::V1_CREATE_SIGNATURE)
+        .and(payload::signing_payload())
+        .and(idempotency.clone())
+        .and(state.clone())
+        .and_then(|account_id, exchange, data_to_sign: Vec<u8>, idempotency: Idempotency, account_repo| {
+            idempotency.run(
+                data_to_sign.clone(),
+                handlers::create_signature_v1(account_id, exchange, account_repo, data_to_sign),
+            )
+        });
+
+    let sign_request = exchange_endpoint(&api::V1_SIGN_REQUEST)
+        .and(json_body::<SignRequestDto>())
+        .and_then(validation::validated_for_exchange::<SignRequestDto>)
+        .untuple_one()
+        .and(idempotency.clone())
+        .and(state.clone())
+        .and_then(|account_id, exchange, dto: SignRequestDto, idempotency: Idempotency, account_repo| {
+            idempotency.run(
+                idempotency::body_of(&dto),
+                handlers::sign_request_v1(account_id, exchange, account_repo, dto),
+            )
+        });
+
+    let generate_key_pair = exchange_endpoint(&api::V1_GENERATE_KEY_PAIR)
+        .and(json_body::<KeyPairRequestDto>())
+        .and_then(validation::validated_for_exchange::<KeyPairRequestDto>)
+        .untuple_one()
+        .and(if_match())
+        .and(idempotency.clone())
+        .and(state.clone())
+        .and_then(|account_id, exchange, dto: KeyPairRequestDto, if_match, idempotency: Idempotency, account_repo| {
+            idempotency.run(
+                idempotency::body_of(&dto),
+                handlers::generate_key_pair_v1(account_id, exchange, if_match, account_repo, dto),
+            )
+        });
+
+    let get_api_key = valid_exchange_endpoint(&api::V1_GET_API_KEY)
+        .and(state.clone())
+        .and_then(handlers::get_api_key_v1);
+
+    let remove_api_key = valid_exchange_endpoint(&api::V1_REMOVE_API_KEY)
+        .and(if_match())
+        .and(idempotency)
+        .and(state)
+        .and_then(|account_id, exchange, if_match, idempotency: Idempotency, account_repo| {
+            idempotency.run(Vec::new(), handlers::remove_api_key_v1(account_id, exchange, if_match, account_repo))
+        });
+
+    let publish_executor_event = valid_uid_endpoint(&api::V1_PUBLISH_EXECUTOR_EVENT)
+        .and(with_hub(hub))
+        .and(json_body::<ExecutorResponse>())
+        .and_then(handlers::publish_executor_event_v1);
+
+    // Boxed one by one: unboxed, the combined future outgrows a worker thread's stack in debug builds.
+    list_accounts.boxed()
+        .or(create_account.boxed()).unify()
+        .or(get_account.boxed()).unify()
+        .or(remove_account.boxed()).unify()
+        .or(restore_account.boxed()).unify()
+        .or(get_exchange_account.boxed()).unify()
+        .or(update_account.boxed()).unify()
+        .or(create_signature.boxed()).unify()
+        .or(sign_request.boxed()).unify()
+        .or(generate_key_pair.boxed()).unify()
+        .or(get_api_key.boxed()).unify()
+        .or(remove_api_key.boxed()).unify()
+        .or(publish_executor_event.boxed()).unify()
+}
+
+fn legacy_routes(
+    account_repo: Arc<AccountRepo>,
+    idempotency_store: Arc<IdempotencyStore>,
+    limiter: Arc<RateLimiter>,
+) -> impl Filter<Extract=(Response, ), Error=warp::Rejection> + Clone {
+    let state = with_state(account_repo);
+    let idempotency = idempotency::idempotency(idempotency_store, limiter.clone());
+
+    let create_rout = endpoint(&api::LEGACY_CREATE_ACCOUNT)
+        .and(idempotency.clone())
+        .and(state.clone())
+        .and(uid_limited_body::<CreateAccountDto>(limiter.clone()))
+        .and_then(|idempotency: Idempotency, account_repo, dto: CreateAccountDto| {
+            idempotency.run(idempotency::body_of(&dto), handlers::create_account_rest(account_repo, dto))
+        });
+
+    let sign_rout = endpoint(&api::LEGACY_SIGN_AND_GET_KEY)
+        .and(idempotency.clone())
+        .and(state.clone())
+        .and(uid_limited_body::<SignAndGetDto>(limiter.clone()))
+        .and_then(|idempotency: Idempotency, account_repo, dto: SignAndGetDto| {
+            idempotency.run(idempotency::body_of(&dto), handlers::sign_and_key_rest(account_repo, dto))
+        });
+
+    let account_update_rout = endpoint(&api::LEGACY_UPDATE_ACCOUNT)
+        .and(idempotency.clone())
+        .and(state.clone())
+        .and(uid_limited_body::<UpdateAccountDto>(limiter.clone()))
+        .and_then(|idempotency: Idempotency, account_repo, dto: UpdateAccountDto| {
+            idempotency.run(idempotency::body_of(&dto), handlers::update_account_rest(account_repo, dto))
+        });
+
+    let remove_account_rout = uid_endpoint(&api::LEGACY_REMOVE_ACCOUNT)
+        .and(idempotency.clone())
+        .and(state.clone())
+        .and_then(|account_id, idempotency: Idempotency, account_repo| {
+            idempotency.run(Vec::new(), handlers::remove_account_rest(account_id, account_repo))
+        });
+
+    let remove_key_rout = uid_endpoint(&api::LEGACY_REMOVE_KEY)
+        .and(idempotency.clone())
+        .and(state.clone())
+        .and_then(|account_id, idempotency: Idempotency, account_repo| {
+            idempotency.run(Vec::new(), handlers::remove_key_rest(account_id, account_repo))
+        });
+
+    let get_api_key_rout = endpoint(&api::LEGACY_GET_API_KEY)
+        .and(idempotency)
+        .and(state.clone())
+        .and(uid_limited_body::<GetApiKeyDto>(limiter.clone()))
+        .and_then(|idempotency: Idempotency, account_repo, dto: GetApiKeyDto| {
+            idempotency.run(idempotency::body_of(&dto), handlers::get_api_key_rest(account_repo, dto))
+        });
+
+    let list_accounts_rout = endpoint(&api::LEGACY_LIST_ACCOUNTS)
+        .and(state.clone())
+        .and(warp::query::<ListAccountsQuery>())
+        .and_then(handlers::list_accounts_rest)
+        .map(Reply::into_response);
+
+    let get_account_rout = uid_endpoint(&api::LEGACY_GET_ACCOUNT)
+        .and(state.clone())
+        .and_then(|account_id, account_repo| handlers::get_account_rest(account_id, None, account_repo));
+
+    let get_exchange_account_rout = exchange_endpoint(&api::LEGACY_GET_EXCHANGE_ACCOUNT)
+        .and(state)
+        .and_then(|account_id, exchange, account_repo| {
+            handlers::get_account_rest(account_id, Some(exchange), account_repo)
+        });
+
+    // Boxed for the same reason as the `/v1` endpoints.
+    create_rout.boxed()
+        .or(sign_rout.boxed()).unify()
+        .or(remove_account_rout.boxed()).unify()
+        .or(remove_key_rout.boxed()).unify()
+        .or(account_update_rout.boxed()).unify()
+        .or(get_api_key_rout.boxed()).unify()
+        .or(list_accounts_rout.boxed()).unify()
+        .or(get_account_rout.boxed()).unify()
+        .or(get_exchange_account_rout.boxed()).unify()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::CredentialCipher;
+    use crate::db::{db_connect, db_migrate};
+    use crate::models::AccountId;
+    use crate::secret::Secret;
+    use serde_json::{json, Value};
+    use sqlx::{Pool, Postgres};
+    use std::env;
+    use warp::http::StatusCode;
+    use warp::hyper::body::Bytes;
+
+    struct App {
+        pg_pool: Pool<Postgres>,
+        account_repo: Arc<AccountRepo>,
+        routes: BoxedFilter<(Response, )>,
+    }
+
+    impl App {
+        /// The repository and routes with `config`, or `None` without a database.
+        async fn start(config: Config) -> Option<App> {
+            let database_url = match env::var("DATABASE_URL") {
+                Ok(database_url) => database_url,
+                Err(_) => {
+                    eprintln!("DATABASE_URL is not set, skipping route test");
+                    return None;
+                }
+            };
+            let pg_pool = db_connect(&database_url).await.unwrap();
+            db_migrate(&pg_pool).await.unwrap();
+            let account_repo = Arc::new(AccountRepo::new(pg_pool.clone(), &config, None).await);
+            let idempotency_store = Arc::new(IdempotencyStore::new(
+                pg_pool.clone(),
+                config.idempotency_ttl_secs,
+                config.idempotency_lease_secs,
+                CredentialCipher::new(None),
+            ));
+            let routes = routes(&config, account_repo.clone(), idempotency_store, Arc::new(RateLimiter::new(&config)), None);
+            Some(App { pg_pool, account_repo, routes })
+        }
+
+        async fn request(&self, method: &str, path: &str, if_match: Option<&str>, body: Option<Value>) -> warp::http::Response<Bytes> {
+            let mut request = warp::test::request().method(method).path(path);
+            if let Some(if_match) = if_match {
+                request = request.header("if-match", if_match);
+            }
+            if let Some(body) = body {
+                request = request.json(&body);
+            }
+            request.reply(&self.routes).await
+        }
+
+        async fn create(&self, uid: &str) {
+            self.account_repo.create_account(&AccountId(uid.to_string()), &ExchangeName::Kraken, &Secret::from("account-api-key".to_string()), None, None)
+                .await
+                .unwrap();
+        }
+    }
+
+    fn etag(response: &warp::http::Response<Bytes>) -> String {
+        response.headers()["etag"].to_str().unwrap().to_string()
+    }
+
+    fn error(response: &warp::http::Response<Bytes>) -> String {
+        let body: Value = serde_json::from_slice(response.body()).unwrap();
+        body["error"].as_str().unwrap().to_string()
+    }
+
+    fn unique_uid(name: &str) -> String {
+        format!("routes-{}-{}", name, chrono::Utc::now().timestamp_micros())
+    }
+
+    #[tokio::test]
+    async fn refuses_changes_made_against_a_stale_version() {
+        let app = match App::start(Config::from_env()).await {
+            Some(app) => app,
+            None => return,
+        };
+        let uid = unique_uid("versions");
+        app.create(&uid).await;
+        let account_path = format!("/v1/accounts/{}/kraken", uid);
+        let rotate = || Some(json!({ "api_key": "rotated-api-key" }));
+
+        let read = app.request("GET", &account_path, None, None).await;
+        assert_eq!(read.status(), StatusCode::OK);
+        let first = etag(&read);
+        let body: Value = serde_json::from_slice(read.body()).unwrap();
+        assert_eq!(first, format!("\"{}\"", body["version"]));
+
+        let updated = app.request("PATCH", &account_path, Some(&first), rotate()).await;
+        assert_eq!(updated.status(), StatusCode::OK, "{:?}", updated.body());
+        let second = etag(&updated);
+        assert_ne!(second, first);
+
+        let stale = app.request("PATCH", &account_path, Some(&first), rotate()).await;
+        assert_eq!(stale.status(), StatusCode::PRECONDITION_FAILED);
+        assert!(error(&stale).contains("was modified"), "{}", error(&stale));
+        let stale = app.request("DELETE", &format!("{}/api-key", account_path), Some(&first), None).await;
+        assert_eq!(stale.status(), StatusCode::PRECONDITION_FAILED);
+        let stale = app.request("DELETE", &format!("/v1/accounts/{}", uid), Some(&first), None).await;
+        assert_eq!(stale.status(), StatusCode::PRECONDITION_FAILED);
+        assert_eq!(etag(&app.request("GET", &account_path, None, None).await), second);
+
+        let invalid = app.request("PATCH", &account_path, Some("not-a-version"), rotate()).await;
+        assert_eq!(invalid.status(), StatusCode::BAD_REQUEST);
+        // `*` and a missing header both match any version.
+        let any = app.request("PATCH", &account_path, Some("*"), rotate()).await;
+        assert_eq!(any.status(), StatusCode::OK);
+        let unconditional = app.request("PATCH", &account_path, None, rotate()).await;
+        assert_eq!(unconditional.status(), StatusCode::OK);
+
+        let current = etag(&unconditional);
+        let removed = app.request("DELETE", &format!("/v1/accounts/{}", uid), Some(&current), None).await;
+        assert_eq!(removed.status(), StatusCode::NO_CONTENT);
+    }
+    #[tokio::test]
+    async fn hides_deleted_accounts_until_restored_or_purged() {
+        let mut config = Config::from_env();
+        config.deleted_retention_secs = 24 * 60 * 60;
+        let app = match App::start(config).await {
+            Some(app) => app,
+            None => return,
+        };
+        let uid = unique_uid("deleted");
+        app.create(&uid).await;
+        let account_path = format!("/v1/accounts/{}", uid);
+        let listed = || async {
+            let response = app.request("GET", &format!("/v1/accounts?uid_prefix={}", uid), None, None).await;
+            let page: Value = serde_json::from_slice(response.body()).unwrap();
+            page["items"].as_array().unwrap().len()
+        };
+        assert_eq!(listed().await, 1);
+
+        assert_eq!(app.request("DELETE", &account_path, None, None).await.status(), StatusCode::NO_CONTENT);
+        for path in [account_path.clone(), format!("{}/kraken", account_path), format!("{}/kraken/api-key", account_path)] {
+            assert_eq!(app.request("GET", &path, None, None).await.status(), StatusCode::NOT_FOUND, "{}", path);
+        }
+        assert_eq!(listed().await, 0);
+        let recreate = json!({ "uid": uid, "exchange": "kraken", "api_key": "other-api-key" });
+        let conflict = app.request("POST", "/v1/accounts", None, Some(recreate.clone())).await;
+        assert_eq!(conflict.status(), StatusCode::CONFLICT);
+        assert!(error(&conflict).contains("was deleted"), "{}", error(&conflict));
+
+        let restored = app.request("POST", &format!("{}/restore", account_path), None, None).await;
+        assert_eq!(restored.status(), StatusCode::OK, "{:?}", restored.body());
+        let api_key = app.request("GET", &format!("{}/kraken/api-key", account_path), None, None).await;
+        assert_eq!(api_key.status(), StatusCode::OK);
+        let body: Value = serde_json::from_slice(api_key.body()).unwrap();
+        assert_eq!(body["api_key"], "account-api-key");
+        assert_eq!(listed().await, 1);
+
+        // Deleted two days ago: past the retention window.
+        assert_eq!(app.request("DELETE", &account_path, None, None).await.status(), StatusCode::NO_CONTENT);
+        sqlx::query("UPDATE test.public.accounts SET deleted_at = now() - INTERVAL '2 days' WHERE uid = $1;")
+            .bind(&uid)
+            .execute(&app.pg_pool)
+            .await
+            .unwrap();
+        let expired = app.request("POST", &format!("{}/restore", account_path), None, None).await;
+        assert_eq!(expired.status(), StatusCode::NOT_FOUND);
+
+        assert!(app.account_repo.purge_deleted().await.unwrap() >= 1);
+        let rows: i64 = sqlx::query_scalar("SELECT count(*) FROM test.public.accounts WHERE uid = $1;")
+            .bind(&uid)
+            .fetch_one(&app.pg_pool)
+            .await
+            .unwrap();
+        assert_eq!(rows, 0);
+        // Once purged, the uid is free again.
+        let created = app.request("POST", "/v1/accounts", None, Some(recreate)).await;
+        assert_eq!(created.status(), StatusCode::CREATED, "{:?}", created.body());
+    }
+}
@@ -0,0 +1,640 @@
+use anyhow::anyhow;
+use futures::{SinkExt, StreamExt};
+use ring::constant_time;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc};
+use tokio::sync::broadcast::error::RecvError;
+use warp::ws::{Message, WebSocket, Ws};
+use warp::{Filter, Reply};
+use crate::api;
+use crate::config::StreamConfig;
+use crate::models::ExecutorResponse;
+use crate::secret::Secret;
+
+/// Sessions are closed after this many heartbeats without a message from the client.
+const MISSED_HEARTBEATS: u32 = 3;
+
+/// What a client subscribes to, written as `<kind>:<uid>`, e.g. `accounts:alice` or `orders:alice`.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Topic {
+    /// Account lifecycle events of the outbox.
+    Accounts(String),
+    /// Placed, cancelled and fetched orders, as published by executors.
+    Orders(String),
+    Trades(String),
+    Balances(String),
+}
+
+impl Topic {
+    pub fn uid(&self) -> &str {
+        match self {
+            Topic::Accounts(uid) | Topic::Orders(uid) | Topic::Trades(uid) | Topic::Balances(uid) => uid,
+        }
+    }
+
+    /// The topic an executor's `response` for the account `uid` is streamed on.
+    pub fn of_response(uid: &str, response: &ExecutorResponse) -> Topic {
+        let uid = uid.to_string();
+        match response {
+            ExecutorResponse::GetBalanceResponse { .. } => Topic::Balances(uid),
+            ExecutorResponse::PlaceOrderResponse { .. }
+            | ExecutorResponse::CancelOrderResponse { .. }
+            | ExecutorResponse::GetOrderResponse { .. } => Topic::Orders(uid),
+            ExecutorResponse::GetTradesResponse { .. } => Topic::Trades(uid),
+        }
+    }
+}
+
+impl fmt::Display for Topic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Topic::Accounts(uid) => write!(f, "accounts:{}", uid),
+            Topic::Orders(uid) => write!(f, "orders:{}", uid),
+            Topic::Trades(uid) => write!(f, "trades:{}", uid),
+            Topic::Balances(uid) => write!(f, "balances:{}", uid),
+        }
+    }
+}
+
+impl FromStr for Topic {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut parts = value.splitn(2, ':');
+        let kind = parts.next().unwrap_or_default();
+        let uid = match parts.next() {
+            Some(uid) if !uid.is_empty() => uid.to_string(),
+            _ => return Err(anyhow!("topic \"{}\" names no uid, expected e.g. \"accounts:<uid>\"", value)),
+        };
+        match kind {
+            "accounts" => Ok(Topic::Accounts(uid)),
+            "orders" => Ok(Topic::Orders(uid)),
+            "trades" => Ok(Topic::Trades(uid)),
+            "balances" => Ok(Topic::Balances(uid)),
+            _ => Err(anyhow!("unknown topic \"{}\"", kind)),
+        }
+    }
+}
+
+impl Serialize for Topic {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Topic {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// An event as delivered to subscribers. `seq` grows by one per published event across all
+/// topics, and starts over when the process restarts.
+#[derive(Debug)]
+pub struct StreamEvent {
+    pub seq: u64,
+    pub topic: Topic,
+    pub event: serde_json::Value,
+}
+
+struct Buffer {
+    seq: u64,
+    events: VecDeque<Arc<StreamEvent>>,
+}
+
+/// Events buffered after a sequence number, for resuming or catching up a lagging session.
+struct Replay {
+    events: Vec<Arc<StreamEvent>>,
+    /// First and last sequence number that fell out of the buffer before they could be replayed.
+    missed: Option<(u64, u64)>,
+}
+
+/// What `EventHub::watch` delivers.
+#[derive(Debug)]
+pub enum Delivery {
+    Event(Arc<StreamEvent>),
+    /// Events `from..=to` were dropped before they could be delivered.
+    Gap { from: u64, to: u64 },
+}
+
+/// Sends the events of `replay` on `topics` up to `position`, announcing what was lost first.
+/// False once the receiver is gone.
+async fn deliver(sender: &mpsc::Sender<Delivery>, replay: Replay, topics: &HashSet<Topic>, position: u64) -> bool {
+    if let Some((from, to)) = replay.missed {
+        if sender.send(Delivery::Gap { from, to }).await.is_err() {
+            return false;
+        }
+    }
+    for event in replay.events.into_iter().filter(|event| event.seq <= position) {
+        if topics.contains(&event.topic) && sender.send(Delivery::Event(event)).await.is_err() {
+            return false;
+        }
+    }
+    true
+}
+
+/// Fans events out to websocket sessions and watchers, keeping the latest ones for sessions that resume.
+pub struct EventHub {
+    buffer: Mutex<Buffer>,
+    capacity: usize,
+    sender: broadcast::Sender<Arc<StreamEvent>>,
+}
+
+impl EventHub {
+    pub fn new(capacity: usize) -> EventHub {
+        let capacity = capacity.max(1);
+        let (sender, _) = broadcast::channel(capacity);
+        EventHub {
+            buffer: Mutex::new(Buffer { seq: 0, events: VecDeque::with_capacity(capacity) }),
+            capacity,
+            sender,
+        }
+    }
+
+    /// Returns the sequence number of the published event.
+    pub fn publish(&self, topic: Topic, event: serde_json::Value) -> u64 {
+        // Sent under the lock, so sessions receive events in sequence order.
+        let mut buffer = self.buffer.lock().unwrap();
+        buffer.seq += 1;
+        let event = Arc::new(StreamEvent { seq: buffer.seq, topic, event });
+        if buffer.events.len() == self.capacity {
+            buffer.events.pop_front();
+        }
+        buffer.events.push_back(event.clone());
+        // Fails only when no session is listening.
+        let _ = self.sender.send(event);
+        buffer.seq
+    }
+
+    /// Streams an executor's `response` for the account `uid` as it is serialized, e.g.
+    /// `{"GetOrderResponse": {"res": {"Ok": {...}}, "id": "..."}}`.
+    pub fn publish_response(&self, uid: &str, response: &ExecutorResponse) -> Result<u64, anyhow::Error> {
+        let event = serde_json::to_value(response)?;
+        Ok(self.publish(Topic::of_response(uid, response), event))
+    }
+
+    /// A receiver of the events after the returned sequence number.
+    fn subscribe(&self) -> (broadcast::Receiver<Arc<StreamEvent>>, u64) {
+        let buffer = self.buffer.lock().unwrap();
+        (self.sender.subscribe(), buffer.seq)
+    }
+
+    fn since(&self, seq: u64) -> Replay {
+        let buffer = self.buffer.lock().unwrap();
+        // A `seq` ahead of the stream comes from before a restart; everything buffered is new to it.
+        let seq = if seq > buffer.seq { 0 } else { seq };
+        let oldest = buffer.events.front().map_or(buffer.seq + 1, |event| event.seq);
+        Replay {
+            events: buffer.events.iter().filter(|event| event.seq > seq).cloned().collect(),
+            missed: if seq + 1 < oldest { Some((seq + 1, oldest - 1)) } else { None },
+        }
+    }
+
+    /// Delivers the events of `topics`, first those buffered after `resume_from`, until the
+    /// returned receiver is dropped. The gRPC counterpart of a websocket session.
+    pub fn watch(self: Arc<Self>, topics: HashSet<Topic>, resume_from: Option<u64>) -> mpsc::Receiver<Delivery> {
+        let (sender, receiver) = mpsc::channel(self.capacity);
+        tokio::spawn(async move {
+            let (mut events, mut seq) = self.subscribe();
+            if let Some(resume_from) = resume_from {
+                if !deliver(&sender, self.since(resume_from), &topics, seq).await {
+                    return;
+                }
+            }
+            loop {
+                tokio::select! {
+                    _ = sender.closed() => return,
+                    event = events.recv() => match event {
+                        Ok(event) => {
+                            if event.seq <= seq {
+                                continue;
+                            }
+                            seq = event.seq;
+                            if topics.contains(&event.topic) && sender.send(Delivery::Event(event)).await.is_err() {
+                                return;
+                            }
+                        }
+                        Err(RecvError::Lagged(_)) => {
+                            let replay = self.since(seq);
+                            let position = replay.events.last().map_or(seq, |event| event.seq);
+                            if !deliver(&sender, replay, &topics, position).await {
+                                return;
+                            }
+                            seq = position;
+                        }
+                        Err(RecvError::Closed) => return,
+                    },
+                }
+            }
+        });
+        receiver
+    }
+}
+
+/// A token of `STREAM_TOKENS` with the accounts its clients may follow, written as
+/// `<token>=<uid>|<uid>`, or `<token>=*` for every account.
+#[derive(Clone, Debug)]
+pub struct StreamToken {
+    pub token: Secret,
+    /// `None` for tokens that may follow every account.
+    pub uids: Option<HashSet<String>>,
+}
+
+impl StreamToken {
+    fn allows(&self, topic: &Topic) -> bool {
+        self.uids.as_ref().is_none_or(|uids| uids.contains(topic.uid()))
+    }
+}
+
+impl FromStr for StreamToken {
+    type Err = anyhow::Error;
+
+    /// Errors never repeat the value, which holds the token.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || anyhow!("stream tokens must be written as \"<token>=<uid>|<uid>\" or \"<token>=*\"");
+        let mut parts = value.trim().splitn(2, '=');
+        let token = parts.next().filter(|token| !token.is_empty()).ok_or_else(invalid)?;
+        let uids = match parts.next().ok_or_else(invalid)? {
+            "*" => None,
+            uids => {
+                let uids: HashSet<String> = uids.split('|').filter(|uid| !uid.is_empty()).map(str::to_string).collect();
+                if uids.is_empty() {
+                    return Err(invalid());
+                }
+                Some(uids)
+            }
+        };
+        Ok(StreamToken { token: Secret::from(token.to_string()), uids })
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum ClientMessage {
+    /// Starts delivering `topics`; with `resume_from`, first replays their buffered events after it.
+    Subscribe { topics: Vec<Topic>, resume_from: Option<u64> },
+    Unsubscribe { topics: Vec<Topic> },
+    Ping,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ServerMessage<'a> {
+    Subscribed { topics: &'a [Topic], seq: u64 },
+    Unsubscribed { topics: &'a [Topic] },
+    Event { seq: u64, topic: &'a Topic, event: &'a serde_json::Value },
+    /// Events `from..=to` were dropped before this session could be sent them.
+    Gap { from: u64, to: u64 },
+    Heartbeat { seq: u64 },
+    Pong { seq: u64 },
+    Error { message: String },
+}
+
+fn event_message(event: &StreamEvent) -> ServerMessage<'_> {
+    ServerMessage::Event { seq: event.seq, topic: &event.topic, event: &event.event }
+}
+
+struct Session {
+    socket: WebSocket,
+    hub: Arc<EventHub>,
+    /// The token the client authenticated with, limiting the topics it may subscribe to.
+    token: Arc<StreamToken>,
+    topics: HashSet<Topic>,
+    /// Events up to this one have been sent or skipped.
+    seq: u64,
+}
+
+impl Session {
+    async fn send(&mut self, message: &ServerMessage<'_>) -> Result<(), anyhow::Error> {
+        let text = serde_json::to_string(message)?;
+        self.socket.send(Message::text(text)).await?;
+        Ok(())
+    }
+
+    /// Sends the replayed events of `topics` up to the session's position, announcing what was lost.
+    async fn replay(&mut self, replay: Replay, topics: &HashSet<Topic>) -> Result<(), anyhow::Error> {
+        if let Some((from, to)) = replay.missed {
+            self.send(&ServerMessage::Gap { from, to }).await?;
+        }
+        let position = self.seq;
+        for event in replay.events.iter().filter(|event| event.seq <= position) {
+            if topics.contains(&event.topic) {
+                self.send(&event_message(event)).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn on_event(&mut self, event: Arc<StreamEvent>) -> Result<(), anyhow::Error> {
+        if event.seq <= self.seq {
+            return Ok(());
+        }
+        self.seq = event.seq;
+        if self.topics.contains(&event.topic) {
+            self.send(&event_message(&event)).await?;
+        }
+        Ok(())
+    }
+
+    /// Catches up from the buffer after the broadcast channel dropped events for this session.
+    async fn on_lagged(&mut self) -> Result<(), anyhow::Error> {
+        let replay = self.hub.since(self.seq);
+        if let Some(last) = replay.events.last() {
+            self.seq = last.seq;
+        }
+        let topics = self.topics.clone();
+        self.replay(replay, &topics).await
+    }
+
+    async fn on_message(&mut self, text: &str) -> Result<(), anyhow::Error> {
+        let message = match serde_json::from_str(text) {
+            Ok(message) => message,
+            Err(err) => return self.send(&ServerMessage::Error { message: err.to_string() }).await,
+        };
+        match message {
+            ClientMessage::Subscribe { topics, resume_from } => {
+                let (topics, denied): (Vec<Topic>, Vec<Topic>) = topics
+                    .into_iter()
+                    .partition(|topic| self.token.allows(topic));
+                if !denied.is_empty() {
+                    let denied: Vec<String> = denied.iter().map(Topic::to_string).collect();
+                    let message = format!("not allowed to subscribe to {}", denied.join(", "));
+                    self.send(&ServerMessage::Error { message }).await?;
+                    if topics.is_empty() {
+                        return Ok(());
+                    }
+                }
+                let added: HashSet<Topic> = topics.iter().filter(|topic| !self.topics.contains(topic)).cloned().collect();
+                self.topics.extend(added.iter().cloned());
+                self.send(&ServerMessage::Subscribed { topics: &topics, seq: self.seq }).await?;
+                if let Some(resume_from) = resume_from {
+                    let replay = self.hub.since(resume_from);
+                    self.replay(replay, &added).await?;
+                }
+                Ok(())
+            }
+            ClientMessage::Unsubscribe { topics } => {
+                for topic in &topics {
+                    self.topics.remove(topic);
+                }
+                self.send(&ServerMessage::Unsubscribed { topics: &topics }).await
+            }
+            ClientMessage::Ping => {
+                let seq = self.seq;
+                self.send(&ServerMessage::Pong { seq }).await
+            }
+        }
+    }
+
+    async fn run(mut self, mut events: broadcast::Receiver<Arc<StreamEvent>>, heartbeat: Duration) -> Result<(), anyhow::Error> {
+        let mut ticks = tokio::time::interval(heartbeat);
+        let mut silent_ticks = 0;
+        loop {
+            tokio::select! {
+                message = self.socket.next() => {
+                    let message = match message {
+                        Some(message) => message?,
+                        None => return Ok(()),
+                    };
+                    silent_ticks = 0;
+                    if message.is_close() {
+                        return Ok(());
+                    }
+                    if let Ok(text) = message.to_str() {
+                        self.on_message(text).await?;
+                    }
+                }
+                event = events.recv() => {
+                    match event {
+                        Ok(event) => self.on_event(event).await?,
+                        Err(RecvError::Lagged(_)) => self.on_lagged().await?,
+                        Err(RecvError::Closed) => return Ok(()),
+                    }
+                }
+                _ = ticks.tick() => {
+                    if silent_ticks >= MISSED_HEARTBEATS {
+                        self.socket.send(Message::close()).await?;
+                        return Ok(());
+                    }
+                    silent_ticks += 1;
+                    let seq = self.seq;
+                    self.send(&ServerMessage::Heartbeat { seq }).await?;
+                }
+            }
+        }
+    }
+}
+
+async fn serve(socket: WebSocket, hub: Arc<EventHub>, token: Arc<StreamToken>, heartbeat: Duration) {
+    let (events, seq) = hub.subscribe();
+    let session = Session { socket, hub, token, topics: HashSet::new(), seq };
+    if let Err(err) = session.run(events, heartbeat).await {
+        log::warn!("event stream session ended: {}", err);
+    }
+}
+
+#[derive(Debug)]
+pub struct Unauthorized;
+
+impl warp::reject::Reject for Unauthorized {}
+
+/// Accepts a configured token as `Authorization: Bearer <token>`, or as `?token=<token>` for
+/// browser clients, which can't set headers on websocket requests, extracting what it may see.
+fn authorized(
+    tokens: Arc<Vec<StreamToken>>,
+) -> impl Filter<Extract=(Arc<StreamToken>, ), Error=warp::Rejection> + Clone {
+    warp::header::optional::<String>("authorization")
+        .and(warp::query::<HashMap<String, String>>())
+        .and_then(move |authorization: Option<String>, query: HashMap<String, String>| {
+            let tokens = tokens.clone();
+            async move {
+                let token = match (&authorization, query.get("token")) {
+                    (Some(authorization), _) => authorization.strip_prefix("Bearer ").unwrap_or(authorization),
+                    (None, Some(token)) => token.as_str(),
+                    (None, None) => return Err(warp::reject::custom(Unauthorized)),
+                };
+                let known = tokens.iter().find(|known| {
+                    constant_time::verify_slices_are_equal(known.token.expose().as_bytes(), token.as_bytes()).is_ok()
+                });
+                match known {
+                    Some(known) => Ok(Arc::new(known.clone())),
+                    None => Err(warp::reject::custom(Unauthorized)),
+                }
+            }
+        })
+}
+
+/// `GET /ws`, upgraded to a websocket streaming the events of the topics a client subscribes to.
+pub fn ws_route(
+    config: &StreamConfig,
+    hub: Arc<EventHub>,
+) -> impl Filter<Extract=(warp::reply::Response, ), Error=warp::Rejection> + Clone {
+    let heartbeat = Duration::from_secs(config.heartbeat_secs);
+    api::route(&api::STREAM)
+        .map(|_| ())
+        .untuple_one()
+        .and(authorized(Arc::new(config.tokens.clone())))
+        .and(warp::ws())
+        .map(move |token: Arc<StreamToken>, ws: Ws| {
+            let hub = hub.clone();
+            ws.on_upgrade(move |socket| serve(socket, hub, token, heartbeat)).into_response()
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+    use uuid::Uuid;
+    use warp::test::WsClient;
+    use crate::models::{Balance, UserOrderId};
+
+    fn accounts(uid: &str) -> Topic {
+        Topic::Accounts(uid.to_string())
+    }
+
+    /// A session of a client allowed to follow alice, sent a heartbeat every `heartbeat_secs`.
+    async fn connect(hub: Arc<EventHub>, heartbeat_secs: u64) -> WsClient {
+        let config = StreamConfig { tokens: vec!["s3cret=alice".parse().unwrap()], heartbeat_secs, buffer_size: 0 };
+        warp::test::ws()
+            .path("/ws")
+            .header("authorization", "Bearer s3cret")
+            .handshake(ws_route(&config, hub))
+            .await
+            .unwrap()
+    }
+
+    async fn receive(client: &mut WsClient) -> Value {
+        let message = client.recv().await.unwrap();
+        serde_json::from_str(message.to_str().unwrap()).unwrap()
+    }
+
+    /// The next message other than a heartbeat.
+    async fn receive_message(client: &mut WsClient) -> Value {
+        loop {
+            let message = receive(client).await;
+            if message["type"] != "heartbeat" {
+                return message;
+            }
+        }
+    }
+
+    async fn subscribe(client: &mut WsClient, topics: &[&str], resume_from: Option<u64>) -> Value {
+        let request = json!({ "op": "subscribe", "topics": topics, "resume_from": resume_from });
+        client.send_text(request.to_string()).await;
+        receive_message(client).await
+    }
+
+    fn event_seq(message: &Value) -> u64 {
+        assert_eq!(message["type"], "event", "{}", message);
+        message["seq"].as_u64().unwrap()
+    }
+
+    #[test]
+    fn parses_topics() {
+        assert_eq!("accounts:alice".parse::<Topic>().unwrap(), accounts("alice"));
+        assert_eq!("orders:alice".parse::<Topic>().unwrap(), Topic::Orders("alice".to_string()));
+        assert_eq!("trades:alice".parse::<Topic>().unwrap(), Topic::Trades("alice".to_string()));
+        assert_eq!("balances:alice".parse::<Topic>().unwrap(), Topic::Balances("alice".to_string()));
+        assert!("accounts:".parse::<Topic>().is_err());
+        assert!("fills:alice".parse::<Topic>().is_err());
+    }
+
+    #[test]
+    fn limits_tokens_to_their_accounts() {
+        let token: StreamToken = "s3cret=alice|bob".parse().unwrap();
+        assert_eq!(token.token.expose(), "s3cret");
+        assert!(token.allows(&accounts("alice")));
+        assert!(token.allows(&accounts("bob")));
+        assert!(!token.allows(&accounts("carol")));
+
+        let token: StreamToken = "s3cret=*".parse().unwrap();
+        assert!(token.allows(&accounts("carol")));
+    }
+
+    #[test]
+    fn rejects_tokens_without_accounts() {
+        for value in &["s3cret", "s3cret=", "s3cret=|", "=alice"] {
+            let err = value.parse::<StreamToken>().unwrap_err();
+            assert!(!err.to_string().contains("s3cret"));
+        }
+    }
+
+    #[tokio::test]
+    async fn resumes_from_a_sequence() {
+        let hub = Arc::new(EventHub::new(10));
+        for n in 1..=3 {
+            hub.publish(accounts("alice"), json!({ "n": n }));
+        }
+        hub.publish(accounts("bob"), json!({}));
+        let mut client = connect(hub.clone(), 60).await;
+
+        let subscribed = subscribe(&mut client, &["accounts:alice"], Some(1)).await;
+        assert_eq!(subscribed, json!({ "type": "subscribed", "topics": ["accounts:alice"], "seq": 4 }));
+        let replayed = receive_message(&mut client).await;
+        assert_eq!(replayed, json!({ "type": "event", "seq": 2, "topic": "accounts:alice", "event": { "n": 2 } }));
+        assert_eq!(event_seq(&receive_message(&mut client).await), 3);
+
+        hub.publish(accounts("alice"), json!({ "n": 5 }));
+        assert_eq!(event_seq(&receive_message(&mut client).await), 5);
+    }
+
+    #[tokio::test]
+    async fn announces_events_dropped_from_the_buffer() {
+        let hub = Arc::new(EventHub::new(2));
+        for n in 1..=5 {
+            hub.publish(accounts("alice"), json!({ "n": n }));
+        }
+        let mut client = connect(hub, 60).await;
+
+        subscribe(&mut client, &["accounts:alice"], Some(1)).await;
+        assert_eq!(receive_message(&mut client).await, json!({ "type": "gap", "from": 2, "to": 3 }));
+        assert_eq!(event_seq(&receive_message(&mut client).await), 4);
+        assert_eq!(event_seq(&receive_message(&mut client).await), 5);
+    }
+
+    #[tokio::test]
+    async fn refuses_topics_of_other_accounts() {
+        let hub = Arc::new(EventHub::new(10));
+        let mut client = connect(hub, 60).await;
+
+        let refused = subscribe(&mut client, &["orders:bob"], None).await;
+        assert_eq!(refused, json!({ "type": "error", "message": "not allowed to subscribe to orders:bob" }));
+    }
+
+    #[tokio::test]
+    async fn streams_executor_responses_on_their_topics() {
+        let hub = Arc::new(EventHub::new(10));
+        let mut client = connect(hub.clone(), 60).await;
+        subscribe(&mut client, &["orders:alice", "balances:alice"], None).await;
+
+        let cancelled = ExecutorResponse::CancelOrderResponse { res: Ok(()), id: UserOrderId(Uuid::nil()) };
+        assert_eq!(hub.publish_response("alice", &cancelled).unwrap(), 1);
+        let balance = ExecutorResponse::GetBalanceResponse { res: Ok(Balance(Vec::new())) };
+        hub.publish_response("alice", &balance).unwrap();
+
+        let message = receive_message(&mut client).await;
+        assert_eq!(message["topic"], "orders:alice");
+        assert_eq!(message["event"], serde_json::to_value(&cancelled).unwrap());
+        let message = receive_message(&mut client).await;
+        assert_eq!(message["topic"], "balances:alice");
+        assert_eq!(message["event"], json!({ "GetBalanceResponse": { "res": { "Ok": [] } } }));
+    }
+
+    #[tokio::test]
+    async fn sends_heartbeats_and_closes_silent_sessions() {
+        let hub = Arc::new(EventHub::new(10));
+        hub.publish(accounts("bob"), json!({}));
+        let mut client = connect(hub, 1).await;
+
+        for _ in 0..MISSED_HEARTBEATS {
+            assert_eq!(receive(&mut client).await, json!({ "type": "heartbeat", "seq": 1 }));
+        }
+        client.recv_closed().await.unwrap();
+    }
+}
@@ -0,0 +1,349 @@
+use age::{Decryptor, Encryptor};
+use anyhow::{anyhow, bail};
+use chrono::{DateTime, Utc};
+use secrecy::SecretString;
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::{Read, Write};
+use crate::account::AccountRepo;
+use crate::db::{AccountSecrets, AccountSummary};
+use crate::dto::CreateAccountDto;
+use crate::models::{AccountId, AccountPatch, ExchangeName, Patch};
+use crate::secret::Secret;
+use crate::validation::{Validate, Validator};
+use zeroize::{Zeroize, Zeroizing};
+
+/// Format version of the archive JSON; archives from newer versions are refused.
+pub const ARCHIVE_VERSION: u32 = 1;
+
+/// Accounts with their decrypted secrets, as written into an encrypted archive.
+#[derive(Serialize, Deserialize)]
+pub struct Archive {
+    pub version: u32,
+    pub exported_at: DateTime<Utc>,
+    pub accounts: Vec<ArchivedAccount>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ArchivedAccount {
+    pub uid: String,
+    /// Kept as written, so an unknown exchange fails that account rather than the whole archive.
+    pub exchange: String,
+    #[serde(flatten)]
+    pub secrets: AccountSecrets,
+}
+
+impl Zeroize for ArchivedAccount {
+    fn zeroize(&mut self) {
+        self.uid.zeroize();
+        self.exchange.zeroize();
+        // Secrets wipe themselves as they are dropped.
+        self.secrets = AccountSecrets { api_key: None, sign_key: None, credentials: None };
+    }
+}
+
+/// Wipes the accounts as soon as the archive is no longer needed, sealed or imported.
+impl Drop for Archive {
+    fn drop(&mut self) {
+        self.accounts.iter_mut().for_each(Zeroize::zeroize);
+    }
+}
+
+#[derive(Deserialize)]
+struct ArchiveHeader {
+    version: u32,
+}
+
+/// What an archive is encrypted to: a passphrase, or age X25519 recipients.
+pub enum ArchiveKey {
+    Passphrase(Secret),
+    Recipients(Vec<age::x25519::Recipient>),
+}
+
+/// What an archive is decrypted with: its passphrase, or identities of its recipients.
+pub enum ArchiveIdentity {
+    Passphrase(Secret),
+    Identities(Vec<age::x25519::Identity>),
+}
+
+/// How accounts already present in the database are treated.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportStrategy {
+    /// Only accounts missing from the database are created; existing ones are reported as conflicts.
+    Merge,
+    /// Existing accounts get the archived secrets.
+    Overwrite,
+}
+
+impl std::str::FromStr for ImportStrategy {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "merge" => Ok(ImportStrategy::Merge),
+            "overwrite" => Ok(ImportStrategy::Overwrite),
+            _ => Err(anyhow!("unknown import strategy \"{}\", expected \"merge\" or \"overwrite\"", value)),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportAction {
+    Created,
+    Overwritten,
+    Conflict,
+    Invalid,
+    Failed,
+}
+
+impl fmt::Display for ImportAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ImportAction::Created => write!(f, "created"),
+            ImportAction::Overwritten => write!(f, "overwritten"),
+            ImportAction::Conflict => write!(f, "conflict"),
+            ImportAction::Invalid => write!(f, "invalid"),
+            ImportAction::Failed => write!(f, "failed"),
+        }
+    }
+}
+
+/// Outcome of importing one archived account; on a dry run, the outcome it would have.
+#[derive(Debug, Serialize)]
+pub struct ImportResult {
+    pub uid: String,
+    pub exchange: String,
+    pub action: ImportAction,
+    pub detail: Option<String>,
+}
+
+/// Reads the secrets of `accounts` into an archive.
+pub async fn export(repo: &AccountRepo, accounts: &[AccountSummary]) -> Result<Archive, anyhow::Error> {
+    let mut archived = Vec::with_capacity(accounts.len());
+    for account in accounts {
+        let secrets = repo.get_secrets(&AccountId(account.uid.clone()), &account.exchange).await?;
+        archived.push(ArchivedAccount {
+            uid: account.uid.clone(),
+            exchange: account.exchange.to_string(),
+            secrets,
+        });
+    }
+    Ok(Archive { version: ARCHIVE_VERSION, exported_at: Utc::now(), accounts: archived })
+}
+
+/// Encrypts the archive into an age file, wiping it afterwards.
+pub fn seal(archive: Archive, key: ArchiveKey) -> Result<Vec<u8>, anyhow::Error> {
+    let plaintext = Zeroizing::new(serde_json::to_vec(&archive)?);
+    drop(archive);
+    let encryptor = match key {
+        ArchiveKey::Passphrase(passphrase) => {
+            Encryptor::with_user_passphrase(SecretString::new(passphrase.expose().to_string()))
+        }
+        ArchiveKey::Recipients(recipients) => {
+            if recipients.is_empty() {
+                bail!("at least one recipient is needed");
+            }
+            Encryptor::with_recipients(recipients
+                .into_iter()
+                .map(|recipient| Box::new(recipient) as Box<dyn age::Recipient>)
+                .collect())
+        }
+    };
+    let mut sealed = Vec::new();
+    let mut writer = encryptor.wrap_output(&mut sealed)?;
+    writer.write_all(&plaintext)?;
+    writer.finish()?;
+    Ok(sealed)
+}
+
+pub fn open(sealed: &[u8], identity: &ArchiveIdentity) -> Result<Archive, anyhow::Error> {
+    let mut reader = match (Decryptor::new(sealed)?, identity) {
+        (Decryptor::Passphrase(decryptor), ArchiveIdentity::Passphrase(passphrase)) => {
+            decryptor.decrypt(&SecretString::new(passphrase.expose().to_string()), None)?
+        }
+        (Decryptor::Recipients(decryptor), ArchiveIdentity::Identities(identities)) => {
+            decryptor.decrypt(identities.iter().map(|identity| identity as &dyn age::Identity))?
+        }
+        (Decryptor::Passphrase(_), _) => bail!("archive is encrypted with a passphrase"),
+        (Decryptor::Recipients(_), _) => bail!("archive is encrypted to recipients, an identity is needed"),
+    };
+    let mut plaintext = Zeroizing::new(Vec::new());
+    reader.read_to_end(&mut plaintext)?;
+
+    let header: ArchiveHeader = serde_json::from_slice(&plaintext)?;
+    if header.version > ARCHIVE_VERSION {
+        bail!("archive version {} is newer than the supported version {}", header.version, ARCHIVE_VERSION);
+    }
+    Ok(serde_json::from_slice(&plaintext)?)
+}
+
+fn validate(account: &ArchivedAccount) -> Result<(ExchangeName, CreateAccountDto), String> {
+    let exchange = ExchangeName::try_from(account.exchange.clone())
+        .map_err(|_| format!("unknown exchange \"{}\"", account.exchange))?;
+    let dto = CreateAccountDto {
+        uid: account.uid.clone(),
+        exchange: exchange.clone(),
+        api_key: account.secrets.api_key.clone().ok_or_else(|| "api_key is missing".to_string())?,
+        sign_key: account.secrets.sign_key.clone(),
+        credentials: account.secrets.credentials.clone(),
+    };
+    let mut validator = Validator::default();
+    dto.validate(&mut validator);
+    let errors = validator.into_errors();
+    if !errors.is_empty() {
+        let errors: Vec<String> = errors.iter().map(|error| format!("{}: {}", error.field, error.message)).collect();
+        return Err(errors.join("; "));
+    }
+    Ok((exchange, dto))
+}
+
+async fn import_account(
+    repo: &AccountRepo,
+    account: &ArchivedAccount,
+    strategy: ImportStrategy,
+    dry_run: bool,
+) -> Result<(ImportAction, Option<String>), anyhow::Error> {
+    let (exchange, dto) = match validate(account) {
+        Ok(valid) => valid,
+        Err(errors) => return Ok((ImportAction::Invalid, Some(errors))),
+    };
+    let uid = AccountId(dto.uid.clone());
+    let version = repo.account_version(&uid, &exchange).await?;
+    if version.is_none() && repo.is_deleted(&uid).await? {
+        let detail = "account was deleted; restore it or wait until it is purged".to_string();
+        return Ok((ImportAction::Conflict, Some(detail)));
+    }
+    match (version, strategy) {
+        (None, _) => {
+            if !dry_run {
+                repo.create_account(&uid, &exchange, &dto.api_key, dto.sign_key.as_ref(), dto.credentials.as_ref())
+                    .await?;
+            }
+            Ok((ImportAction::Created, None))
+        }
+        (Some(version), ImportStrategy::Merge) => {
+            Ok((ImportAction::Conflict, Some(format!("account exists at version {}", version))))
+        }
+        (Some(version), ImportStrategy::Overwrite) => {
+            if !dry_run {
+                let patch = AccountPatch {
+                    api_key: Patch::Set(dto.api_key),
+                    sign_key: dto.sign_key.map_or(Patch::Clear, Patch::Set),
+                    credentials: dto.credentials.map_or(Patch::Clear, Patch::Set),
+                };
+                repo.update_account(&uid, &exchange, &patch, Some(version)).await?;
+            }
+            Ok((ImportAction::Overwritten, Some(format!("replaced version {}", version))))
+        }
+    }
+}
+
+/// Imports each archived account on its own, so one bad account doesn't stop the rest.
+/// With `dry_run`, nothing is written and the results tell what would happen.
+pub async fn import(
+    repo: &AccountRepo,
+    archive: &Archive,
+    strategy: ImportStrategy,
+    dry_run: bool,
+) -> Vec<ImportResult> {
+    let mut results = Vec::with_capacity(archive.accounts.len());
+    for account in &archive.accounts {
+        let (action, detail) = match import_account(repo, account, strategy, dry_run).await {
+            Ok(outcome) => outcome,
+            Err(err) => (ImportAction::Failed, Some(err.to_string())),
+        };
+        results.push(ImportResult {
+            uid: account.uid.clone(),
+            exchange: account.exchange.clone(),
+            action,
+            detail,
+        });
+    }
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::ExchangeCredentials;
+
+    fn archive(version: u32) -> Archive {
+        Archive {
+            version,
+            exported_at: Utc::now(),
+            accounts: vec![ArchivedAccount {
+                uid: "alice".to_string(),
+                exchange: "Okex".to_string(),
+                secrets: AccountSecrets {
+                    api_key: Some(Secret::from("api-key".to_string())),
+                    sign_key: Some(Secret::from("sign-key".to_string())),
+                    credentials: Some(ExchangeCredentials::Okex { passphrase: Secret::from("phrase".to_string()) }),
+                },
+            }],
+        }
+    }
+
+    fn passphrase() -> Secret {
+        Secret::from("correct horse battery staple".to_string())
+    }
+
+    fn assert_restored(archive: &Archive) {
+        assert_eq!(archive.version, ARCHIVE_VERSION);
+        let account = &archive.accounts[0];
+        assert_eq!(account.uid, "alice");
+        assert_eq!(account.exchange, "Okex");
+        assert_eq!(account.secrets.api_key.as_ref().map(Secret::expose), Some("api-key"));
+        assert_eq!(account.secrets.sign_key.as_ref().map(Secret::expose), Some("sign-key"));
+        assert_eq!(account.secrets.credentials.as_ref().and_then(ExchangeCredentials::passphrase), Some("phrase"));
+    }
+
+    #[test]
+    fn round_trips_with_passphrase() {
+        let sealed = seal(archive(ARCHIVE_VERSION), ArchiveKey::Passphrase(passphrase())).unwrap();
+        assert!(!sealed.windows(b"api-key".len()).any(|window| window == b"api-key"));
+        assert_restored(&open(&sealed, &ArchiveIdentity::Passphrase(passphrase())).unwrap());
+
+        let wrong = ArchiveIdentity::Passphrase(Secret::from("wrong".to_string()));
+        assert!(open(&sealed, &wrong).is_err());
+        assert!(open(&sealed, &ArchiveIdentity::Identities(vec![age::x25519::Identity::generate()])).is_err());
+    }
+
+    #[test]
+    fn round_trips_with_recipients() {
+        let identity = age::x25519::Identity::generate();
+        let other = age::x25519::Identity::generate();
+        let recipients = vec![identity.to_public(), other.to_public()];
+        let sealed = seal(archive(ARCHIVE_VERSION), ArchiveKey::Recipients(recipients)).unwrap();
+        assert_restored(&open(&sealed, &ArchiveIdentity::Identities(vec![identity])).unwrap());
+        assert_restored(&open(&sealed, &ArchiveIdentity::Identities(vec![other])).unwrap());
+
+        let stranger = ArchiveIdentity::Identities(vec![age::x25519::Identity::generate()]);
+        assert!(open(&sealed, &stranger).is_err());
+        assert!(open(&sealed, &ArchiveIdentity::Passphrase(passphrase())).is_err());
+    }
+
+    #[test]
+    fn refuses_sealing_to_nobody() {
+        assert!(seal(archive(ARCHIVE_VERSION), ArchiveKey::Recipients(Vec::new())).is_err());
+    }
+
+    #[test]
+    fn rejects_newer_version() {
+        let identity = age::x25519::Identity::generate();
+        let sealed = seal(archive(ARCHIVE_VERSION + 1), ArchiveKey::Recipients(vec![identity.to_public()])).unwrap();
+        let err = open(&sealed, &ArchiveIdentity::Identities(vec![identity])).err().unwrap();
+        assert!(err.to_string().contains("newer than the supported version"));
+    }
+
+    #[test]
+    fn wipes_accounts_on_zeroize() {
+        let mut account = archive(ARCHIVE_VERSION).accounts.remove(0);
+        account.zeroize();
+        assert!(account.uid.is_empty());
+        assert!(account.secrets.api_key.is_none() && account.secrets.sign_key.is_none());
+        assert!(account.secrets.credentials.is_none());
+    }
+}
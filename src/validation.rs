@@ -0,0 +1,407 @@
+use crate::dto::{
+    CreateAccountDto, SignAndGetDto, UpdateAccountDto, GetApiKeyDto, AccountChangesDto, SignRequestDto,
+    KeyPairRequestDto, FieldErrorDto,
+};
+use crate::models::{ExchangeName, ExchangeCredentials, KeyType};
+use crate::keys;
+use crate::secret::Secret;
+
+const UID_MAX_LEN: usize = 255;
+const KEY_MAX_LEN: usize = 512;
+
+#[derive(Copy, Clone, Debug)]
+pub enum Charset {
+    Alphanumeric,
+    /// Letters, digits and `-_.:@`.
+    Uid,
+    /// Visible ASCII characters, no whitespace.
+    PrintableAscii,
+}
+
+impl Charset {
+    fn allows(self, c: char) -> bool {
+        match self {
+            Charset::Alphanumeric => c.is_ascii_alphanumeric(),
+            Charset::Uid => c.is_ascii_alphanumeric() || "-_.:@".contains(c),
+            Charset::PrintableAscii => c.is_ascii_graphic(),
+        }
+    }
+
+    fn describe(self) -> &'static str {
+        match self {
+            Charset::Alphanumeric => "ASCII letters and digits",
+            Charset::Uid => "ASCII letters, digits and \"-_.:@\"",
+            Charset::PrintableAscii => "printable ASCII characters without whitespace",
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum Rule {
+    NonEmpty,
+    MaxLen(usize),
+    ExactLen(usize),
+    Charset(Charset),
+}
+
+impl Rule {
+    fn check(self, value: &str) -> Option<String> {
+        let len = value.chars().count();
+        match self {
+            Rule::NonEmpty if value.trim().is_empty() => Some("must not be empty".to_string()),
+            Rule::MaxLen(max) if len > max => Some(format!("must be at most {} characters long", max)),
+            Rule::ExactLen(expected) if len != expected => Some(format!("must be exactly {} characters long", expected)),
+            Rule::Charset(charset) if !value.chars().all(|c| charset.allows(c)) => {
+                Some(format!("must contain only {}", charset.describe()))
+            }
+            _ => None,
+        }
+    }
+}
+
+const UID_RULES: &[Rule] = &[Rule::NonEmpty, Rule::MaxLen(UID_MAX_LEN), Rule::Charset(Charset::Uid)];
+const KEY_RULES: &[Rule] = &[Rule::NonEmpty, Rule::MaxLen(KEY_MAX_LEN), Rule::Charset(Charset::PrintableAscii)];
+const METHOD_RULES: &[Rule] = &[Rule::NonEmpty, Rule::MaxLen(7), Rule::Charset(Charset::Alphanumeric)];
+const PATH_RULES: &[Rule] = &[Rule::NonEmpty, Rule::MaxLen(2048), Rule::Charset(Charset::PrintableAscii)];
+const PASSPHRASE_RULES: &[Rule] = &[Rule::NonEmpty, Rule::MaxLen(128), Rule::Charset(Charset::PrintableAscii)];
+const ACCOUNT_REF_RULES: &[Rule] = &[Rule::NonEmpty, Rule::MaxLen(64), Rule::Charset(Charset::Uid)];
+const BINANCE_KEY_RULES: &[Rule] = &[Rule::NonEmpty, Rule::ExactLen(64), Rule::Charset(Charset::Alphanumeric)];
+
+/// Format of api and sign keys issued by `exchange`. Only Binance documents a fixed one; the keys of
+/// the other exchanges vary in length and alphabet, so any non-empty printable key is accepted.
+pub fn key_rules(exchange: &ExchangeName) -> &'static [Rule] {
+    match exchange {
+        ExchangeName::Binance => BINANCE_KEY_RULES,
+        _ => KEY_RULES,
+    }
+}
+
+/// Exchanges accepting Ed25519 or RSA keys in place of a shared secret.
+pub fn supports_key_pairs(exchange: &ExchangeName) -> bool {
+    matches!(exchange, ExchangeName::Binance)
+}
+
+/// Collects field errors; each field reports the first rule it breaks.
+#[derive(Default)]
+pub struct Validator {
+    errors: Vec<FieldErrorDto>,
+}
+
+impl Validator {
+    pub fn field(&mut self, field: &str, value: &str, rules: &[Rule]) -> &mut Self {
+        if let Some(message) = rules.iter().find_map(|rule| rule.check(value)) {
+            self.errors.push(FieldErrorDto { field: field.to_string(), message });
+        }
+        self
+    }
+
+    pub fn optional_field(&mut self, field: &str, value: Option<&str>, rules: &[Rule]) -> &mut Self {
+        match value {
+            Some(value) => self.field(field, value, rules),
+            None => self,
+        }
+    }
+
+    pub fn error(&mut self, field: &str, message: &str) -> &mut Self {
+        self.errors.push(FieldErrorDto { field: field.to_string(), message: message.to_string() });
+        self
+    }
+
+    /// A shared secret following the exchange's key format, or a PEM private key where supported.
+    pub fn sign_key(&mut self, field: &str, exchange: &ExchangeName, value: Option<&str>) -> &mut Self {
+        match value {
+            Some(value) if keys::is_pem(value) => {
+                if !supports_key_pairs(exchange) {
+                    return self.error(field, &format!("private keys are not supported for {}", exchange));
+                }
+                match keys::import_pem(value) {
+                    Ok(_) => self,
+                    Err(err) => self.error(field, &err.to_string()),
+                }
+            }
+            value => self.optional_field(field, value, key_rules(exchange)),
+        }
+    }
+
+    /// `credentials` must have the shape of `exchange`; `required` rejects their absence.
+    pub fn credentials(
+        &mut self,
+        field: &str,
+        exchange: &ExchangeName,
+        credentials: Option<&ExchangeCredentials>,
+        required: bool,
+    ) -> &mut Self {
+        let credentials = match credentials {
+            Some(credentials) => credentials,
+            None if required && ExchangeCredentials::required_for(exchange) => {
+                return self.error(field, &format!("are required for {}", exchange));
+            }
+            None => return self,
+        };
+        if credentials.exchange() != *exchange {
+            return self.error(
+                &format!("{}.exchange", field),
+                &format!("must be \"{}\"", exchange),
+            );
+        }
+        match credentials {
+            ExchangeCredentials::Okex { passphrase } => {
+                self.field(&format!("{}.passphrase", field), passphrase.expose(), PASSPHRASE_RULES)
+            }
+            ExchangeCredentials::Kucoin { passphrase, key_version } => {
+                self.field(&format!("{}.passphrase", field), passphrase.expose(), PASSPHRASE_RULES);
+                if !(1..=2).contains(key_version) {
+                    self.error(&format!("{}.key_version", field), "must be 1 or 2");
+                }
+                self
+            }
+            ExchangeCredentials::Binance { sub_account_id } | ExchangeCredentials::Bitfinex { sub_account_id } => {
+                self.optional_field(&format!("{}.sub_account_id", field), sub_account_id.as_deref(), ACCOUNT_REF_RULES)
+            }
+            ExchangeCredentials::Huobi { account_id } => {
+                self.optional_field(&format!("{}.account_id", field), account_id.as_deref(), ACCOUNT_REF_RULES)
+            }
+            _ => self,
+        }
+    }
+
+    pub fn into_errors(self) -> Vec<FieldErrorDto> {
+        self.errors
+    }
+}
+
+pub trait Validate {
+    fn validate(&self, validator: &mut Validator);
+}
+
+/// Bodies of routes that take the exchange from the path.
+pub trait ValidateForExchange {
+    fn validate_for(&self, exchange: &ExchangeName, validator: &mut Validator);
+}
+
+impl Validate for CreateAccountDto {
+    fn validate(&self, validator: &mut Validator) {
+        validator
+            .field("uid", &self.uid, UID_RULES)
+            .field("api_key", self.api_key.expose(), key_rules(&self.exchange))
+            .sign_key("sign_key", &self.exchange, self.sign_key.as_ref().map(Secret::expose))
+            .credentials("credentials", &self.exchange, self.credentials.as_ref(), true);
+    }
+}
+
+impl Validate for UpdateAccountDto {
+    fn validate(&self, validator: &mut Validator) {
+        validator
+            .field("uid", &self.uid, UID_RULES)
+            .optional_field("api_key", self.api_key.as_ref().map(Secret::expose), key_rules(&self.exchange))
+            .sign_key("sign_key", &self.exchange, self.sign_key.as_ref().map(Secret::expose))
+            .credentials("credentials", &self.exchange, self.credentials.as_ref(), false);
+    }
+}
+
+impl Validate for SignAndGetDto {
+    fn validate(&self, validator: &mut Validator) {
+        validator.field("uid", &self.uid, UID_RULES);
+    }
+}
+
+impl Validate for GetApiKeyDto {
+    fn validate(&self, validator: &mut Validator) {
+        validator.field("uid", &self.uid, UID_RULES);
+    }
+}
+
+impl ValidateForExchange for AccountChangesDto {
+    fn validate_for(&self, exchange: &ExchangeName, validator: &mut Validator) {
+        validator
+            .optional_field("api_key", self.api_key.as_ref().and_then(Option::as_ref).map(Secret::expose), key_rules(exchange))
+            .sign_key("sign_key", exchange, self.sign_key.as_ref().and_then(Option::as_ref).map(Secret::expose));
+        match &self.credentials {
+            Some(Some(credentials)) => {
+                validator.credentials("credentials", exchange, Some(credentials), false);
+            }
+            Some(None) if ExchangeCredentials::required_for(exchange) => {
+                validator.error("credentials", &format!("can't be removed for {}", exchange));
+            }
+            _ => {}
+        }
+    }
+}
+
+impl ValidateForExchange for KeyPairRequestDto {
+    fn validate_for(&self, exchange: &ExchangeName, validator: &mut Validator) {
+        if self.key_type == KeyType::Hmac {
+            validator.error("key_type", "must be \"ed25519\" or \"rsa\"");
+        } else if !supports_key_pairs(exchange) {
+            validator.error("key_type", &format!("key pairs are not supported for {}", exchange));
+        }
+    }
+}
+
+impl ValidateForExchange for SignRequestDto {
+    fn validate_for(&self, _exchange: &ExchangeName, validator: &mut Validator) {
+        validator
+            .field("method", &self.method, METHOD_RULES)
+            .field("path", &self.path, PATH_RULES);
+        if !self.path.starts_with('/') {
+            validator.error("path", "must start with \"/\"");
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ValidationRejection(pub Vec<FieldErrorDto>);
+
+impl warp::reject::Reject for ValidationRejection {}
+
+pub async fn validated<T: Validate>(body: T) -> Result<T, warp::Rejection> {
+    let mut validator = Validator::default();
+    body.validate(&mut validator);
+    let errors = validator.into_errors();
+    if errors.is_empty() {
+        Ok(body)
+    } else {
+        Err(warp::reject::custom(ValidationRejection(errors)))
+    }
+}
+
+pub fn uid_errors(uid: &str) -> Vec<FieldErrorDto> {
+    let mut validator = Validator::default();
+    validator.field("uid", uid, UID_RULES);
+    validator.into_errors()
+}
+
+/// Checks the `{uid}` of a route that takes no body, as body routes check theirs.
+pub async fn validated_uid(account_id: String) -> Result<String, warp::Rejection> {
+    let errors = uid_errors(&account_id);
+    if errors.is_empty() {
+        Ok(account_id)
+    } else {
+        Err(warp::reject::custom(ValidationRejection(errors)))
+    }
+}
+
+/// Field errors of a body acting on the account and exchange named apart from it.
+pub fn errors_for_exchange<T: ValidateForExchange>(
+    account_id: &str,
+    exchange: &ExchangeName,
+    body: &T,
+) -> Vec<FieldErrorDto> {
+    let mut validator = Validator::default();
+    validator.field("uid", account_id, UID_RULES);
+    body.validate_for(exchange, &mut validator);
+    validator.into_errors()
+}
+
+pub async fn validated_for_exchange<T: ValidateForExchange>(
+    account_id: String,
+    exchange: ExchangeName,
+    body: T,
+) -> Result<(String, ExchangeName, T), warp::Rejection> {
+    let errors = errors_for_exchange(&account_id, &exchange, &body);
+    if errors.is_empty() {
+        Ok((account_id, exchange, body))
+    } else {
+        Err(warp::reject::custom(ValidationRejection(errors)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use warp::Filter;
+    use crate::rejections;
+
+    fn errors(value: &str, rules: &[Rule]) -> Vec<FieldErrorDto> {
+        let mut validator = Validator::default();
+        validator.field("field", value, rules);
+        validator.into_errors()
+    }
+
+    fn message(value: &str, rules: &[Rule]) -> Option<String> {
+        errors(value, rules).pop().map(|error| error.message)
+    }
+
+    #[test]
+    fn checks_each_rule() {
+        assert_eq!(message("", &[Rule::NonEmpty]).unwrap(), "must not be empty");
+        assert_eq!(message("  ", &[Rule::NonEmpty]).unwrap(), "must not be empty");
+        assert_eq!(message("abc", &[Rule::MaxLen(2)]).unwrap(), "must be at most 2 characters long");
+        assert_eq!(message("ab", &[Rule::MaxLen(2)]), None);
+        assert_eq!(message("abc", &[Rule::ExactLen(2)]).unwrap(), "must be exactly 2 characters long");
+        assert_eq!(message("a", &[Rule::ExactLen(2)]).unwrap(), "must be exactly 2 characters long");
+        assert_eq!(message("ab", &[Rule::ExactLen(2)]), None);
+        // Lengths count characters, not bytes.
+        assert_eq!(message("éé", &[Rule::ExactLen(2)]), None);
+    }
+
+    #[test]
+    fn checks_each_charset() {
+        let alphanumeric = &[Rule::Charset(Charset::Alphanumeric)];
+        assert_eq!(message("aZ09", alphanumeric), None);
+        assert_eq!(message("a-b", alphanumeric).unwrap(), "must contain only ASCII letters and digits");
+
+        let uid = &[Rule::Charset(Charset::Uid)];
+        assert_eq!(message("alice-1_2.3:4@x", uid), None);
+        assert!(message("alice bob", uid).is_some());
+        assert!(message("alice/bob", uid).is_some());
+
+        let printable = &[Rule::Charset(Charset::PrintableAscii)];
+        assert_eq!(message("a!b~c{}", printable), None);
+        assert!(message("a b", printable).is_some());
+        assert!(message("ключ", printable).is_some());
+    }
+
+    #[test]
+    fn reports_the_first_broken_rule_of_a_field() {
+        let errors = errors("", UID_RULES);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].message, "must not be empty");
+    }
+
+    #[test]
+    fn requires_binance_keys_of_64_letters_and_digits() {
+        let rules = key_rules(&ExchangeName::Binance);
+        let key = "vmPUZE6mv9SD5VNHk4HlWFsOr6aKE2zvsw0MuIgwCIPy6utIco14y7Ju91duEh8A";
+        assert_eq!(key.len(), 64);
+        assert_eq!(message(key, rules), None);
+        assert_eq!(message(&key[1..], rules).unwrap(), "must be exactly 64 characters long");
+        assert_eq!(message(&format!("{}-", &key[1..]), rules).unwrap(), "must contain only ASCII letters and digits");
+        assert_eq!(message("", rules).unwrap(), "must not be empty");
+    }
+
+    #[test]
+    fn accepts_any_printable_key_for_other_exchanges() {
+        for exchange in ExchangeName::ALL.iter().filter(|exchange| **exchange != ExchangeName::Binance) {
+            let rules = key_rules(exchange);
+            assert_eq!(message("5a7b2c9d-0e1f-4a3b-8c5d-6e7f8a9b0c1d", rules), None, "{}", exchange);
+            assert_eq!(message("k", rules), None, "{}", exchange);
+            assert!(message("", rules).is_some(), "{}", exchange);
+            assert!(message(&"k".repeat(KEY_MAX_LEN + 1), rules).is_some(), "{}", exchange);
+        }
+    }
+
+    #[tokio::test]
+    async fn collects_every_field_error_into_the_422_body() {
+        let route = warp::body::json::<CreateAccountDto>()
+            .and_then(validated)
+            .map(|_| warp::reply())
+            .recover(rejections::handle_rejection);
+        let response = warp::test::request()
+            .method("POST")
+            .json(&serde_json::json!({ "uid": "not a uid", "exchange": "binance", "api_key": "short", "sign_key": "" }))
+            .reply(&route)
+            .await;
+
+        assert_eq!(response.status(), 422);
+        let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(body["error"], "Request validation failed");
+        let fields: Vec<&str> = body["errors"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|error| error["field"].as_str().unwrap())
+            .collect();
+        assert_eq!(fields, ["uid", "api_key", "sign_key"]);
+    }
+}
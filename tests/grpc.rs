@@ -0,0 +1,205 @@
+//! Calls the gRPC services through a tonic client. The account calls need the database at
+//! `DATABASE_URL` and are skipped when it isn't set.
+
+use std::env;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::transport::{Channel, Server};
+use tonic::Code;
+use try_api::account::AccountRepo;
+use try_api::config::Config;
+use try_api::db::{db_connect, db_migrate};
+use try_api::grpc::proto::accounts_client::AccountsClient;
+use try_api::grpc::proto::accounts_server::AccountsServer;
+use try_api::grpc::proto::orders_client::OrdersClient;
+use try_api::grpc::proto::orders_server::OrdersServer;
+use try_api::grpc::proto::{self, order_event};
+use try_api::grpc::{AccountService, OrderService};
+use try_api::models::ExchangeName;
+use try_api::rate_limit::{RateLimit, RateLimiter};
+use try_api::stream::EventHub;
+
+/// Serves both services with `config` on an ephemeral port, or `None` without a database.
+async fn start(config: Config, hub: Option<Arc<EventHub>>) -> Option<SocketAddr> {
+    let database_url = match env::var("DATABASE_URL") {
+        Ok(database_url) => database_url,
+        Err(_) => {
+            eprintln!("DATABASE_URL is not set, skipping gRPC test");
+            return None;
+        }
+    };
+    let pg_pool = db_connect(&database_url).await.unwrap();
+    db_migrate(&pg_pool).await.unwrap();
+    let account_repo = Arc::new(AccountRepo::new(pg_pool, &config, hub.clone()).await);
+    let limiter = Arc::new(RateLimiter::new(&config));
+
+    let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = Server::builder()
+        .add_service(AccountsServer::new(AccountService::new(account_repo, limiter.clone())))
+        .add_service(OrdersServer::new(OrderService::new(hub, limiter)))
+        .serve_with_incoming(TcpListenerStream::new(listener));
+    tokio::spawn(server);
+    Some(addr)
+}
+
+async fn accounts(addr: SocketAddr) -> AccountsClient<Channel> {
+    AccountsClient::connect(format!("http://{}", addr)).await.unwrap()
+}
+
+async fn orders(addr: SocketAddr) -> OrdersClient<Channel> {
+    OrdersClient::connect(format!("http://{}", addr)).await.unwrap()
+}
+
+fn unique_uid(name: &str) -> String {
+    format!("grpc-{}-{}", name, chrono::Utc::now().timestamp_micros())
+}
+
+fn account(uid: &str) -> proto::AccountKey {
+    proto::AccountKey { uid: uid.to_string(), exchange: ExchangeName::Kraken.to_string() }
+}
+
+fn create_request(uid: &str) -> proto::CreateAccountRequest {
+    proto::CreateAccountRequest {
+        account: Some(account(uid)),
+        api_key: "grpc-api-key".to_string(),
+        sign_key: Some("a3Jha2VuLXNlY3JldA==".to_string()),
+        credentials_json: None,
+    }
+}
+
+#[tokio::test]
+async fn creates_gets_signs_and_removes_accounts() {
+    let addr = match start(Config::from_env(), None).await {
+        Some(addr) => addr,
+        None => return,
+    };
+    let mut client = accounts(addr).await;
+    let uid = unique_uid("lifecycle");
+
+    let created = client.create_account(create_request(&uid)).await.unwrap().into_inner();
+    assert_eq!(created.uid, uid);
+    assert_eq!(created.exchange, ExchangeName::Kraken.to_string());
+
+    let api_key = client.get_api_key(account(&uid)).await.unwrap().into_inner();
+    assert_eq!(api_key.api_key, "grpc-api-key");
+
+    let signed = client.sign_payload(proto::SignPayloadRequest {
+        account: Some(account(&uid)),
+        data_to_sign: b"payload".to_vec(),
+    }).await.unwrap().into_inner();
+    assert_eq!((signed.uid.as_str(), signed.api_key.as_str()), (uid.as_str(), "grpc-api-key"));
+    assert!(signed.nonce > 0);
+
+    client.remove_api_key(proto::RemoveApiKeyRequest { account: Some(account(&uid)), expected_version: None })
+        .await
+        .unwrap();
+    let err = client.get_api_key(account(&uid)).await.unwrap_err();
+    assert_eq!(err.code(), Code::NotFound);
+
+    client.remove_account(proto::RemoveAccountRequest { uid: uid.clone(), expected_version: None }).await.unwrap();
+    let err = client.remove_account(proto::RemoveAccountRequest { uid, expected_version: None }).await.unwrap_err();
+    assert_eq!(err.code(), Code::NotFound);
+}
+
+#[tokio::test]
+async fn maps_errors_to_status_codes() {
+    let addr = match start(Config::from_env(), None).await {
+        Some(addr) => addr,
+        None => return,
+    };
+    let mut client = accounts(addr).await;
+    let uid = unique_uid("codes");
+
+    let err = client.create_account(create_request("not a uid")).await.unwrap_err();
+    assert_eq!(err.code(), Code::InvalidArgument);
+    assert!(err.message().contains("uid"), "{}", err.message());
+
+    let mut unknown_exchange = create_request(&uid);
+    unknown_exchange.account = Some(proto::AccountKey { uid: uid.clone(), exchange: "Mtgox".to_string() });
+    assert_eq!(client.create_account(unknown_exchange).await.unwrap_err().code(), Code::InvalidArgument);
+
+    client.create_account(create_request(&uid)).await.unwrap();
+    assert_eq!(client.create_account(create_request(&uid)).await.unwrap_err().code(), Code::AlreadyExists);
+
+    let stale = client.update_account(proto::UpdateAccountRequest {
+        account: Some(account(&uid)),
+        api_key: Some(proto::FieldChange { change: Some(proto::field_change::Change::Set("rotated".to_string())) }),
+        sign_key: None,
+        credentials_json: None,
+        expected_version: Some(-1),
+    }).await.unwrap_err();
+    assert_eq!(stale.code(), Code::FailedPrecondition);
+
+    let missing = client.get_api_key(account(&unique_uid("missing"))).await.unwrap_err();
+    assert_eq!(missing.code(), Code::NotFound);
+}
+
+#[tokio::test]
+async fn reports_rate_limits_as_resource_exhausted() {
+    let mut config = Config::from_env();
+    config.rate_limit_uid = RateLimit::Bucket { burst: 1, period_secs: 60 };
+    let addr = match start(config, None).await {
+        Some(addr) => addr,
+        None => return,
+    };
+    let mut client = accounts(addr).await;
+    let uid = unique_uid("limited");
+
+    assert_eq!(client.get_api_key(account(&uid)).await.unwrap_err().code(), Code::NotFound);
+    let limited = client.get_api_key(account(&uid)).await.unwrap_err();
+    assert_eq!(limited.code(), Code::ResourceExhausted);
+    assert!(limited.metadata().get("retry-after").is_some());
+}
+
+#[tokio::test]
+async fn publishes_and_watches_order_events() {
+    let hub = Arc::new(EventHub::new(10));
+    let addr = match start(Config::from_env(), Some(hub)).await {
+        Some(addr) => addr,
+        None => return,
+    };
+    let mut client = orders(addr).await;
+    let uid = unique_uid("orders");
+    let cancelled = r#"{"CancelOrderResponse":{"res":{"Ok":null},"id":"6f1e2d3c-4b5a-4978-8a6b-5c4d3e2f1a0b"}}"#;
+
+    let published = client.publish_executor_response(proto::PublishExecutorResponseRequest {
+        uid: uid.clone(),
+        executor_response_json: cancelled.to_string(),
+    }).await.unwrap().into_inner();
+
+    let mut events = client.watch_orders(proto::WatchOrdersRequest {
+        uid: uid.clone(),
+        resume_from: Some(published.seq - 1),
+    }).await.unwrap().into_inner();
+    let event = match events.message().await.unwrap().unwrap().kind {
+        Some(order_event::Kind::Event(event)) => event,
+        kind => panic!("expected an event, got {:?}", kind),
+    };
+    assert_eq!(event.seq, published.seq);
+    assert_eq!(event.topic, format!("orders:{}", uid));
+    let event_json: serde_json::Value = serde_json::from_str(&event.event_json).unwrap();
+    assert_eq!(event_json, serde_json::from_str::<serde_json::Value>(cancelled).unwrap());
+
+    let invalid = client.publish_executor_response(proto::PublishExecutorResponseRequest {
+        uid,
+        executor_response_json: "{}".to_string(),
+    }).await.unwrap_err();
+    assert_eq!(invalid.code(), Code::InvalidArgument);
+}
+
+#[tokio::test]
+async fn leaves_order_calls_unimplemented_without_the_stream() {
+    let addr = match start(Config::from_env(), None).await {
+        Some(addr) => addr,
+        None => return,
+    };
+    let mut client = orders(addr).await;
+
+    let err = client.watch_orders(proto::WatchOrdersRequest { uid: unique_uid("off"), resume_from: None })
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Code::Unimplemented);
+}
@@ -0,0 +1,26 @@
+//! Guards the published OpenAPI document: regenerate the snapshot with `UPDATE_SNAPSHOTS=1 cargo test`
+//! after changing routes or DTOs, and review its diff with the change.
+
+use std::env;
+use std::fs;
+use std::path::Path;
+use try_api::docs;
+
+const SNAPSHOT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/snapshots/openapi.json");
+
+#[test]
+fn openapi_document_matches_snapshot() {
+    let generated = docs::json(&docs::document("http://127.0.0.1:3030")) + "\n";
+    if env::var_os("UPDATE_SNAPSHOTS").is_some() {
+        fs::create_dir_all(Path::new(SNAPSHOT).parent().unwrap()).expect("snapshot directory is writable");
+        fs::write(SNAPSHOT, &generated).expect("snapshot is writable");
+        return;
+    }
+    let committed = fs::read_to_string(SNAPSHOT)
+        .unwrap_or_else(|_| panic!("{} is missing; write it with UPDATE_SNAPSHOTS=1", SNAPSHOT));
+    assert!(
+        committed == generated,
+        "the OpenAPI document differs from {}; if the change is intended, update it with UPDATE_SNAPSHOTS=1",
+        SNAPSHOT,
+    );
+}